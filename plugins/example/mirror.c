@@ -0,0 +1,30 @@
+/* Bars mirrored around the horizontal center line, colored along the theme gradient.
+ *
+ *   cc -shared -fPIC -O2 -I.. mirror.c -o ~/.config/rusty-raven/plugins/mirror.so
+ */
+#include "raven_plugin.h"
+
+static void draw(void *state, const RavenFrame *frame, const RavenDrawContext *ctx) {
+    (void)state;
+    if (frame->band_count == 0)
+        return;
+    float width = (float)ctx->width / frame->band_count;
+    float middle = ctx->height / 2.0f;
+    for (size_t i = 0; i < frame->band_count; i++) {
+        float height = frame->bands[i] * middle;
+        RavenColor color = ctx->gradient[i * 3 / frame->band_count];
+        ctx->rect(i * width, middle - height, width * 0.8f, height * 2.0f, color);
+    }
+}
+
+static const RavenPlugin plugin = {
+    .abi_version = RAVEN_PLUGIN_ABI_VERSION,
+    .name = "Mirror",
+    .create = NULL,
+    .draw = draw,
+    .destroy = NULL,
+};
+
+const RavenPlugin *raven_plugin(void) {
+    return &plugin;
+}
@@ -0,0 +1,66 @@
+/* Visualizer plugin interface of Rusty rAVen.
+ *
+ * A plugin is a shared object in $XDG_CONFIG_HOME/rusty-raven/plugins/ (~/.config/rusty-raven/plugins/)
+ * that exports `raven_plugin`. Each one shows up as an extra mode after the built-in ones in
+ * the v/b cycle. Everything is called from the render thread.
+ */
+#ifndef RAVEN_PLUGIN_H
+#define RAVEN_PLUGIN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define RAVEN_PLUGIN_ABI_VERSION 1
+
+typedef struct {
+    uint8_t r, g, b, a;
+} RavenColor;
+
+/* One frame of analysis. The pointers are only valid during the draw call. */
+typedef struct {
+    /* Smoothed bands, raw bands and peak-hold values, 0..1, lowest frequency first */
+    const float *bands;
+    const float *raw_bands;
+    const float *peaks;
+    size_t band_count;
+    /* Newest raw samples of each channel in -1..1, triggered like the oscilloscope */
+    const float *left;
+    const float *right;
+    size_t sample_count;
+    /* -1 (out of phase) to 1 (mono) */
+    float correlation;
+    /* Seconds since the previous frame */
+    float delta;
+} RavenFrame;
+
+/* Screen size, the current theme and what to draw with */
+typedef struct {
+    int32_t width;
+    int32_t height;
+    RavenColor background;
+    RavenColor foreground;
+    RavenColor accents[6];
+    /* Quiet/low to loud/high */
+    RavenColor gradient[3];
+    void (*rect)(float x, float y, float width, float height, RavenColor color);
+    void (*line)(float x1, float y1, float x2, float y2, float thick, RavenColor color);
+    void (*circle)(float x, float y, float radius, RavenColor color);
+    void (*text)(const char *text, float x, float y, float size, RavenColor color);
+} RavenDrawContext;
+
+typedef struct {
+    /* Must be RAVEN_PLUGIN_ABI_VERSION */
+    uint32_t abi_version;
+    /* The file name is used when NULL */
+    const char *name;
+    /* Optional. The returned state is handed to draw and destroy. */
+    void *(*create)(void);
+    void (*draw)(void *state, const RavenFrame *frame, const RavenDrawContext *ctx);
+    /* Optional */
+    void (*destroy)(void *state);
+} RavenPlugin;
+
+/* The one symbol a plugin exports. The returned struct must stay valid while it is loaded. */
+const RavenPlugin *raven_plugin(void);
+
+#endif
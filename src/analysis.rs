@@ -0,0 +1,489 @@
+use crate::fft::Fft;
+use crate::spectrum::{
+    compute_magnitudes, BandLayout, SpectrumSettings, StereoView, WindowFunction,
+};
+use num_complex::Complex32;
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+// How often the analysis thread polls the ring for new samples
+const POLL_INTERVAL: Duration = Duration::from_millis(4);
+
+// Single-producer ring of the most recent mono samples.
+//
+// The audio callback is the only writer and never blocks or allocates. Samples are stored as
+// f32 bits in atomics so a reader racing with the writer gets stale values, never UB.
+pub struct SampleRing {
+    samples: Box<[AtomicU32]>,
+    // Total number of samples ever pushed, the slot is `written & mask`
+    written: AtomicUsize,
+    // Bumped by `clear` so readers notice a reset even when no new audio arrives
+    epoch: AtomicUsize,
+    mask: usize,
+}
+
+impl SampleRing {
+    pub fn new(capacity: usize) -> SampleRing {
+        assert!(capacity.is_power_of_two());
+        SampleRing {
+            samples: (0..capacity).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+            epoch: AtomicUsize::new(0),
+            mask: capacity - 1,
+        }
+    }
+
+    // Called from the audio thread only
+    pub fn push(&self, sample: f32) {
+        let pos = self.written.load(Ordering::Relaxed);
+        self.samples[pos & self.mask].store(sample.to_bits(), Ordering::Relaxed);
+        self.written.store(pos.wrapping_add(1), Ordering::Release);
+    }
+
+    pub fn written(&self) -> usize {
+        self.written.load(Ordering::Acquire)
+    }
+
+    pub fn epoch(&self) -> usize {
+        self.epoch.load(Ordering::Acquire)
+    }
+
+    // Forgets the buffered history, e.g. after a seek. Safe to call while the audio thread
+    // pushes: at worst a few fresh samples are zeroed too.
+    pub fn clear(&self) {
+        for sample in self.samples.iter() {
+            sample.store(0, Ordering::Relaxed);
+        }
+        self.epoch.fetch_add(1, Ordering::AcqRel);
+    }
+
+    // Copies the `dest.len()` newest samples into `dest`, oldest first
+    pub fn read_latest(&self, dest: &mut [f32]) -> usize {
+        let end = self.written();
+        self.read_ending_at(end, dest);
+        end
+    }
+
+    // Copies the newest samples of a left and a right ring into `left_dest` and `right_dest`.
+    // The callback pushes left before right, so reading both up to the right ring's position
+    // gives sample-aligned channels.
+    pub fn read_stereo_aligned(
+        left: &SampleRing,
+        right: &SampleRing,
+        left_dest: &mut [f32],
+        right_dest: &mut [f32],
+    ) {
+        let end = right.written();
+        left.read_ending_at(end, left_dest);
+        right.read_ending_at(end, right_dest);
+    }
+
+    // Copies the `dest.len()` samples preceding write position `end` into `dest`
+    fn read_ending_at(&self, end: usize, dest: &mut [f32]) {
+        assert!(dest.len() <= self.samples.len());
+        let start = end.wrapping_sub(dest.len());
+        for (i, sample) in dest.iter_mut().enumerate() {
+            let slot = start.wrapping_add(i) & self.mask;
+            *sample = f32::from_bits(self.samples[slot].load(Ordering::Relaxed));
+        }
+    }
+}
+
+// One published analysis result
+#[derive(Clone)]
+pub struct SpectrumFrame {
+    pub bins: Vec<Complex32>,
+    // Display values in 0..=1 for the n/2 positive-frequency bins, see compute_magnitudes
+    pub magnitudes: Vec<f32>,
+    // `magnitudes` folded into the configured number of log-spaced bands
+    pub bands: Vec<f32>,
+    // Bands of the left and right (or mid and side) signal, empty in the mono view
+    pub channel_bands: [Vec<f32>; 2],
+    // The view `channel_bands` were computed for
+    pub stereo: StereoView,
+    // Correlation of left and right over the analysed window: +1 mono, 0 unrelated, -1 out of
+    // phase
+    pub correlation: f32,
+    // Counts the published frames, so readers can tell a new frame from the last one again
+    pub sequence: u64,
+}
+
+impl SpectrumFrame {
+    pub fn new(n: usize) -> SpectrumFrame {
+        SpectrumFrame {
+            bins: vec![Complex32::new(0.0, 0.0); n],
+            magnitudes: vec![0.0; n / 2],
+            bands: Vec::new(),
+            channel_bands: [Vec::new(), Vec::new()],
+            stereo: StereoView::Mono,
+            correlation: 0.0,
+            sequence: 0,
+        }
+    }
+}
+
+const INDEX_MASK: u8 = 0b011;
+const FRESH: u8 = 0b100;
+
+// Triple buffer shared by one SnapshotWriter and one SnapshotReader. The writer always has a
+// slot of its own to fill, the reader always has a stable slot to look at, and the third slot
+// is swapped between them through `back`. Neither side ever waits for the other.
+struct TripleBuffer<T> {
+    slots: [UnsafeCell<T>; 3],
+    back: AtomicU8,
+}
+
+// Each slot is only ever touched by the side currently owning its index
+unsafe impl<T: Send> Sync for TripleBuffer<T> {}
+
+pub struct SnapshotWriter<T> {
+    shared: Arc<TripleBuffer<T>>,
+    index: usize,
+}
+
+pub struct SnapshotReader<T> {
+    shared: Arc<TripleBuffer<T>>,
+    index: usize,
+}
+
+pub fn snapshot_channel<T: Clone>(initial: T) -> (SnapshotWriter<T>, SnapshotReader<T>) {
+    let shared = Arc::new(TripleBuffer {
+        slots: [
+            UnsafeCell::new(initial.clone()),
+            UnsafeCell::new(initial.clone()),
+            UnsafeCell::new(initial),
+        ],
+        back: AtomicU8::new(1),
+    });
+    (
+        SnapshotWriter {
+            shared: shared.clone(),
+            index: 0,
+        },
+        SnapshotReader { shared, index: 2 },
+    )
+}
+
+impl<T> SnapshotWriter<T> {
+    // The slot being filled, not visible to the reader until `publish`
+    pub fn slot(&mut self) -> &mut T {
+        unsafe { &mut *self.shared.slots[self.index].get() }
+    }
+
+    pub fn publish(&mut self) {
+        let previous = self
+            .shared
+            .back
+            .swap(self.index as u8 | FRESH, Ordering::AcqRel);
+        self.index = (previous & INDEX_MASK) as usize;
+    }
+}
+
+impl<T> SnapshotReader<T> {
+    // The newest published value, or the last one read if nothing new was published
+    pub fn read(&mut self) -> &T {
+        if self.shared.back.load(Ordering::Relaxed) & FRESH != 0 {
+            let previous = self.shared.back.swap(self.index as u8, Ordering::AcqRel);
+            self.index = (previous & INDEX_MASK) as usize;
+        }
+        unsafe { &*self.shared.slots[self.index].get() }
+    }
+
+    // Like `read`, but only when something was published since the last read
+    pub fn read_fresh(&mut self) -> Option<&T> {
+        if self.shared.back.load(Ordering::Relaxed) & FRESH == 0 {
+            return None;
+        }
+        Some(self.read())
+    }
+}
+
+// Pearson correlation of two equally long signals, 0 when either is silent
+fn correlation(left: &[f32], right: &[f32]) -> f32 {
+    let (mut lr, mut ll, mut rr) = (0.0, 0.0, 0.0);
+    for (&l, &r) in left.iter().zip(right) {
+        lr += l * r;
+        ll += l * l;
+        rr += r * r;
+    }
+    let energy = (ll * rr).sqrt();
+    if energy > 1e-9 {
+        (lr / energy).clamp(-1.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+// The FFT state and scratch buffers of one analysis stream. The analysis thread runs one per
+// playback; the offline renderer drives its own synchronously.
+pub struct Analyzer {
+    n: usize,
+    planner: Fft,
+    samples: Vec<f32>,
+    channels: [Vec<f32>; 2],
+    // Scratch for the channel spectra, only the bands end up in the frame
+    channel_bins: Vec<Complex32>,
+    channel_magnitudes: Vec<f32>,
+    // Only recomputed when the selected window changes
+    window_kind: Option<WindowFunction>,
+    window: Vec<f32>,
+    window_sum: f32,
+    layout: Option<BandLayout>,
+    sequence: u64,
+}
+
+impl Analyzer {
+    pub fn new(n: usize) -> Analyzer {
+        Analyzer {
+            n,
+            planner: Fft::new(n),
+            samples: vec![0.0; n],
+            channels: [vec![0.0; n], vec![0.0; n]],
+            channel_bins: vec![Complex32::new(0.0, 0.0); n],
+            channel_magnitudes: vec![0.0; n / 2],
+            window_kind: None,
+            window: vec![0.0; n],
+            window_sum: 0.0,
+            layout: None,
+            sequence: 0,
+        }
+    }
+
+    // Analyses the newest samples of `ring` into `frame`. `left` and `right` hold the raw
+    // channels for the stereo views and the correlation. Returns the ring position read up to.
+    pub fn analyse(
+        &mut self,
+        ring: &SampleRing,
+        left: &SampleRing,
+        right: &SampleRing,
+        current: &SpectrumSettings,
+        frame: &mut SpectrumFrame,
+    ) -> usize {
+        let n = self.n;
+        let written = ring.read_latest(&mut self.samples);
+
+        let [left_channel, right_channel] = &mut self.channels;
+        SampleRing::read_stereo_aligned(left, right, left_channel, right_channel);
+
+        if self.window_kind != Some(current.window) {
+            current.window.fill(&mut self.window);
+            self.window_sum = self.window.iter().sum();
+            self.window_kind = Some(current.window);
+        }
+        for (sample, w) in self.samples.iter_mut().zip(self.window.iter()) {
+            *sample *= w;
+        }
+
+        self.planner.process_real(&self.samples, &mut frame.bins);
+        compute_magnitudes(
+            &frame.bins[..n / 2],
+            self.window_sum,
+            current,
+            &mut frame.magnitudes,
+        );
+
+        if !self
+            .layout
+            .as_ref()
+            .is_some_and(|l| l.matches(current.bands, n / 2, current.sample_rate))
+        {
+            self.layout = Some(BandLayout::new(current.bands, n / 2, current.sample_rate));
+        }
+        if let Some(layout) = &self.layout {
+            layout.aggregate(&frame.magnitudes, current.aggregation, &mut frame.bands);
+        }
+
+        frame.correlation = correlation(&self.channels[0], &self.channels[1]);
+        frame.stereo = current.stereo;
+        if current.stereo == StereoView::MidSide {
+            let [l, r] = &mut self.channels;
+            for (l, r) in l.iter_mut().zip(r.iter_mut()) {
+                (*l, *r) = ((*l + *r) / 2.0, (*l - *r) / 2.0);
+            }
+        }
+        for (signal, bands) in self.channels.iter_mut().zip(frame.channel_bands.iter_mut()) {
+            bands.clear();
+            if current.stereo == StereoView::Mono {
+                continue;
+            }
+            for (sample, w) in signal.iter_mut().zip(self.window.iter()) {
+                *sample *= w;
+            }
+            self.planner.process_real(signal, &mut self.channel_bins);
+            compute_magnitudes(
+                &self.channel_bins[..n / 2],
+                self.window_sum,
+                current,
+                &mut self.channel_magnitudes,
+            );
+            if let Some(layout) = &self.layout {
+                layout.aggregate(&self.channel_magnitudes, current.aggregation, bands);
+            }
+        }
+        self.sequence += 1;
+        frame.sequence = self.sequence;
+        written
+    }
+}
+
+// Runs the analysis whenever new audio arrives in `ring` and publishes the result. Keeps the
+// real-time audio callback down to a handful of atomic stores.
+pub fn spawn_analysis_thread(
+    ring: &'static SampleRing,
+    left: &'static SampleRing,
+    right: &'static SampleRing,
+    n: usize,
+    mut writer: SnapshotWriter<SpectrumFrame>,
+    settings: Arc<Mutex<SpectrumSettings>>,
+    stop: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    std::thread::Builder::new()
+        .name("analysis".into())
+        .spawn(move || {
+            let mut analyzer = Analyzer::new(n);
+            let mut last_written = ring.written();
+            let mut last_epoch = ring.epoch();
+
+            while !stop.load(Ordering::Relaxed) {
+                if ring.written() == last_written && ring.epoch() == last_epoch {
+                    std::thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+                last_epoch = ring.epoch();
+                let current = *settings.lock().unwrap();
+                last_written = analyzer.analyse(ring, left, right, &current, writer.slot());
+                writer.publish();
+            }
+        })
+        .expect("failed to spawn analysis thread")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ring_with(capacity: usize, count: usize) -> SampleRing {
+        let ring = SampleRing::new(capacity);
+        for i in 0..count {
+            ring.push(i as f32);
+        }
+        ring
+    }
+
+    #[test]
+    fn ring_reads_newest_samples_across_wraparound() {
+        let ring = ring_with(8, 13);
+        let mut dest = [0.0; 5];
+        assert_eq!(ring.read_latest(&mut dest), 13);
+        assert_eq!(dest, [8.0, 9.0, 10.0, 11.0, 12.0]);
+
+        // A full ring's worth still comes out oldest first
+        let mut dest = [0.0; 8];
+        ring.read_latest(&mut dest);
+        assert_eq!(dest, [5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 11.0, 12.0]);
+    }
+
+    #[test]
+    fn ring_reads_ending_at_an_earlier_position() {
+        let ring = ring_with(8, 11);
+        let mut dest = [0.0; 3];
+        ring.read_ending_at(9, &mut dest);
+        assert_eq!(dest, [6.0, 7.0, 8.0]);
+    }
+
+    #[test]
+    fn ring_starts_out_silent() {
+        let ring = ring_with(8, 2);
+        let mut dest = [1.0; 4];
+        ring.read_latest(&mut dest);
+        assert_eq!(dest, [0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn stereo_read_is_aligned_to_the_right_ring() {
+        let (left, right) = (SampleRing::new(8), SampleRing::new(8));
+        for i in 0..10 {
+            left.push(i as f32);
+            right.push(-(i as f32));
+        }
+        // The callback has pushed the next left sample but not its right one yet
+        left.push(10.0);
+        let (mut l, mut r) = ([0.0; 3], [0.0; 3]);
+        SampleRing::read_stereo_aligned(&left, &right, &mut l, &mut r);
+        assert_eq!(l, [7.0, 8.0, 9.0]);
+        assert_eq!(r, [-7.0, -8.0, -9.0]);
+    }
+
+    #[test]
+    fn clear_zeroes_history_and_bumps_epoch() {
+        let ring = ring_with(8, 6);
+        let epoch = ring.epoch();
+        ring.clear();
+        assert_eq!(ring.epoch(), epoch + 1);
+        // The write position is kept, so readers comparing `written` don't go backwards
+        assert_eq!(ring.written(), 6);
+        let mut dest = [1.0; 8];
+        ring.read_latest(&mut dest);
+        assert!(dest.iter().all(|&s| s == 0.0));
+
+        ring.push(42.0);
+        ring.read_latest(&mut dest[..1]);
+        assert_eq!(dest[0], 42.0);
+    }
+
+    #[test]
+    fn snapshot_reader_sees_initial_value_until_publish() {
+        let (mut writer, mut reader) = snapshot_channel(0);
+        assert_eq!(*reader.read(), 0);
+        *writer.slot() = 1;
+        // Not published yet
+        assert_eq!(*reader.read(), 0);
+        writer.publish();
+        assert_eq!(*reader.read(), 1);
+        // Nothing new: the last value is read again
+        assert_eq!(*reader.read(), 1);
+    }
+
+    #[test]
+    fn snapshot_reader_gets_only_the_newest_publish() {
+        let (mut writer, mut reader) = snapshot_channel(0);
+        for value in 1..=5 {
+            *writer.slot() = value;
+            writer.publish();
+        }
+        assert_eq!(*reader.read(), 5);
+        assert_eq!(*reader.read(), 5);
+    }
+
+    #[test]
+    fn snapshot_read_fresh_only_returns_new_publishes() {
+        let (mut writer, mut reader) = snapshot_channel(0);
+        assert_eq!(reader.read_fresh(), None);
+        *writer.slot() = 1;
+        writer.publish();
+        assert_eq!(reader.read_fresh(), Some(&1));
+        assert_eq!(reader.read_fresh(), None);
+        assert_eq!(*reader.read(), 1);
+    }
+
+    #[test]
+    fn snapshot_writer_never_touches_the_slot_being_read() {
+        let (mut writer, mut reader) = snapshot_channel(0);
+        for value in 1..100 {
+            *writer.slot() = value;
+            writer.publish();
+            let read = *reader.read();
+            assert_eq!(read, value);
+            // Whatever slot the writer fills next, the reader's value stays put
+            *writer.slot() = -1;
+            assert_eq!(*reader.read(), read);
+            if value % 3 == 0 {
+                writer.publish();
+                assert_eq!(*reader.read(), -1);
+            }
+        }
+    }
+}
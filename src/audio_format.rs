@@ -0,0 +1,104 @@
+// How a stereo frame becomes the single signal the spectrum is computed from
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Downmix {
+    // Average of both channels
+    Mid,
+    Left,
+    Right,
+    // Louder channel, so content panned hard to one side isn't halved
+    Max,
+}
+
+impl Downmix {
+    pub fn next(self) -> Downmix {
+        match self {
+            Downmix::Mid => Downmix::Left,
+            Downmix::Left => Downmix::Right,
+            Downmix::Right => Downmix::Max,
+            Downmix::Max => Downmix::Mid,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Downmix::Mid => "Mid",
+            Downmix::Left => "Left",
+            Downmix::Right => "Right",
+            Downmix::Max => "Max",
+        }
+    }
+
+    pub fn apply(self, left: f32, right: f32) -> f32 {
+        match self {
+            Downmix::Mid => (left + right) / 2.0,
+            Downmix::Left => left,
+            Downmix::Right => right,
+            Downmix::Max => {
+                if right.abs() > left.abs() {
+                    right
+                } else {
+                    left
+                }
+            }
+        }
+    }
+}
+
+// Walks interleaved stereo f32 frame by frame. `sink` receives the downmixed sample followed by
+// the left and right channel. Every Decoder hands out this format whatever the file contains,
+// mono files with the same signal in both channels.
+pub fn for_each_frame(samples: &[f32], downmix: Downmix, mut sink: impl FnMut(f32, f32, f32)) {
+    for frame in samples.chunks_exact(2) {
+        sink(downmix.apply(frame[0], frame[1]), frame[0], frame[1]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Downmix; 4] = [Downmix::Mid, Downmix::Left, Downmix::Right, Downmix::Max];
+
+    #[test]
+    fn downmix_picks_the_channels() {
+        assert_eq!(Downmix::Mid.apply(0.5, -0.25), 0.125);
+        assert_eq!(Downmix::Left.apply(0.5, -0.25), 0.5);
+        assert_eq!(Downmix::Right.apply(0.5, -0.25), -0.25);
+    }
+
+    #[test]
+    fn downmix_max_keeps_the_louder_sample_with_its_sign() {
+        assert_eq!(Downmix::Max.apply(0.25, -0.75), -0.75);
+        assert_eq!(Downmix::Max.apply(-0.5, 0.25), -0.5);
+        assert_eq!(Downmix::Max.apply(0.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn downmix_of_mono_is_the_mono_signal() {
+        // Mono files are decoded to the same signal in both channels
+        for downmix in ALL {
+            assert_eq!(downmix.apply(-0.3, -0.3), -0.3, "{}", downmix.name());
+        }
+    }
+
+    #[test]
+    fn downmix_cycles_through_every_mode() {
+        let mut downmix = Downmix::Mid;
+        for expected in ALL.iter().cycle().skip(1).take(4) {
+            downmix = downmix.next();
+            assert_eq!(downmix, *expected);
+        }
+    }
+
+    #[test]
+    fn for_each_frame_splits_interleaved_stereo() {
+        let mut frames = Vec::new();
+        for_each_frame(
+            &[0.5, -0.5, 1.0, 0.0, 0.25],
+            Downmix::Mid,
+            |mixed, left, right| frames.push((mixed, left, right)),
+        );
+        // The odd sample isn't a whole frame
+        assert_eq!(frames, [(0.0, 0.5, -0.5), (0.5, 1.0, 0.0)]);
+    }
+}
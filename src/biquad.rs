@@ -0,0 +1,208 @@
+use num_complex::Complex64;
+use std::f64::consts::PI;
+
+// One second-order IIR section in direct form I. The designs are the ones from Robert
+// Bristow-Johnson's Audio EQ Cookbook.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    // Coefficients already divided by a0
+    pub fn new(b: [f64; 3], a: [f64; 2]) -> Biquad {
+        Biquad {
+            b,
+            a,
+            ..Biquad::default()
+        }
+    }
+
+    fn normalized(b: [f64; 3], a: [f64; 3]) -> Biquad {
+        Biquad::new(
+            [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            [a[1] / a[0], a[2] / a[0]],
+        )
+    }
+
+    pub fn peaking(sample_rate: u32, frequency: f32, q: f32, gain_db: f32) -> Biquad {
+        let (cos, alpha) = angle(sample_rate, frequency, q);
+        let a = 10f64.powf(gain_db as f64 / 40.0);
+        Biquad::normalized(
+            [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+            [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+        )
+    }
+
+    pub fn low_shelf(sample_rate: u32, frequency: f32, q: f32, gain_db: f32) -> Biquad {
+        let (cos, alpha) = angle(sample_rate, frequency, q);
+        let a = 10f64.powf(gain_db as f64 / 40.0);
+        let beta = 2.0 * a.sqrt() * alpha;
+        Biquad::normalized(
+            [
+                a * ((a + 1.0) - (a - 1.0) * cos + beta),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - beta),
+            ],
+            [
+                (a + 1.0) + (a - 1.0) * cos + beta,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - beta,
+            ],
+        )
+    }
+
+    pub fn high_shelf(sample_rate: u32, frequency: f32, q: f32, gain_db: f32) -> Biquad {
+        let (cos, alpha) = angle(sample_rate, frequency, q);
+        let a = 10f64.powf(gain_db as f64 / 40.0);
+        let beta = 2.0 * a.sqrt() * alpha;
+        Biquad::normalized(
+            [
+                a * ((a + 1.0) + (a - 1.0) * cos + beta),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - beta),
+            ],
+            [
+                (a + 1.0) - (a - 1.0) * cos + beta,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - beta,
+            ],
+        )
+    }
+
+    pub fn low_pass(sample_rate: u32, frequency: f32, q: f32) -> Biquad {
+        let (cos, alpha) = angle(sample_rate, frequency, q);
+        Biquad::normalized(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    pub fn high_pass(sample_rate: u32, frequency: f32, q: f32) -> Biquad {
+        let (cos, alpha) = angle(sample_rate, frequency, q);
+        Biquad::normalized(
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    pub fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [input, self.x[0]];
+        self.y = [output, self.y[0]];
+        output
+    }
+
+    // Takes over the coefficients of `design` but keeps this section's history, so a band
+    // can be moved while audio runs through it without a click
+    pub fn retune(&mut self, design: &Biquad) {
+        self.b = design.b;
+        self.a = design.a;
+    }
+
+    // Gain in dB the section applies at `frequency`
+    pub fn response_db(&self, sample_rate: u32, frequency: f32) -> f32 {
+        let w = 2.0 * PI * frequency as f64 / sample_rate as f64;
+        let z1 = Complex64::from_polar(1.0, -w);
+        let z2 = z1 * z1;
+        let numerator = self.b[0] + self.b[1] * z1 + self.b[2] * z2;
+        let denominator = 1.0 + self.a[0] * z1 + self.a[1] * z2;
+        (20.0 * (numerator.norm() / denominator.norm()).max(1e-12).log10()) as f32
+    }
+}
+
+// cos(w0) and alpha of the cookbook formulas. The frequency is kept below Nyquist, where the
+// designs stop being stable.
+fn angle(sample_rate: u32, frequency: f32, q: f32) -> (f64, f64) {
+    let nyquist = sample_rate as f64 / 2.0;
+    let frequency = (frequency as f64).clamp(1.0, nyquist * 0.99);
+    let w0 = 2.0 * PI * frequency / sample_rate as f64;
+    (w0.cos(), w0.sin() / (2.0 * q.max(0.01) as f64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48000;
+    const NYQUIST: f32 = RATE as f32 / 2.0;
+
+    fn assert_db(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "{} dB, expected {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn peaking_applies_its_gain_at_the_center() {
+        for frequency in [100.0, 1000.0, 10000.0] {
+            for q in [0.5, 1.41, 4.0] {
+                for gain in [-12.0, -3.0, 6.0, 12.0] {
+                    let section = Biquad::peaking(RATE, frequency, q, gain);
+                    assert_db(section.response_db(RATE, frequency), gain);
+                    // And leaves the far ends alone
+                    assert_db(section.response_db(RATE, 0.0), 0.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn shelves_apply_their_gain_past_the_corner() {
+        for gain in [-12.0, 6.0] {
+            let low = Biquad::low_shelf(RATE, 500.0, 0.707, gain);
+            assert_db(low.response_db(RATE, 0.0), gain);
+            assert_db(low.response_db(RATE, 500.0), gain / 2.0);
+            assert_db(low.response_db(RATE, NYQUIST), 0.0);
+
+            let high = Biquad::high_shelf(RATE, 5000.0, 0.707, gain);
+            assert_db(high.response_db(RATE, NYQUIST), gain);
+            assert_db(high.response_db(RATE, 5000.0), gain / 2.0);
+            assert_db(high.response_db(RATE, 0.0), 0.0);
+        }
+    }
+
+    #[test]
+    fn pass_filters_pass_one_end_and_block_the_other() {
+        let low = Biquad::low_pass(RATE, 1000.0, std::f32::consts::FRAC_1_SQRT_2);
+        assert_db(low.response_db(RATE, 0.0), 0.0);
+        assert_db(low.response_db(RATE, 1000.0), -3.01);
+        assert!(low.response_db(RATE, NYQUIST) < -100.0);
+
+        let high = Biquad::high_pass(RATE, 1000.0, std::f32::consts::FRAC_1_SQRT_2);
+        assert_db(high.response_db(RATE, NYQUIST), 0.0);
+        assert_db(high.response_db(RATE, 1000.0), -3.01);
+        assert!(high.response_db(RATE, 0.0) < -100.0);
+    }
+
+    #[test]
+    fn process_settles_to_the_dc_gain() {
+        let mut section = Biquad::low_shelf(RATE, 200.0, 0.707, 6.0);
+        let mut output = 0.0;
+        for _ in 0..RATE {
+            output = section.process(1.0);
+        }
+        let expected = 10f64.powf(6.0 / 20.0);
+        assert!((output - expected).abs() < 1e-6, "{}", output);
+    }
+
+    #[test]
+    fn retune_keeps_the_history() {
+        let mut section = Biquad::low_pass(RATE, 1000.0, 0.707);
+        for _ in 0..100 {
+            section.process(1.0);
+        }
+        let settled = section.process(1.0);
+        // The same design again changes nothing, the next output carries on smoothly
+        section.retune(&Biquad::low_pass(RATE, 1000.0, 0.707));
+        assert!((section.process(1.0) - settled).abs() < 1e-3);
+    }
+}
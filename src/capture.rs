@@ -0,0 +1,229 @@
+use raylib::ffi::{
+    rlDrawRenderBatchActive, ExportImage, Image, LoadImageFromScreen, PixelFormat, UnloadImage,
+};
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Frames a recording may run ahead of each PNG encoder before frames get dropped
+const RECORD_QUEUE: usize = 8;
+// PNG encoder threads per recording at most
+const MAX_RECORD_WORKERS: usize = 4;
+
+// A copy of the screen, tightly packed RGBA8
+pub struct CapturedFrame {
+    width: i32,
+    height: i32,
+    pixels: Vec<u8>,
+}
+
+// Reads back what has been drawn so far this frame, so it must be called between BeginDrawing
+// and EndDrawing
+pub fn capture_screen() -> CapturedFrame {
+    unsafe {
+        // Anything still batched has to reach the framebuffer first
+        rlDrawRenderBatchActive();
+        let image = LoadImageFromScreen();
+        let len = image.width as usize * image.height as usize * 4;
+        let pixels = std::slice::from_raw_parts(image.data as *const u8, len).to_vec();
+        let frame = CapturedFrame {
+            width: image.width,
+            height: image.height,
+            pixels,
+        };
+        UnloadImage(image);
+        frame
+    }
+}
+
+pub fn save_png(frame: &CapturedFrame, path: &Path) -> Result<(), String> {
+    let c_path = CString::new(path.to_string_lossy().into_owned()).map_err(|e| e.to_string())?;
+    // raylib only reads the pixels, they stay owned by `frame`
+    let image = Image {
+        data: frame.pixels.as_ptr() as *mut std::ffi::c_void,
+        width: frame.width,
+        height: frame.height,
+        mipmaps: 1,
+        format: PixelFormat::PIXELFORMAT_UNCOMPRESSED_R8G8B8A8 as i32,
+    };
+    if unsafe { ExportImage(image, c_path.as_ptr()) } {
+        Ok(())
+    } else {
+        Err(format!("could not write {}", path.display()))
+    }
+}
+
+// Current UTC time as 20241017-153045-123, sorting like the time it names
+pub fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = now.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}",
+        year,
+        month,
+        day,
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60,
+        now.subsec_millis()
+    )
+}
+
+// Gregorian date of the day `days` after 1970-01-01, from Howard Hinnant's date algorithms
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// Writes the frames it is handed as numbered PNGs into one directory. Encoding is spread over
+// a few worker threads; when they still fall behind, frames are dropped rather than stalling
+// the render loop, and `frames.ffconcat` keeps the time each written frame was captured at.
+pub struct Recorder {
+    pub dir: PathBuf,
+    pub frames: usize,
+    pub dropped: usize,
+    started: Instant,
+    // Capture time of every written frame, relative to `started`
+    times: Vec<Duration>,
+    sender: SyncSender<(PathBuf, CapturedFrame)>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Recorder {
+    pub fn start(dir: PathBuf) -> Result<Recorder, String> {
+        std::fs::create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        // Leaves a core each for drawing and audio
+        let count = std::thread::available_parallelism()
+            .map_or(1, |n| n.get().saturating_sub(2))
+            .clamp(1, MAX_RECORD_WORKERS);
+        let (sender, receiver) = sync_channel::<(PathBuf, CapturedFrame)>(RECORD_QUEUE * count);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let mut workers = Vec::with_capacity(count);
+        for i in 0..count {
+            let receiver = receiver.clone();
+            let worker = std::thread::Builder::new()
+                .name(format!("recorder-{}", i))
+                .spawn(move || loop {
+                    // The lock is only held while waiting, not while encoding
+                    let job = receiver.lock().unwrap().recv();
+                    let Ok((path, frame)) = job else {
+                        break;
+                    };
+                    if let Err(e) = save_png(&frame, &path) {
+                        println!("Recording: {}", e);
+                    }
+                })
+                .map_err(|e| e.to_string())?;
+            workers.push(worker);
+        }
+        Ok(Recorder {
+            dir,
+            frames: 0,
+            dropped: 0,
+            started: Instant::now(),
+            times: Vec::new(),
+            sender,
+            workers,
+        })
+    }
+
+    pub fn push(&mut self, frame: CapturedFrame) {
+        let path = self.dir.join(frame_name(self.frames + 1));
+        match self.sender.try_send((path, frame)) {
+            Ok(()) => {
+                self.frames += 1;
+                self.times.push(self.started.elapsed());
+            }
+            Err(TrySendError::Full(_)) => {
+                if self.dropped == 0 {
+                    println!("Recording: the PNG encoders can't keep up, dropping frames");
+                }
+                self.dropped += 1;
+            }
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+
+    // Waits for the queued frames to be written, then writes the frame list
+    pub fn finish(self) {
+        drop(self.sender);
+        for worker in self.workers {
+            worker.join().ok();
+        }
+        let path = self.dir.join("frames.ffconcat");
+        if let Err(e) = std::fs::write(&path, concat_list(&self.times)) {
+            println!("Recording: {}: {}", path.display(), e);
+        }
+        if self.dropped > 0 {
+            println!(
+                "Recording dropped {} frames, {} has the real timing",
+                self.dropped,
+                path.display()
+            );
+        }
+    }
+}
+
+fn frame_name(number: usize) -> String {
+    format!("frame-{:06}.png", number)
+}
+
+// An ffmpeg concat script showing every frame for as long as it was on screen. The last frame
+// lasts as long as the one before it and is listed twice, since ffmpeg ignores the last duration.
+fn concat_list(times: &[Duration]) -> String {
+    let mut list = String::from("ffconcat version 1.0\n");
+    for (i, time) in times.iter().enumerate() {
+        let duration = match (times.get(i + 1), i.checked_sub(1)) {
+            (Some(next), _) => *next - *time,
+            (None, Some(previous)) => *time - times[previous],
+            (None, None) => Duration::ZERO,
+        };
+        list.push_str(&format!(
+            "file {}\nduration {:.6}\n",
+            frame_name(i + 1),
+            duration.as_secs_f64()
+        ));
+    }
+    if !times.is_empty() {
+        list.push_str(&format!("file {}\n", frame_name(times.len())));
+    }
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concat_list_times_every_frame_until_the_next() {
+        let times = [0, 20, 35, 70].map(Duration::from_millis);
+        assert_eq!(
+            concat_list(&times),
+            "ffconcat version 1.0\n\
+             file frame-000001.png\nduration 0.020000\n\
+             file frame-000002.png\nduration 0.015000\n\
+             file frame-000003.png\nduration 0.035000\n\
+             file frame-000004.png\nduration 0.035000\n\
+             file frame-000004.png\n"
+        );
+    }
+
+    #[test]
+    fn concat_list_of_no_frames_is_only_the_header() {
+        assert_eq!(concat_list(&[]), "ffconcat version 1.0\n");
+    }
+}
@@ -0,0 +1,950 @@
+use crate::equalizer::{
+    Band, EqSettings, FilterKind, GRAPHIC_FREQUENCIES, MAX_FREQUENCY, MAX_GAIN_DB,
+    MAX_PARAMETRIC_BANDS, MAX_Q, MIN_FREQUENCY, MIN_Q, PRESETS as EQ_PRESETS,
+};
+use crate::loudness::GainMode;
+use crate::modes::builtin_visualizers;
+use crate::playback::Transition;
+use crate::spectrogram::Colormap;
+use crate::theme::{load_theme_dir, Theme, ThemeOverrides, PRESETS};
+use raylib::ffi::{KeyboardKey, KeyboardKey::*};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use toml::Spanned;
+
+// FFT sizes accepted in the config, the sample rings are sized for the largest
+pub const MIN_FFT_SIZE: usize = 1 << 10;
+pub const MAX_FFT_SIZE: usize = 1 << 16;
+
+// Every action that can be rebound in [keys], with its default key. The number keys are not
+// listed, they always jump to a tenth of the track.
+const DEFAULT_KEYS: [(&str, KeyboardKey); 43] = [
+    ("add_file", KEY_F),
+    ("pause", KEY_SPACE),
+    ("next_track", KEY_N),
+    ("previous_track", KEY_P),
+    ("shuffle", KEY_S),
+    ("repeat", KEY_R),
+    ("export_queue", KEY_E),
+    ("quit", KEY_Q),
+    ("mute", KEY_M),
+    ("volume_up", KEY_UP),
+    ("volume_down", KEY_DOWN),
+    ("seek_back", KEY_LEFT),
+    ("seek_forward", KEY_RIGHT),
+    ("next_mode", KEY_V),
+    ("previous_mode", KEY_B),
+    ("scope_view", KEY_X),
+    ("window", KEY_W),
+    ("db_scale", KEY_D),
+    ("tilt", KEY_T),
+    ("aggregation", KEY_A),
+    ("downmix", KEY_C),
+    ("stereo", KEY_L),
+    ("theme", KEY_H),
+    ("gradient", KEY_G),
+    ("colormap", KEY_K),
+    ("screenshot", KEY_F12),
+    ("record", KEY_F11),
+    ("capture_hud", KEY_F10),
+    ("transition", KEY_Z),
+    ("loudness", KEY_J),
+    ("equalizer", KEY_O),
+    ("eq_preset", KEY_I),
+    ("eq_bypass", KEY_U),
+    ("tempo_down", KEY_LEFT_BRACKET),
+    ("tempo_up", KEY_RIGHT_BRACKET),
+    ("pitch_down", KEY_MINUS),
+    ("pitch_up", KEY_EQUAL),
+    ("varispeed_down", KEY_COMMA),
+    ("varispeed_up", KEY_PERIOD),
+    ("speed_reset", KEY_BACKSPACE),
+    ("loop_start", KEY_SEMICOLON),
+    ("loop_end", KEY_APOSTROPHE),
+    ("loop_clear", KEY_SLASH),
+];
+
+// raylib key codes for every action, see DEFAULT_KEYS for the names used in the file
+#[derive(Copy, Clone, Debug)]
+pub struct KeyBindings {
+    pub add_file: i32,
+    pub pause: i32,
+    pub next_track: i32,
+    pub previous_track: i32,
+    pub shuffle: i32,
+    pub repeat: i32,
+    pub export_queue: i32,
+    pub quit: i32,
+    pub mute: i32,
+    pub volume_up: i32,
+    pub volume_down: i32,
+    pub seek_back: i32,
+    pub seek_forward: i32,
+    pub next_mode: i32,
+    pub previous_mode: i32,
+    pub scope_view: i32,
+    pub window: i32,
+    pub db_scale: i32,
+    pub tilt: i32,
+    pub aggregation: i32,
+    pub downmix: i32,
+    pub stereo: i32,
+    pub theme: i32,
+    pub gradient: i32,
+    pub colormap: i32,
+    pub screenshot: i32,
+    pub record: i32,
+    pub capture_hud: i32,
+    pub transition: i32,
+    pub loudness: i32,
+    pub equalizer: i32,
+    pub eq_preset: i32,
+    pub eq_bypass: i32,
+    pub tempo_down: i32,
+    pub tempo_up: i32,
+    pub pitch_down: i32,
+    pub pitch_up: i32,
+    pub varispeed_down: i32,
+    pub varispeed_up: i32,
+    pub speed_reset: i32,
+    pub loop_start: i32,
+    pub loop_end: i32,
+    pub loop_clear: i32,
+}
+
+// Validated settings, with today's hard-coded values for everything the file leaves out
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub width: i32,
+    pub height: i32,
+    pub fps: i32,
+    pub volume: f32,
+    pub fft_size: usize,
+    pub font_path: String,
+    // Name of a built-in visualization mode
+    pub startup_mode: String,
+    // The presets, then the theme files, then the [colors] table if there is one
+    pub themes: Vec<(String, Theme)>,
+    // Index into `themes`
+    pub startup_theme: usize,
+    pub colormap: Colormap,
+    // Seconds of history the spectrogram shows
+    pub spectrogram_history: f32,
+    pub transition: Transition,
+    // Length of the crossfade, also when switching to it at runtime from gapless
+    pub crossfade: f32,
+    pub gain_mode: GainMode,
+    // dB added to every ReplayGain gain
+    pub preamp: f32,
+    pub equalizer: EqSettings,
+    // Index into equalizer::PRESETS of the graphic curve, None for one set in the file
+    pub eq_preset: Option<usize>,
+    pub keys: KeyBindings,
+}
+
+// The file as written. Every value keeps its position so errors can point at it.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawConfig {
+    font: Option<Spanned<String>>,
+    theme: Option<Spanned<String>>,
+    window: RawWindow,
+    audio: RawAudio,
+    analysis: RawAnalysis,
+    colors: ThemeOverrides,
+    spectrogram: RawSpectrogram,
+    playback: RawPlayback,
+    loudness: RawLoudness,
+    equalizer: RawEqualizer,
+    keys: BTreeMap<String, Spanned<String>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawWindow {
+    width: Option<Spanned<i64>>,
+    height: Option<Spanned<i64>>,
+    fps: Option<Spanned<i64>>,
+    mode: Option<Spanned<String>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawAudio {
+    volume: Option<Spanned<f64>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawAnalysis {
+    fft_size: Option<Spanned<i64>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawSpectrogram {
+    history: Option<Spanned<f64>>,
+    colormap: Option<Spanned<String>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawPlayback {
+    transition: Option<Spanned<String>>,
+    crossfade: Option<Spanned<f64>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawLoudness {
+    mode: Option<Spanned<String>>,
+    preamp: Option<Spanned<f64>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawEqualizer {
+    enabled: Option<Spanned<bool>>,
+    preset: Option<Spanned<String>>,
+    graphic: Option<Spanned<Vec<f64>>>,
+    bands: Vec<Spanned<RawBand>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawBand {
+    #[serde(rename = "type")]
+    kind: Spanned<String>,
+    frequency: Spanned<f64>,
+    gain: Option<Spanned<f64>>,
+    q: Option<Spanned<f64>>,
+}
+
+// $XDG_CONFIG_HOME/rusty-raven, or ~/.config/rusty-raven when XDG_CONFIG_HOME is unset
+pub fn config_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("rusty-raven"))
+}
+
+impl Config {
+    // Reads the config file. A missing file gives the defaults, anything wrong in an existing
+    // one is an error naming the file, line and column.
+    // Custom themes are read from the themes/ directory next to the file.
+    pub fn load() -> Result<Config, String> {
+        let Some(dir) = config_dir() else {
+            return Config::from_raw(RawConfig::default(), "", Vec::new());
+        };
+        let custom_themes = load_theme_dir(&dir.join("themes"));
+        let path = dir.join("config.toml");
+        let source = match std::fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Config::from_raw(RawConfig::default(), "", custom_themes)
+            }
+            Err(e) => return Err(format!("{}: {}", path.display(), e)),
+        };
+        Config::parse(&source, custom_themes).map_err(|e| format!("{}:{}", path.display(), e))
+    }
+
+    fn parse(source: &str, custom_themes: Vec<(String, Theme)>) -> Result<Config, String> {
+        let raw: RawConfig = toml::from_str(source).map_err(|e| match e.span() {
+            Some(span) => format!("{}: {}", location(source, span.start), e.message()),
+            None => format!(" {}", e.message()),
+        })?;
+        Config::from_raw(raw, source, custom_themes)
+    }
+
+    fn from_raw(
+        raw: RawConfig,
+        source: &str,
+        custom_themes: Vec<(String, Theme)>,
+    ) -> Result<Config, String> {
+        // Points an error at the value it is about
+        let fail = |span: std::ops::Range<usize>, message: String| {
+            format!("{}: {}", location(source, span.start), message)
+        };
+        let int_in =
+            |value: &Option<Spanned<i64>>, name: &str, min: i64, max: i64, default| match value {
+                None => Ok(default),
+                Some(v) if (min..=max).contains(v.get_ref()) => Ok(*v.get_ref()),
+                Some(v) => Err(fail(
+                    v.span(),
+                    format!(
+                        "{} must be between {} and {}, got {}",
+                        name,
+                        min,
+                        max,
+                        v.get_ref()
+                    ),
+                )),
+            };
+
+        let width = int_in(&raw.window.width, "window.width", 800, 7680, 1280)? as i32;
+        let height = int_in(&raw.window.height, "window.height", 450, 4320, 720)? as i32;
+        let fps = int_in(&raw.window.fps, "window.fps", 10, 500, 60)? as i32;
+
+        let volume = match &raw.audio.volume {
+            None => 0.8,
+            Some(v) if (0.0..=1.0).contains(v.get_ref()) => *v.get_ref() as f32,
+            Some(v) => {
+                return Err(fail(
+                    v.span(),
+                    format!(
+                        "audio.volume must be between 0.0 and 1.0, got {}",
+                        v.get_ref()
+                    ),
+                ))
+            }
+        };
+
+        let fft_size = int_in(
+            &raw.analysis.fft_size,
+            "analysis.fft_size",
+            MIN_FFT_SIZE as i64,
+            MAX_FFT_SIZE as i64,
+            1 << 13,
+        )? as usize;
+        if !fft_size.is_power_of_two() {
+            let span = raw.analysis.fft_size.as_ref().map(|v| v.span());
+            return Err(fail(
+                span.unwrap_or(0..0),
+                format!(
+                    "analysis.fft_size must be a power of two, e.g. {}",
+                    fft_size.next_power_of_two()
+                ),
+            ));
+        }
+
+        let font_path = match &raw.font {
+            None => "resources/fonts/monogram.ttf".to_string(),
+            Some(font) if font.get_ref().trim().is_empty() => {
+                return Err(fail(font.span(), "font must not be empty".to_string()))
+            }
+            Some(font) => font.get_ref().clone(),
+        };
+
+        let spectrogram_history = match &raw.spectrogram.history {
+            None => 10.0,
+            Some(v) if (1.0..=120.0).contains(v.get_ref()) => *v.get_ref() as f32,
+            Some(v) => {
+                return Err(fail(
+                    v.span(),
+                    format!(
+                        "spectrogram.history must be between 1 and 120 seconds, got {}",
+                        v.get_ref()
+                    ),
+                ))
+            }
+        };
+        let colormap = match &raw.spectrogram.colormap {
+            None => Colormap::Viridis,
+            Some(name) => Colormap::from_name(name.get_ref()).ok_or_else(|| {
+                fail(
+                    name.span(),
+                    format!(
+                        "unknown spectrogram.colormap \"{}\", expected one of {}",
+                        name.get_ref(),
+                        Colormap::NAMES.join(", ")
+                    ),
+                )
+            })?,
+        };
+
+        let modes: Vec<String> = builtin_visualizers(colormap, spectrogram_history)
+            .iter()
+            .map(|v| v.name().to_string())
+            .collect();
+        let startup_mode = match &raw.window.mode {
+            None => modes[0].clone(),
+            Some(mode) => modes
+                .iter()
+                .find(|name| name.eq_ignore_ascii_case(mode.get_ref()))
+                .cloned()
+                .ok_or_else(|| {
+                    fail(
+                        mode.span(),
+                        format!(
+                            "unknown window.mode \"{}\", expected one of {}",
+                            mode.get_ref(),
+                            modes.join(", ")
+                        ),
+                    )
+                })?,
+        };
+
+        let mut themes: Vec<(String, Theme)> = PRESETS
+            .iter()
+            .map(|(name, theme)| (name.to_string(), *theme))
+            .chain(custom_themes)
+            .collect();
+        let mut startup_theme = match &raw.theme {
+            None => 0,
+            Some(name) => themes
+                .iter()
+                .position(|(n, _)| n.eq_ignore_ascii_case(name.get_ref()))
+                .ok_or_else(|| {
+                    let names: Vec<&str> = themes.iter().map(|(n, _)| n.as_str()).collect();
+                    fail(
+                        name.span(),
+                        format!(
+                            "unknown theme \"{}\", expected one of {}",
+                            name.get_ref(),
+                            names.join(", ")
+                        ),
+                    )
+                })?,
+        };
+        // [colors] tweaks the startup theme into a theme of its own, the original stays in the
+        // cycle
+        if !raw.colors.is_empty() {
+            let mut theme = themes[startup_theme].1;
+            raw.colors.apply(&mut theme, source)?;
+            let name = raw
+                .colors
+                .name
+                .clone()
+                .unwrap_or_else(|| "Custom".to_string());
+            themes.push((name, theme));
+            startup_theme = themes.len() - 1;
+        }
+
+        let crossfade = match &raw.playback.crossfade {
+            None => 4.0,
+            Some(v) if (0.5..=12.0).contains(v.get_ref()) => *v.get_ref() as f32,
+            Some(v) => {
+                return Err(fail(
+                    v.span(),
+                    format!(
+                        "playback.crossfade must be between 0.5 and 12 seconds, got {}",
+                        v.get_ref()
+                    ),
+                ))
+            }
+        };
+        let transition = match &raw.playback.transition {
+            None => Transition::Gapless,
+            Some(name) => match name.get_ref().to_ascii_lowercase().as_str() {
+                "gapless" => Transition::Gapless,
+                "crossfade" => Transition::Crossfade(crossfade),
+                _ => {
+                    return Err(fail(
+                        name.span(),
+                        format!(
+                            "unknown playback.transition \"{}\", expected gapless or crossfade",
+                            name.get_ref()
+                        ),
+                    ))
+                }
+            },
+        };
+
+        let gain_mode = match &raw.loudness.mode {
+            None => GainMode::Track,
+            Some(name) => GainMode::from_name(name.get_ref()).ok_or_else(|| {
+                fail(
+                    name.span(),
+                    format!(
+                        "unknown loudness.mode \"{}\", expected one of {}",
+                        name.get_ref(),
+                        GainMode::NAMES.join(", ")
+                    ),
+                )
+            })?,
+        };
+        let preamp = match &raw.loudness.preamp {
+            None => 0.0,
+            Some(v) if (-15.0..=15.0).contains(v.get_ref()) => *v.get_ref() as f32,
+            Some(v) => {
+                return Err(fail(
+                    v.span(),
+                    format!(
+                        "loudness.preamp must be between -15 and 15 dB, got {}",
+                        v.get_ref()
+                    ),
+                ))
+            }
+        };
+
+        let (equalizer, eq_preset) = parse_equalizer(&raw.equalizer, &fail)?;
+
+        let keys = parse_keys(&raw.keys, &fail)?;
+
+        Ok(Config {
+            width,
+            height,
+            fps,
+            volume,
+            fft_size,
+            font_path,
+            startup_mode,
+            themes,
+            startup_theme,
+            colormap,
+            spectrogram_history,
+            transition,
+            crossfade,
+            gain_mode,
+            preamp,
+            equalizer,
+            eq_preset,
+            keys,
+        })
+    }
+}
+
+fn parse_equalizer(
+    raw: &RawEqualizer,
+    fail: &dyn Fn(std::ops::Range<usize>, String) -> String,
+) -> Result<(EqSettings, Option<usize>), String> {
+    let float_in = |value: &Spanned<f64>, name: &str, min: f32, max: f32| {
+        let v = *value.get_ref() as f32;
+        if (min..=max).contains(&v) {
+            Ok(v)
+        } else {
+            Err(fail(
+                value.span(),
+                format!("{} must be between {} and {}, got {}", name, min, max, v),
+            ))
+        }
+    };
+
+    let mut preset = match &raw.preset {
+        None => Some(0),
+        Some(name) => Some(
+            EQ_PRESETS
+                .iter()
+                .position(|(n, _)| n.eq_ignore_ascii_case(&name.get_ref().replace('_', " ")))
+                .ok_or_else(|| {
+                    let names: Vec<String> = EQ_PRESETS
+                        .iter()
+                        .map(|(n, _)| n.to_lowercase().replace(' ', "_"))
+                        .collect();
+                    fail(
+                        name.span(),
+                        format!(
+                            "unknown equalizer.preset \"{}\", expected one of {}",
+                            name.get_ref(),
+                            names.join(", ")
+                        ),
+                    )
+                })?,
+        ),
+    };
+    let mut graphic = EQ_PRESETS[preset.unwrap_or(0)].1;
+    // Explicit gains win over the preset
+    if let Some(gains) = &raw.graphic {
+        if gains.get_ref().len() != GRAPHIC_FREQUENCIES.len() {
+            return Err(fail(
+                gains.span(),
+                format!(
+                    "equalizer.graphic needs {} gains, one per band from 31 Hz to 16 kHz, got {}",
+                    GRAPHIC_FREQUENCIES.len(),
+                    gains.get_ref().len()
+                ),
+            ));
+        }
+        for (i, &gain) in gains.get_ref().iter().enumerate() {
+            let gain = gain as f32;
+            if !(-MAX_GAIN_DB..=MAX_GAIN_DB).contains(&gain) {
+                return Err(fail(
+                    gains.span(),
+                    format!(
+                        "equalizer.graphic gains must be between -{} and {} dB, got {} for {} Hz",
+                        MAX_GAIN_DB, MAX_GAIN_DB, gain, GRAPHIC_FREQUENCIES[i]
+                    ),
+                ));
+            }
+            graphic[i] = gain;
+        }
+        preset = None;
+    }
+
+    if raw.bands.len() > MAX_PARAMETRIC_BANDS {
+        return Err(fail(
+            raw.bands[MAX_PARAMETRIC_BANDS].span(),
+            format!(
+                "equalizer.bands can have at most {} bands",
+                MAX_PARAMETRIC_BANDS
+            ),
+        ));
+    }
+    let mut parametric = Vec::new();
+    for band in &raw.bands {
+        let band = band.get_ref();
+        let kind = FilterKind::from_name(band.kind.get_ref()).ok_or_else(|| {
+            fail(
+                band.kind.span(),
+                format!(
+                    "unknown band type \"{}\", expected one of {}",
+                    band.kind.get_ref(),
+                    FilterKind::NAMES.join(", ")
+                ),
+            )
+        })?;
+        let gain_db = match &band.gain {
+            Some(gain) if !kind.has_gain() => {
+                return Err(fail(
+                    gain.span(),
+                    format!("{} bands have no gain", band.kind.get_ref()),
+                ))
+            }
+            Some(gain) => float_in(gain, "gain", -MAX_GAIN_DB, MAX_GAIN_DB)?,
+            None => 0.0,
+        };
+        let q = match &band.q {
+            Some(q) => float_in(q, "q", MIN_Q, MAX_Q)?,
+            // A single peak, or the flattest shelf and pass curves
+            None if kind == FilterKind::Peaking => 1.0,
+            None => std::f32::consts::FRAC_1_SQRT_2,
+        };
+        parametric.push(Band {
+            kind,
+            frequency: float_in(&band.frequency, "frequency", MIN_FREQUENCY, MAX_FREQUENCY)?,
+            gain_db,
+            q,
+        });
+    }
+
+    let settings = EqSettings {
+        enabled: raw
+            .enabled
+            .as_ref()
+            .is_none_or(|enabled| *enabled.get_ref()),
+        graphic,
+        parametric,
+    };
+    Ok((settings, preset))
+}
+
+fn parse_keys(
+    raw: &BTreeMap<String, Spanned<String>>,
+    fail: &dyn Fn(std::ops::Range<usize>, String) -> String,
+) -> Result<KeyBindings, String> {
+    let mut bound: HashMap<&str, i32> = DEFAULT_KEYS
+        .iter()
+        .map(|&(action, key)| (action, key as i32))
+        .collect();
+
+    for (action, name) in raw {
+        let Some(&(action, _)) = DEFAULT_KEYS.iter().find(|(a, _)| a == action) else {
+            let actions: Vec<&str> = DEFAULT_KEYS.iter().map(|(a, _)| *a).collect();
+            return Err(fail(
+                name.span(),
+                format!(
+                    "unknown action \"{}\" in [keys], expected one of {}",
+                    action,
+                    actions.join(", ")
+                ),
+            ));
+        };
+        let code = key_code(name.get_ref()).ok_or_else(|| {
+            fail(
+                name.span(),
+                format!(
+                    "unknown key \"{}\" for {}, use a letter, space, enter, tab, backspace, \
+                     an arrow (up, down, left, right), f1-f12 or punctuation",
+                    name.get_ref(),
+                    action
+                ),
+            )
+        })?;
+        bound.insert(action, code);
+    }
+
+    // Two actions on one key would both fire, report the first clash in file order
+    for (action, name) in raw {
+        let code = bound[action.as_str()];
+        if let Some(reason) = reserved_key(code) {
+            return Err(fail(
+                name.span(),
+                format!(
+                    "key \"{}\" of {} is reserved for {}",
+                    name.get_ref(),
+                    action,
+                    reason
+                ),
+            ));
+        }
+        if let Some(&(other, _)) = DEFAULT_KEYS
+            .iter()
+            .find(|(a, _)| *a != action && bound[a] == code)
+        {
+            return Err(fail(
+                name.span(),
+                format!(
+                    "key \"{}\" of {} is already bound to {}",
+                    name.get_ref(),
+                    action,
+                    other
+                ),
+            ));
+        }
+    }
+
+    let key = |action: &str| bound[action];
+    Ok(KeyBindings {
+        add_file: key("add_file"),
+        pause: key("pause"),
+        next_track: key("next_track"),
+        previous_track: key("previous_track"),
+        shuffle: key("shuffle"),
+        repeat: key("repeat"),
+        export_queue: key("export_queue"),
+        quit: key("quit"),
+        mute: key("mute"),
+        volume_up: key("volume_up"),
+        volume_down: key("volume_down"),
+        seek_back: key("seek_back"),
+        seek_forward: key("seek_forward"),
+        next_mode: key("next_mode"),
+        previous_mode: key("previous_mode"),
+        scope_view: key("scope_view"),
+        window: key("window"),
+        db_scale: key("db_scale"),
+        tilt: key("tilt"),
+        aggregation: key("aggregation"),
+        downmix: key("downmix"),
+        stereo: key("stereo"),
+        theme: key("theme"),
+        gradient: key("gradient"),
+        colormap: key("colormap"),
+        screenshot: key("screenshot"),
+        record: key("record"),
+        capture_hud: key("capture_hud"),
+        transition: key("transition"),
+        loudness: key("loudness"),
+        equalizer: key("equalizer"),
+        eq_preset: key("eq_preset"),
+        eq_bypass: key("eq_bypass"),
+        tempo_down: key("tempo_down"),
+        tempo_up: key("tempo_up"),
+        pitch_down: key("pitch_down"),
+        pitch_up: key("pitch_up"),
+        varispeed_down: key("varispeed_down"),
+        varispeed_up: key("varispeed_up"),
+        speed_reset: key("speed_reset"),
+        loop_start: key("loop_start"),
+        loop_end: key("loop_end"),
+        loop_clear: key("loop_clear"),
+    })
+}
+
+// Keys main.rs reads directly, so no action can be bound to them
+fn reserved_key(code: i32) -> Option<&'static str> {
+    if (KEY_ZERO as i32..=KEY_NINE as i32).contains(&code) {
+        Some("jumping to a tenth of the track")
+    } else if code == KEY_LEFT_SHIFT as i32 || code == KEY_RIGHT_SHIFT as i32 {
+        Some("longer seeks")
+    } else {
+        None
+    }
+}
+
+// raylib key code for a key name as written in the config, case-insensitive
+fn key_code(name: &str) -> Option<i32> {
+    let name = name.trim().to_lowercase();
+    let bytes = name.as_bytes();
+    if bytes.len() == 1 && bytes[0].is_ascii_lowercase() {
+        return Some(KEY_A as i32 + (bytes[0] - b'a') as i32);
+    }
+    // Only known so they can be reported as reserved
+    if bytes.len() == 1 && bytes[0].is_ascii_digit() {
+        return Some(KEY_ZERO as i32 + (bytes[0] - b'0') as i32);
+    }
+    if let Some(n) = name.strip_prefix('f').and_then(|n| n.parse::<i32>().ok()) {
+        return (1..=12).contains(&n).then_some(KEY_F1 as i32 + n - 1);
+    }
+    let key = match name.as_str() {
+        "space" => KEY_SPACE,
+        "enter" => KEY_ENTER,
+        "tab" => KEY_TAB,
+        "backspace" => KEY_BACKSPACE,
+        "up" => KEY_UP,
+        "down" => KEY_DOWN,
+        "left" => KEY_LEFT,
+        "right" => KEY_RIGHT,
+        "home" => KEY_HOME,
+        "end" => KEY_END,
+        "page_up" => KEY_PAGE_UP,
+        "page_down" => KEY_PAGE_DOWN,
+        "left_shift" => KEY_LEFT_SHIFT,
+        "right_shift" => KEY_RIGHT_SHIFT,
+        "," => KEY_COMMA,
+        "." => KEY_PERIOD,
+        "/" => KEY_SLASH,
+        ";" => KEY_SEMICOLON,
+        "'" => KEY_APOSTROPHE,
+        "-" => KEY_MINUS,
+        "=" => KEY_EQUAL,
+        "[" => KEY_LEFT_BRACKET,
+        "]" => KEY_RIGHT_BRACKET,
+        "\\" => KEY_BACKSLASH,
+        "`" => KEY_GRAVE,
+        _ => return None,
+    };
+    Some(key as i32)
+}
+
+// 1-based "line:column" of a byte offset
+pub fn location(source: &str, offset: usize) -> String {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+    format!("{}:{}", line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<Config, String> {
+        Config::parse(source, Vec::new())
+    }
+
+    // Asserts `source` is rejected with an error at `at` mentioning `message`
+    fn assert_error(source: &str, at: &str, message: &str) {
+        match parse(source) {
+            Ok(_) => panic!("accepted {:?}", source),
+            Err(e) => assert!(
+                e.starts_with(&format!("{}: ", at)) && e.contains(message),
+                "{:?} gave {:?}",
+                source,
+                e
+            ),
+        }
+    }
+
+    #[test]
+    fn empty_file_gives_defaults() {
+        let config = parse("").unwrap();
+        assert_eq!((config.width, config.height, config.fps), (1280, 720, 60));
+        assert_eq!(config.fft_size, 1 << 13);
+        assert_eq!(config.spectrogram_history, 10.0);
+        assert_eq!(config.crossfade, 4.0);
+        assert_eq!(config.keys.pause, KEY_SPACE as i32);
+    }
+
+    #[test]
+    fn keys_can_be_rebound_and_swapped() {
+        let config = parse("[keys]\npause = \"p\"\nprevious_track = \"Space\"\n").unwrap();
+        assert_eq!(config.keys.pause, KEY_P as i32);
+        assert_eq!(config.keys.previous_track, KEY_SPACE as i32);
+        assert_eq!(config.keys.screenshot, KEY_F12 as i32);
+    }
+
+    #[test]
+    fn bad_key_names_are_rejected() {
+        for name in ["", "ctrl", "f13", "f0", "ab", "\u{e9}"] {
+            let source = format!("[keys]\npause = \"{}\"\n", name);
+            assert_error(&source, "2:9", "unknown key");
+        }
+        assert_error("[keys]\nplay = \"p\"\n", "2:8", "unknown action \"play\"");
+    }
+
+    #[test]
+    fn key_clashes_are_rejected() {
+        // With another action's default key
+        assert_error(
+            "[keys]\npause = \"n\"\n",
+            "2:9",
+            "already bound to next_track",
+        );
+        // Between two rebound actions, reported at the first in file order
+        assert_error(
+            "[keys]\nquit = \"k\"\nmute = \"f9\"\nnext_track = \"f9\"\ncolormap = \"y\"\n",
+            "3:8",
+            "of mute is already bound to next_track",
+        );
+    }
+
+    #[test]
+    fn reserved_keys_are_rejected() {
+        for name in ["0", "5", "9", "left_shift", "Right_Shift"] {
+            let source = format!("[keys]\nmute = \"{}\"\n", name);
+            assert_error(&source, "2:8", "is reserved for");
+        }
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        let cases = [
+            (
+                "[analysis]\nfft_size = 512\n",
+                "2:12",
+                "between 1024 and 65536",
+            ),
+            (
+                "[analysis]\nfft_size = 131072\n",
+                "2:12",
+                "between 1024 and 65536",
+            ),
+            (
+                "[analysis]\nfft_size = 3000\n",
+                "2:12",
+                "power of two, e.g. 4096",
+            ),
+            (
+                "[spectrogram]\nhistory = 0.5\n",
+                "2:11",
+                "spectrogram.history",
+            ),
+            (
+                "[spectrogram]\nhistory = 121.0\n",
+                "2:11",
+                "spectrogram.history",
+            ),
+            (
+                "[playback]\ncrossfade = 0.1\n",
+                "2:13",
+                "playback.crossfade",
+            ),
+            (
+                "[playback]\ncrossfade = 20.0\n",
+                "2:13",
+                "playback.crossfade",
+            ),
+            ("[window]\nwidth = 100\n", "2:9", "window.width"),
+            ("[audio]\nvolume = 1.5\n", "2:10", "audio.volume"),
+        ];
+        for (source, at, message) in cases {
+            assert_error(source, at, message);
+        }
+        assert_eq!(
+            parse("[analysis]\nfft_size = 1024\n").unwrap().fft_size,
+            1024
+        );
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert_error("colour = \"red\"\n", "1:1", "unknown field `colour`");
+        assert_error(
+            "\n[window]\nfullscreen = true\n",
+            "3:1",
+            "unknown field `fullscreen`",
+        );
+        assert_error("[playback]\nfade = 2.0\n", "2:1", "unknown field `fade`");
+    }
+
+    #[test]
+    fn colors_accept_short_role_names() {
+        let config = parse("[colors]\nbg = \"#102030\"\nfg = \"#ffffff\"\n").unwrap();
+        let theme = config.themes[config.startup_theme].1;
+        assert_eq!((theme.background.r, theme.background.g), (0x10, 0x20));
+        assert_eq!(theme.foreground.b, 0xff);
+    }
+
+    #[test]
+    fn errors_point_at_the_value() {
+        assert_error(
+            "# comment\n[window]\nwidth = 1280\nmode = \"Nope\"\n",
+            "4:8",
+            "unknown window.mode \"Nope\"",
+        );
+        assert_error("[window]\nwidth = \"wide\"\n", "2:9", "invalid type");
+    }
+}
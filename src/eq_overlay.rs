@@ -0,0 +1,305 @@
+use crate::currentTheme;
+use crate::equalizer::{
+    log_frequency, log_position, Band, EqSettings, GRAPHIC_FREQUENCIES, MAX_GAIN_DB, MAX_Q, MIN_Q,
+};
+use crate::playback::ENGINE_SAMPLE_RATE;
+use raylib::consts::MouseButton::*;
+use raylib::ffi::{
+    Color, ColorAlpha, DrawCircleLines, DrawCircleV, DrawLineEx, DrawRectangleLines,
+    DrawRectangleRec, DrawTextEx, Font, GetMousePosition, GetMouseWheelMove, IsMouseButtonDown,
+    IsMouseButtonPressed, Rectangle, Vector2,
+};
+use std::ffi::CString;
+
+// Gain the overlay spans above and below 0 dB, a little more than a band can be set to
+const RANGE_DB: f32 = 15.0;
+// How close the mouse has to be to pick up a handle, in pixels
+const GRAB_RADIUS: f32 = 12.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Handle {
+    Graphic(usize),
+    Parametric(usize),
+}
+
+// The EQ curve with a handle for every band. Graphic bands are dragged up and down,
+// parametric ones anywhere, and the wheel over a parametric band changes its width.
+#[derive(Default)]
+pub struct EqOverlay {
+    pub visible: bool,
+    dragging: Option<Handle>,
+}
+
+// The part of the screen the curve is drawn in, clear of the HUD at the top and bottom
+fn PlotArea(screenWidth: i32, screenHeight: i32) -> Rectangle {
+    Rectangle {
+        x: 60.0,
+        y: 190.0,
+        width: (screenWidth - 120) as f32,
+        height: (screenHeight - 310) as f32,
+    }
+}
+
+fn PlotPoint(area: Rectangle, frequency: f32, gain_db: f32) -> Vector2 {
+    Vector2 {
+        x: area.x + area.width * log_position(frequency).clamp(0.0, 1.0),
+        y: area.y + area.height / 2.0 - gain_db / RANGE_DB * area.height / 2.0,
+    }
+}
+
+// Pass filters sit on the 0 dB line, they have no gain to show
+fn HandleGain(band: &Band) -> f32 {
+    if band.kind.has_gain() {
+        band.gain_db
+    } else {
+        0.0
+    }
+}
+
+fn Bands(settings: &EqSettings) -> Vec<(Handle, Band)> {
+    (0..GRAPHIC_FREQUENCIES.len())
+        .map(|i| (Handle::Graphic(i), settings.graphic_band(i)))
+        .chain(
+            settings
+                .parametric
+                .iter()
+                .enumerate()
+                .map(|(i, band)| (Handle::Parametric(i), *band)),
+        )
+        .collect()
+}
+
+fn DrawLabel(font: Font, text: &str, x: f32, y: f32, color: Color) {
+    unsafe {
+        let c_text = CString::new(text).unwrap_or_default();
+        DrawTextEx(font, c_text.as_ptr(), Vector2 { x, y }, 20.0, 1.0, color);
+    }
+}
+
+// The handle closest to `mouse` within reach, parametric bands first since they can sit
+// on top of graphic ones
+fn HandleAt(settings: &EqSettings, area: Rectangle, mouse: Vector2) -> Option<Handle> {
+    let mut closest: Option<(Handle, f32)> = None;
+    for (handle, band) in Bands(settings).into_iter().rev() {
+        let point = PlotPoint(area, band.frequency, HandleGain(&band));
+        let distance = ((point.x - mouse.x).powi(2) + (point.y - mouse.y).powi(2)).sqrt();
+        if distance <= GRAB_RADIUS && closest.is_none_or(|(_, best)| distance < best) {
+            closest = Some((handle, distance));
+        }
+    }
+    closest.map(|(handle, _)| handle)
+}
+
+impl EqOverlay {
+    // Applies this frame's mouse input, returns whether the settings changed
+    pub fn update(
+        &mut self,
+        settings: &mut EqSettings,
+        screenWidth: i32,
+        screenHeight: i32,
+    ) -> bool {
+        if !self.visible {
+            self.dragging = None;
+            return false;
+        }
+        let area = PlotArea(screenWidth, screenHeight);
+        let before = settings.clone();
+        unsafe {
+            let mouse = GetMousePosition();
+            let hovered = HandleAt(settings, area, mouse);
+            if IsMouseButtonPressed(MOUSE_BUTTON_LEFT as i32) {
+                self.dragging = hovered;
+            }
+            if !IsMouseButtonDown(MOUSE_BUTTON_LEFT as i32) {
+                self.dragging = None;
+            }
+
+            if let Some(handle) = self.dragging {
+                let offset = (area.y + area.height / 2.0 - mouse.y) / (area.height / 2.0);
+                // Half-dB steps are fine enough and land exactly on 0 dB
+                let gain =
+                    ((offset * RANGE_DB).clamp(-MAX_GAIN_DB, MAX_GAIN_DB) * 2.0).round() / 2.0;
+                match handle {
+                    Handle::Graphic(i) => settings.graphic[i] = gain,
+                    Handle::Parametric(i) => {
+                        let band = &mut settings.parametric[i];
+                        let position = ((mouse.x - area.x) / area.width).clamp(0.0, 1.0);
+                        band.frequency = log_frequency(position).round();
+                        if band.kind.has_gain() {
+                            band.gain_db = gain;
+                        }
+                    }
+                }
+            }
+
+            let wheel = GetMouseWheelMove();
+            if wheel != 0.0 {
+                if let Some(Handle::Parametric(i)) = self.dragging.or(hovered) {
+                    let band = &mut settings.parametric[i];
+                    band.q = (band.q * 1.2f32.powf(wheel)).clamp(MIN_Q, MAX_Q);
+                }
+            }
+
+            // Right click puts a band back on 0 dB
+            if IsMouseButtonPressed(MOUSE_BUTTON_RIGHT as i32) {
+                match hovered {
+                    Some(Handle::Graphic(i)) => settings.graphic[i] = 0.0,
+                    Some(Handle::Parametric(i)) => settings.parametric[i].gain_db = 0.0,
+                    None => {}
+                }
+            }
+        }
+        *settings != before
+    }
+
+    pub fn draw(
+        &self,
+        font: Font,
+        settings: &EqSettings,
+        preset: &str,
+        screenWidth: i32,
+        screenHeight: i32,
+    ) {
+        if !self.visible {
+            return;
+        }
+        let area = PlotArea(screenWidth, screenHeight);
+        unsafe {
+            DrawRectangleRec(
+                Rectangle {
+                    x: area.x - 40.0,
+                    y: area.y - 40.0,
+                    width: area.width + 60.0,
+                    height: area.height + 70.0,
+                },
+                ColorAlpha(currentTheme.background, 0.85),
+            );
+            DrawRectangleLines(
+                area.x as i32,
+                area.y as i32,
+                area.width as i32,
+                area.height as i32,
+                ColorAlpha(currentTheme.foreground, 0.3),
+            );
+
+            let title = format!(
+                "Equalizer: {}{}",
+                preset,
+                if settings.enabled { "" } else { " (bypassed)" }
+            );
+            DrawLabel(font, &title, area.x, area.y - 30.0, currentTheme.yellow);
+
+            // Grid, every 6 dB and at each decade
+            for db in [-12.0, -6.0, 0.0, 6.0, 12.0] {
+                let y = PlotPoint(area, 1000.0, db).y;
+                DrawLineEx(
+                    Vector2 { x: area.x, y },
+                    Vector2 {
+                        x: area.x + area.width,
+                        y,
+                    },
+                    1.0,
+                    ColorAlpha(currentTheme.foreground, if db == 0.0 { 0.4 } else { 0.12 }),
+                );
+                DrawLabel(
+                    font,
+                    &format!("{:+}", db),
+                    area.x - 36.0,
+                    y - 10.0,
+                    ColorAlpha(currentTheme.foreground, 0.6),
+                );
+            }
+            for (frequency, label) in [(100.0, "100"), (1000.0, "1k"), (10000.0, "10k")] {
+                let x = PlotPoint(area, frequency, 0.0).x;
+                DrawLineEx(
+                    Vector2 { x, y: area.y },
+                    Vector2 {
+                        x,
+                        y: area.y + area.height,
+                    },
+                    1.0,
+                    ColorAlpha(currentTheme.foreground, 0.12),
+                );
+                DrawLabel(
+                    font,
+                    label,
+                    x + 4.0,
+                    area.y + area.height + 4.0,
+                    ColorAlpha(currentTheme.foreground, 0.6),
+                );
+            }
+
+            // The combined response of every band
+            let steps = (area.width / 4.0) as usize;
+            let frequencies: Vec<f32> = (0..=steps)
+                .map(|i| log_frequency(i as f32 / steps as f32))
+                .collect();
+            let response = settings.response_db(ENGINE_SAMPLE_RATE, &frequencies);
+            let curveColor = if settings.enabled {
+                currentTheme.yellow
+            } else {
+                ColorAlpha(currentTheme.foreground, 0.4)
+            };
+            for i in 1..frequencies.len() {
+                let clamp = |db: f32| db.clamp(-RANGE_DB, RANGE_DB);
+                DrawLineEx(
+                    PlotPoint(area, frequencies[i - 1], clamp(response[i - 1])),
+                    PlotPoint(area, frequencies[i], clamp(response[i])),
+                    2.0,
+                    curveColor,
+                );
+            }
+
+            let mouse = GetMousePosition();
+            let active = self.dragging.or_else(|| HandleAt(settings, area, mouse));
+            for (handle, band) in Bands(settings) {
+                let point = PlotPoint(area, band.frequency, HandleGain(&band));
+                let color = match handle {
+                    Handle::Graphic(_) => currentTheme.aqua,
+                    Handle::Parametric(_) => currentTheme.orange,
+                };
+                DrawCircleV(point, 6.0, color);
+                if active == Some(handle) {
+                    DrawCircleLines(point.x as i32, point.y as i32, 10.0, color);
+                }
+            }
+
+            // Details of the band under the mouse, or a hint on how to use the overlay
+            let detail = match active {
+                Some(Handle::Graphic(i)) => {
+                    format!(
+                        "{} Hz  {:+.1} dB",
+                        GRAPHIC_FREQUENCIES[i], settings.graphic[i]
+                    )
+                }
+                Some(Handle::Parametric(i)) => {
+                    let band = settings.parametric[i];
+                    if band.kind.has_gain() {
+                        format!(
+                            "{}  {} Hz  {:+.1} dB  Q {:.2}",
+                            band.kind.name(),
+                            band.frequency,
+                            band.gain_db,
+                            band.q
+                        )
+                    } else {
+                        format!(
+                            "{}  {} Hz  Q {:.2}",
+                            band.kind.name(),
+                            band.frequency,
+                            band.q
+                        )
+                    }
+                }
+                None => "Drag a band, wheel for width, right click for 0 dB".to_string(),
+            };
+            DrawLabel(
+                font,
+                &detail,
+                area.x + area.width / 2.0,
+                area.y - 30.0,
+                currentTheme.foreground,
+            );
+        }
+    }
+}
@@ -0,0 +1,376 @@
+use crate::analysis::{snapshot_channel, SnapshotReader, SnapshotWriter};
+use crate::biquad::Biquad;
+use crate::loudness::db_to_linear;
+
+// Centers of the graphic EQ's bands, an octave apart
+pub const GRAPHIC_FREQUENCIES: [f32; 10] = [
+    31.0, 63.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+// About one octave wide, so neighbouring bands blend into a smooth curve
+const GRAPHIC_Q: f32 = 1.41;
+
+// Limits for every band's settings, also what the config accepts
+pub const MAX_GAIN_DB: f32 = 12.0;
+pub const MIN_FREQUENCY: f32 = 20.0;
+pub const MAX_FREQUENCY: f32 = 20000.0;
+pub const MIN_Q: f32 = 0.1;
+pub const MAX_Q: f32 = 10.0;
+pub const MAX_PARAMETRIC_BANDS: usize = 8;
+
+// Graphic EQ curves in GRAPHIC_FREQUENCIES order
+pub const PRESETS: [(&str, [f32; 10]); 7] = [
+    ("Flat", [0.0; 10]),
+    (
+        "Bass Boost",
+        [6.0, 5.0, 4.0, 2.0, 0.5, 0.0, 0.0, 0.0, 0.0, 0.0],
+    ),
+    (
+        "Bass Cut",
+        [-8.0, -6.0, -3.0, -1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    ),
+    (
+        "Treble Boost",
+        [0.0, 0.0, 0.0, 0.0, 0.0, 0.5, 2.0, 4.0, 5.0, 6.0],
+    ),
+    (
+        "Loudness",
+        [5.0, 4.0, 2.0, 0.0, -1.0, -1.0, 0.0, 1.5, 3.0, 4.0],
+    ),
+    (
+        "Vocal",
+        [-3.0, -2.0, -1.0, 1.0, 3.0, 3.5, 3.0, 1.5, 0.0, -1.0],
+    ),
+    (
+        "Rock",
+        [4.0, 3.0, 1.5, -0.5, -1.5, -0.5, 1.5, 3.0, 3.5, 4.0],
+    ),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FilterKind {
+    Peaking,
+    LowShelf,
+    HighShelf,
+    LowPass,
+    HighPass,
+}
+
+impl FilterKind {
+    // Names used in the config file
+    pub const NAMES: [&'static str; 5] = [
+        "peaking",
+        "low_shelf",
+        "high_shelf",
+        "low_pass",
+        "high_pass",
+    ];
+
+    pub fn from_name(name: &str) -> Option<FilterKind> {
+        match name.to_ascii_lowercase().as_str() {
+            "peaking" => Some(FilterKind::Peaking),
+            "low_shelf" => Some(FilterKind::LowShelf),
+            "high_shelf" => Some(FilterKind::HighShelf),
+            "low_pass" => Some(FilterKind::LowPass),
+            "high_pass" => Some(FilterKind::HighPass),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterKind::Peaking => "Peaking",
+            FilterKind::LowShelf => "Low Shelf",
+            FilterKind::HighShelf => "High Shelf",
+            FilterKind::LowPass => "Low Pass",
+            FilterKind::HighPass => "High Pass",
+        }
+    }
+
+    // Pass filters only have a cutoff and a resonance
+    pub fn has_gain(self) -> bool {
+        !matches!(self, FilterKind::LowPass | FilterKind::HighPass)
+    }
+}
+
+// One band of the parametric EQ
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Band {
+    pub kind: FilterKind,
+    pub frequency: f32,
+    pub gain_db: f32,
+    pub q: f32,
+}
+
+impl Band {
+    fn design(&self, sample_rate: u32) -> Biquad {
+        match self.kind {
+            FilterKind::Peaking => {
+                Biquad::peaking(sample_rate, self.frequency, self.q, self.gain_db)
+            }
+            FilterKind::LowShelf => {
+                Biquad::low_shelf(sample_rate, self.frequency, self.q, self.gain_db)
+            }
+            FilterKind::HighShelf => {
+                Biquad::high_shelf(sample_rate, self.frequency, self.q, self.gain_db)
+            }
+            FilterKind::LowPass => Biquad::low_pass(sample_rate, self.frequency, self.q),
+            FilterKind::HighPass => Biquad::high_pass(sample_rate, self.frequency, self.q),
+        }
+    }
+}
+
+// The graphic bands followed by the parametric ones, all in series
+#[derive(Clone, Debug, PartialEq)]
+pub struct EqSettings {
+    pub enabled: bool,
+    // Gain of each GRAPHIC_FREQUENCIES band in dB
+    pub graphic: [f32; 10],
+    pub parametric: Vec<Band>,
+}
+
+impl Default for EqSettings {
+    fn default() -> EqSettings {
+        EqSettings {
+            enabled: true,
+            graphic: [0.0; 10],
+            parametric: Vec::new(),
+        }
+    }
+}
+
+impl EqSettings {
+    pub fn graphic_band(&self, index: usize) -> Band {
+        Band {
+            kind: FilterKind::Peaking,
+            frequency: GRAPHIC_FREQUENCIES[index],
+            gain_db: self.graphic[index],
+            q: GRAPHIC_Q,
+        }
+    }
+
+    // Whether the audio would come out unchanged, then nothing runs at all
+    pub fn is_flat(&self) -> bool {
+        !self.enabled
+            || (self.graphic.iter().all(|&gain| gain == 0.0)
+                && self
+                    .parametric
+                    .iter()
+                    .all(|band| band.kind.has_gain() && band.gain_db == 0.0))
+    }
+
+    fn sections(&self, sample_rate: u32) -> Vec<Biquad> {
+        if self.is_flat() {
+            return Vec::new();
+        }
+        (0..self.graphic.len())
+            .map(|i| self.graphic_band(i))
+            .chain(self.parametric.iter().copied())
+            .map(|band| band.design(sample_rate))
+            .collect()
+    }
+
+    // Gain in dB the whole curve applies at each of `frequencies`, before the headroom
+    pub fn response_db(&self, sample_rate: u32, frequencies: &[f32]) -> Vec<f32> {
+        let sections = self.sections(sample_rate);
+        frequencies
+            .iter()
+            .map(|&frequency| {
+                sections
+                    .iter()
+                    .map(|section| section.response_db(sample_rate, frequency))
+                    .sum()
+            })
+            .collect()
+    }
+
+    // Slow enough to keep out of the audio thread, see EqControl::set
+    pub fn design(&self, sample_rate: u32) -> EqDesign {
+        EqDesign {
+            sections: self.sections(sample_rate),
+            gain: db_to_linear(self.headroom_db(sample_rate)),
+        }
+    }
+
+    // Lowers the input by the curve's largest boost, so boosting a band never clips
+    fn headroom_db(&self, sample_rate: u32) -> f32 {
+        let steps = 200;
+        let frequencies: Vec<f32> = (0..=steps)
+            .map(|i| log_frequency(i as f32 / steps as f32))
+            .collect();
+        let highest = self
+            .response_db(sample_rate, &frequencies)
+            .into_iter()
+            .fold(0.0f32, f32::max);
+        -highest
+    }
+}
+
+// Frequency `position` (0 to 1) of the way along a log axis from MIN_FREQUENCY to MAX_FREQUENCY
+pub fn log_frequency(position: f32) -> f32 {
+    MIN_FREQUENCY * (MAX_FREQUENCY / MIN_FREQUENCY).powf(position)
+}
+
+// Where `frequency` sits on that axis
+pub fn log_position(frequency: f32) -> f32 {
+    (frequency / MIN_FREQUENCY).ln() / (MAX_FREQUENCY / MIN_FREQUENCY).ln()
+}
+
+// Filters designed for one sample rate, ready to hand to the stream processor
+#[derive(Clone, Debug)]
+pub struct EqDesign {
+    sections: Vec<Biquad>,
+    gain: f32,
+}
+
+impl EqDesign {
+    fn flat() -> EqDesign {
+        EqDesign {
+            sections: Vec::new(),
+            gain: 1.0,
+        }
+    }
+}
+
+// The render loop's end of an Equalizer, handing it new designs
+pub struct EqControl {
+    designs: SnapshotWriter<EqDesign>,
+}
+
+impl EqControl {
+    // Never waits for the audio thread. The design this replaces is freed here, so the audio
+    // thread doesn't free memory either.
+    pub fn set(&mut self, design: EqDesign) {
+        *self.designs.slot() = design;
+        self.designs.publish();
+    }
+}
+
+// The filters the stream processor runs. Designs arrive through a snapshot channel, the
+// filters themselves only change between two buffers.
+pub struct Equalizer {
+    // Left and right section for each band
+    sections: Vec<[Biquad; 2]>,
+    gain: f32,
+    designs: SnapshotReader<EqDesign>,
+}
+
+impl Equalizer {
+    // The equalizer for the stream processor and the control for the render loop
+    pub fn new() -> (Equalizer, EqControl) {
+        let (writer, reader) = snapshot_channel(EqDesign::flat());
+        let equalizer = Equalizer {
+            // Room for every band there can be, so the audio thread never allocates
+            sections: Vec::with_capacity(GRAPHIC_FREQUENCIES.len() + MAX_PARAMETRIC_BANDS),
+            gain: 1.0,
+            designs: reader,
+        };
+        (equalizer, EqControl { designs: writer })
+    }
+
+    // Filters interleaved stereo in place
+    pub fn process(&mut self, samples: &mut [f32]) {
+        if let Some(design) = self.designs.read_fresh() {
+            // Bands that stay keep their history, so changes don't click
+            self.sections.truncate(design.sections.len());
+            for (i, section) in design.sections.iter().enumerate() {
+                match self.sections.get_mut(i) {
+                    Some([left, right]) => {
+                        left.retune(section);
+                        right.retune(section);
+                    }
+                    None => self.sections.push([*section, *section]),
+                }
+            }
+            self.gain = design.gain;
+        }
+        if self.sections.is_empty() {
+            return;
+        }
+        for frame in samples.chunks_exact_mut(2) {
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let mut value = (*sample * self.gain) as f64;
+                for section in self.sections.iter_mut() {
+                    value = section[channel].process(value);
+                }
+                *sample = value as f32;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn band(kind: FilterKind, gain_db: f32) -> Band {
+        Band {
+            kind,
+            frequency: 1000.0,
+            gain_db,
+            q: 1.0,
+        }
+    }
+
+    #[test]
+    fn flat_settings_are_flat() {
+        let mut settings = EqSettings::default();
+        assert!(settings.is_flat());
+        settings.parametric.push(band(FilterKind::Peaking, 0.0));
+        settings.parametric.push(band(FilterKind::HighShelf, 0.0));
+        assert!(settings.is_flat());
+
+        let mut boosted = EqSettings::default();
+        boosted.graphic[3] = 2.0;
+        assert!(!boosted.is_flat());
+        // Bypassed, any curve is flat
+        boosted.enabled = false;
+        assert!(boosted.is_flat());
+
+        // A pass filter always changes the sound, it has no gain to be 0
+        let mut filtered = EqSettings::default();
+        filtered.parametric.push(band(FilterKind::LowPass, 0.0));
+        assert!(!filtered.is_flat());
+    }
+
+    #[test]
+    fn headroom_matches_the_largest_boost() {
+        assert_eq!(EqSettings::default().headroom_db(48000), 0.0);
+
+        let mut boosted = EqSettings::default();
+        boosted.parametric.push(band(FilterKind::Peaking, 6.0));
+        let headroom = boosted.headroom_db(48000);
+        assert!((headroom + 6.0).abs() < 0.1, "{}", headroom);
+
+        // Cuts never need headroom
+        let cut = EqSettings {
+            graphic: [-6.0; 10],
+            ..EqSettings::default()
+        };
+        assert_eq!(cut.headroom_db(48000), 0.0);
+    }
+
+    #[test]
+    fn equalizer_takes_over_the_newest_design() {
+        let (mut equalizer, mut control) = Equalizer::new();
+        let mut samples = [0.5f32; 64];
+        equalizer.process(&mut samples);
+        assert!(samples.iter().all(|&s| s == 0.5));
+
+        let quieter = EqSettings {
+            graphic: [-6.0; 10],
+            ..EqSettings::default()
+        };
+        control.set(EqSettings::default().design(48000));
+        control.set(quieter.design(48000));
+        equalizer.process(&mut samples);
+        assert_eq!(equalizer.sections.len(), 10);
+        assert!(samples.iter().all(|&s| s != 0.5));
+
+        // Back to flat, nothing runs at all
+        control.set(EqSettings::default().design(48000));
+        let mut samples = [0.5f32; 64];
+        equalizer.process(&mut samples);
+        assert!(equalizer.sections.is_empty());
+        assert!(samples.iter().all(|&s| s == 0.5));
+    }
+}
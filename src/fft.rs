@@ -1,50 +1,258 @@
 use num_complex::Complex32;
+#[cfg(test)]
 use std::f32::consts::PI;
 
+// Naive O(n^2) transform, kept as the reference the planner is tested against.
+// Uses the same forward sign convention as Fft (e^{-2 pi i f t}).
+#[cfg(test)]
 fn dft(input: &[f32], output: &mut [Complex32], n: usize) {
-    for f in 0..n {
-        output[f] = Complex32::new(0.0, 0.0);
-        for i in 0..n {
+    for (f, out) in output.iter_mut().enumerate().take(n) {
+        *out = Complex32::new(0.0, 0.0);
+        for (i, &sample) in input.iter().enumerate().take(n) {
             let t = i as f32 / n as f32;
-            let exp = Complex32::new(0.0, 2.0 * PI * f as f32 * t).exp();
-            output[f] += input[i] * exp;
+            let exp = Complex32::new(0.0, -2.0 * PI * f as f32 * t).exp();
+            *out += sample * exp;
         }
     }
 }
 
-fn fft(input: &[f32], stride: usize, output: &mut [Complex32], n: usize) {
-    assert!(n > 0);
+// Iterative in-place radix-2 FFT for a fixed power-of-two size.
+//
+// Twiddles and the bit-reversal permutation are computed once in `new`, so `process` and
+// `process_real` never allocate and are safe to call from the audio thread. The planner is
+// immutable after construction and can be shared between threads.
+pub struct Fft {
+    n: usize,
+    // e^{-2 pi i k / n} for k in 0..n/2
+    twiddles: Vec<Complex32>,
+    // Bit-reversed index of every i in 0..n, over log2(n) bits
+    bitrev: Vec<usize>,
+}
+
+impl Fft {
+    pub fn new(n: usize) -> Fft {
+        assert!(n.is_power_of_two(), "FFT size must be a power of two");
+
+        // Computed in f64 so large sizes don't accumulate rounding error in the table
+        let twiddles = (0..n / 2)
+            .map(|k| {
+                let phase = -2.0 * std::f64::consts::PI * k as f64 / n as f64;
+                Complex32::new(phase.cos() as f32, phase.sin() as f32)
+            })
+            .collect();
+
+        let bits = n.trailing_zeros();
+        let bitrev = (0..n)
+            .map(|i| {
+                if bits == 0 {
+                    0
+                } else {
+                    i.reverse_bits() >> (usize::BITS - bits)
+                }
+            })
+            .collect();
+
+        Fft {
+            n,
+            twiddles,
+            bitrev,
+        }
+    }
+
+    // Complex forward transform of `buf` in place, `buf.len()` must equal the planned size
+    pub fn process(&self, buf: &mut [Complex32]) {
+        assert_eq!(buf.len(), self.n);
+        self.transform(buf, 0);
+    }
+
+    // Forward transform of a real signal. `output` receives the full n-point spectrum, the
+    // upper half being filled from conjugate symmetry.
+    //
+    // Uses the usual packing trick: the even samples go in the real part and the odd samples
+    // in the imaginary part of an n/2 point complex FFT, which is then split back into the
+    // spectrum of the real input. `output` doubles as the scratch space.
+    pub fn process_real(&self, input: &[f32], output: &mut [Complex32]) {
+        assert_eq!(input.len(), self.n);
+        assert_eq!(output.len(), self.n);
+
+        if self.n == 1 {
+            output[0] = Complex32::new(input[0], 0.0);
+            return;
+        }
+
+        let m = self.n / 2;
+        for k in 0..m {
+            output[k] = Complex32::new(input[2 * k], input[2 * k + 1]);
+        }
+        self.transform(&mut output[..m], 1);
+
+        let z0 = output[0];
+        output[0] = Complex32::new(z0.re + z0.im, 0.0);
+        output[m] = Complex32::new(z0.re - z0.im, 0.0);
+
+        // X[k] and X[m - k] depend on the same pair Z[k], Z[m - k], so both are produced at once
+        for k in 1..=m / 2 {
+            let a = output[k];
+            let b = output[m - k].conj();
+            let even = (a + b) * 0.5;
+            let odd = Complex32::new(0.0, -0.5) * (a - b);
+            let w = self.twiddles[k] * odd;
+            output[m - k] = (even - w).conj();
+            output[k] = even + w;
+        }
 
-    if n == 1 {
-        output[0] = Complex32::new(input[0], 0.0);
-        return;
+        for k in 1..m {
+            output[self.n - k] = output[k].conj();
+        }
     }
 
-    fft(input, stride * 2, &mut output[..n / 2], n / 2);
-    fft(&input[stride..], stride * 2, &mut output[n / 2..], n / 2);
+    // In-place transform of `buf`, whose length is n >> shift
+    fn transform(&self, buf: &mut [Complex32], shift: u32) {
+        let len = buf.len();
+        if len <= 1 {
+            return;
+        }
 
-    for k in 0..n / 2 {
-        let t = k as f32 / n as f32;
-        let v = Complex32::new(0.0, -2.0 * PI * t).exp() * output[k + n / 2];
-        let e = output[k];
-        output[k] = e + v;
-        output[k + n / 2] = e - v;
+        for i in 0..len {
+            let j = self.bitrev[i] >> shift;
+            if i < j {
+                buf.swap(i, j);
+            }
+        }
+
+        let mut size = 2;
+        while size <= len {
+            let half = size / 2;
+            let stride = self.n / size;
+            for start in (0..len).step_by(size) {
+                for k in 0..half {
+                    let w = self.twiddles[k * stride];
+                    let v = buf[start + k + half] * w;
+                    let e = buf[start + k];
+                    buf[start + k] = e + v;
+                    buf[start + k + half] = e - v;
+                }
+            }
+            size *= 2;
+        }
     }
 }
 
-fn main() {
-    let n = 4096;
-    let mut input = vec![0.0; n];
-    let mut output = vec![Complex32::new(0.0, 0.0); n];
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    for i in 0..n {
-        let t = i as f32 / n as f32;
-        input[i] = (2.0 * PI * t).cos() + (2.0 * PI * t * 2.0).sin();
+    // Small deterministic LCG so the tests don't need a rand dependency
+    fn signal(n: usize, seed: u32) -> Vec<f32> {
+        let mut state = seed;
+        (0..n)
+            .map(|_| {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                (state >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+            })
+            .collect()
     }
 
-    fft(&input, 1, &mut output, n);
+    fn assert_close(actual: &[Complex32], expected: &[Complex32], tolerance: f32) {
+        assert_eq!(actual.len(), expected.len());
+        for (i, (a, e)) in actual.iter().zip(expected.iter()).enumerate() {
+            assert!(
+                (a - e).norm() <= tolerance,
+                "bin {}: got {}, expected {}",
+                i,
+                a,
+                e
+            );
+        }
+    }
+
+    #[test]
+    fn real_matches_dft() {
+        for &n in &[1, 2, 4, 8, 16, 64, 256, 1024] {
+            let input = signal(n, n as u32);
+            let mut expected = vec![Complex32::new(0.0, 0.0); n];
+            dft(&input, &mut expected, n);
+
+            let mut output = vec![Complex32::new(0.0, 0.0); n];
+            Fft::new(n).process_real(&input, &mut output);
+
+            assert_close(&output, &expected, 1e-3 * n as f32);
+        }
+    }
+
+    #[test]
+    fn complex_matches_dft_of_real_input() {
+        for &n in &[1, 2, 8, 32, 512] {
+            let input = signal(n, 7);
+            let mut expected = vec![Complex32::new(0.0, 0.0); n];
+            dft(&input, &mut expected, n);
+
+            let mut buf: Vec<Complex32> = input.iter().map(|&x| Complex32::new(x, 0.0)).collect();
+            Fft::new(n).process(&mut buf);
+
+            assert_close(&buf, &expected, 1e-3 * n as f32);
+        }
+    }
+
+    #[test]
+    fn complex_is_linear_in_imaginary_part() {
+        // FFT(a + ib) = FFT(a) + i FFT(b), which exercises the complex path beyond real input
+        let n = 128;
+        let a = signal(n, 1);
+        let b = signal(n, 2);
+        let mut fa = vec![Complex32::new(0.0, 0.0); n];
+        let mut fb = vec![Complex32::new(0.0, 0.0); n];
+        dft(&a, &mut fa, n);
+        dft(&b, &mut fb, n);
+        let expected: Vec<Complex32> = fa
+            .iter()
+            .zip(fb.iter())
+            .map(|(x, y)| x + Complex32::new(0.0, 1.0) * y)
+            .collect();
+
+        let mut buf: Vec<Complex32> = a
+            .iter()
+            .zip(b.iter())
+            .map(|(&x, &y)| Complex32::new(x, y))
+            .collect();
+        Fft::new(n).process(&mut buf);
 
-    for f in 0..n {
-        println!("{}: {:.2}, {:.2}", f, output[f].re, output[f].im);
+        assert_close(&buf, &expected, 1e-3 * n as f32);
     }
 
+    #[test]
+    fn planner_is_reusable() {
+        let n = 4096;
+        let fft = Fft::new(n);
+        let mut output = vec![Complex32::new(0.0, 0.0); n];
+
+        for seed in 0..3 {
+            let input = signal(n, seed);
+            fft.process_real(&input, &mut output);
+            let first = output.clone();
+            fft.process_real(&input, &mut output);
+            assert_eq!(first, output);
+        }
+    }
+
+    #[test]
+    fn finds_tone_bins() {
+        // The signal the old standalone fft.rs demo printed: a cosine at bin 1 and a sine at bin 2
+        let n = 4096;
+        let input: Vec<f32> = (0..n)
+            .map(|i| {
+                let t = i as f32 / n as f32;
+                (2.0 * PI * t).cos() + (2.0 * PI * t * 2.0).sin()
+            })
+            .collect();
+        let mut output = vec![Complex32::new(0.0, 0.0); n];
+        Fft::new(n).process_real(&input, &mut output);
+
+        let half = n as f32 / 2.0;
+        assert!((output[1] - Complex32::new(half, 0.0)).norm() < 0.5);
+        assert!((output[2] - Complex32::new(0.0, -half)).norm() < 0.5);
+        for (f, bin) in output.iter().enumerate().take(n / 2).skip(3) {
+            assert!(bin.norm() < 0.5, "unexpected energy in bin {}", f);
+        }
+    }
+}
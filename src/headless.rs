@@ -0,0 +1,271 @@
+use libloading::Library;
+use raylib::ffi::{
+    rlGetTextureIdDefault, rlLoadExtensions, rlglClose, rlglInit, Rectangle, SetShapesTexture,
+    Texture2D,
+};
+use std::ffi::{c_char, c_void};
+
+type EGLDisplay = *mut c_void;
+type EGLConfig = *mut c_void;
+type EGLContext = *mut c_void;
+type EGLSurface = *mut c_void;
+
+const EGL_NONE: i32 = 0x3038;
+const EGL_SURFACE_TYPE: i32 = 0x3033;
+const EGL_PBUFFER_BIT: i32 = 0x0001;
+const EGL_RENDERABLE_TYPE: i32 = 0x3040;
+const EGL_OPENGL_BIT: i32 = 0x0008;
+const EGL_RED_SIZE: i32 = 0x3024;
+const EGL_GREEN_SIZE: i32 = 0x3023;
+const EGL_BLUE_SIZE: i32 = 0x3022;
+const EGL_ALPHA_SIZE: i32 = 0x3021;
+const EGL_WIDTH: i32 = 0x3057;
+const EGL_HEIGHT: i32 = 0x3056;
+const EGL_OPENGL_API: u32 = 0x30A2;
+const EGL_CONTEXT_MAJOR_VERSION: i32 = 0x3098;
+const EGL_CONTEXT_MINOR_VERSION: i32 = 0x30FB;
+const EGL_CONTEXT_OPENGL_PROFILE_MASK: i32 = 0x30FD;
+const EGL_CONTEXT_OPENGL_CORE_PROFILE_BIT: i32 = 0x0001;
+// Mesa's platform for rendering without any window system, not even a GPU device node
+const EGL_PLATFORM_SURFACELESS_MESA: u32 = 0x31DD;
+
+// raylib's built-in font, normally loaded by InitWindow. Not in raylib.h, but exported from
+// rtext.c for rcore.c.
+extern "C" {
+    fn LoadFontDefault();
+    fn UnloadFontDefault();
+}
+
+// The EGL entry points used, copied out of the library so they can outlive the lookups
+struct Egl {
+    get_proc_address: unsafe extern "C" fn(*const c_char) -> *mut c_void,
+    get_display: unsafe extern "C" fn(*mut c_void) -> EGLDisplay,
+    initialize: unsafe extern "C" fn(EGLDisplay, *mut i32, *mut i32) -> u32,
+    choose_config:
+        unsafe extern "C" fn(EGLDisplay, *const i32, *mut EGLConfig, i32, *mut i32) -> u32,
+    bind_api: unsafe extern "C" fn(u32) -> u32,
+    create_context:
+        unsafe extern "C" fn(EGLDisplay, EGLConfig, EGLContext, *const i32) -> EGLContext,
+    create_pbuffer_surface: unsafe extern "C" fn(EGLDisplay, EGLConfig, *const i32) -> EGLSurface,
+    make_current: unsafe extern "C" fn(EGLDisplay, EGLSurface, EGLSurface, EGLContext) -> u32,
+    destroy_surface: unsafe extern "C" fn(EGLDisplay, EGLSurface) -> u32,
+    destroy_context: unsafe extern "C" fn(EGLDisplay, EGLContext) -> u32,
+    terminate: unsafe extern "C" fn(EGLDisplay) -> u32,
+    get_error: unsafe extern "C" fn() -> i32,
+}
+
+impl Egl {
+    unsafe fn load(library: &Library) -> Result<Egl, String> {
+        macro_rules! symbol {
+            ($name:literal) => {
+                *library
+                    .get(concat!($name, "\0").as_bytes())
+                    .map_err(|e| format!("{} is missing from libEGL: {}", $name, e))?
+            };
+        }
+        Ok(Egl {
+            get_proc_address: symbol!("eglGetProcAddress"),
+            get_display: symbol!("eglGetDisplay"),
+            initialize: symbol!("eglInitialize"),
+            choose_config: symbol!("eglChooseConfig"),
+            bind_api: symbol!("eglBindAPI"),
+            create_context: symbol!("eglCreateContext"),
+            create_pbuffer_surface: symbol!("eglCreatePbufferSurface"),
+            make_current: symbol!("eglMakeCurrent"),
+            destroy_surface: symbol!("eglDestroySurface"),
+            destroy_context: symbol!("eglDestroyContext"),
+            terminate: symbol!("eglTerminate"),
+            get_error: symbol!("eglGetError"),
+        })
+    }
+
+    // A failed call's message, with the EGL error code to look up
+    unsafe fn fail(&self, call: &str) -> String {
+        format!("{} failed (EGL error 0x{:x})", call, (self.get_error)())
+    }
+}
+
+// An OpenGL 3.3 context without any window or display server, made current on the calling
+// thread with raylib's rlgl set up on top, so everything drawn into render textures works like
+// after InitWindow. Only render textures can be drawn to; BeginDrawing has no window to show.
+pub struct HeadlessContext {
+    egl: Egl,
+    display: EGLDisplay,
+    context: EGLContext,
+    surface: EGLSurface,
+    // Whether rlgl was set up and has to be closed before the context goes
+    rlgl: bool,
+    // Dropped last, the function pointers point into it
+    _library: Library,
+}
+
+impl HeadlessContext {
+    pub fn new(width: i32, height: i32) -> Result<HeadlessContext, String> {
+        unsafe {
+            let library = Library::new("libEGL.so.1").map_err(|e| {
+                format!(
+                    "headless rendering needs EGL (libEGL.so.1 from Mesa or the GPU driver): {}",
+                    e
+                )
+            })?;
+            let egl = Egl::load(&library)?;
+
+            // Mesa's surfaceless platform needs neither X nor Wayland; other drivers get the
+            // default display, which they can open without one too
+            let display = match platform_display(&egl, EGL_PLATFORM_SURFACELESS_MESA) {
+                Some(display)
+                    if (egl.initialize)(display, std::ptr::null_mut(), std::ptr::null_mut())
+                        != 0 =>
+                {
+                    display
+                }
+                _ => {
+                    let display = (egl.get_display)(std::ptr::null_mut());
+                    if display.is_null()
+                        || (egl.initialize)(display, std::ptr::null_mut(), std::ptr::null_mut())
+                            == 0
+                    {
+                        return Err(egl.fail("eglInitialize"));
+                    }
+                    display
+                }
+            };
+
+            let mut context = HeadlessContext {
+                egl,
+                display,
+                context: std::ptr::null_mut(),
+                surface: std::ptr::null_mut(),
+                rlgl: false,
+                _library: library,
+            };
+            // From here on Drop cleans up whatever was created
+            context.create(width, height)?;
+            Ok(context)
+        }
+    }
+
+    unsafe fn create(&mut self, width: i32, height: i32) -> Result<(), String> {
+        let egl = &self.egl;
+        let configAttributes = [
+            EGL_SURFACE_TYPE,
+            EGL_PBUFFER_BIT,
+            EGL_RENDERABLE_TYPE,
+            EGL_OPENGL_BIT,
+            EGL_RED_SIZE,
+            8,
+            EGL_GREEN_SIZE,
+            8,
+            EGL_BLUE_SIZE,
+            8,
+            EGL_ALPHA_SIZE,
+            8,
+            EGL_NONE,
+        ];
+        let mut config: EGLConfig = std::ptr::null_mut();
+        let mut count = 0;
+        if (egl.choose_config)(
+            self.display,
+            configAttributes.as_ptr(),
+            &mut config,
+            1,
+            &mut count,
+        ) == 0
+            || count == 0
+        {
+            return Err("no EGL config supports desktop OpenGL".to_string());
+        }
+        if (egl.bind_api)(EGL_OPENGL_API) == 0 {
+            return Err(egl.fail("eglBindAPI"));
+        }
+
+        // raylib is built for OpenGL 3.3 core
+        let contextAttributes = [
+            EGL_CONTEXT_MAJOR_VERSION,
+            3,
+            EGL_CONTEXT_MINOR_VERSION,
+            3,
+            EGL_CONTEXT_OPENGL_PROFILE_MASK,
+            EGL_CONTEXT_OPENGL_CORE_PROFILE_BIT,
+            EGL_NONE,
+        ];
+        self.context = (egl.create_context)(
+            self.display,
+            config,
+            std::ptr::null_mut(),
+            contextAttributes.as_ptr(),
+        );
+        if self.context.is_null() {
+            return Err(egl.fail("Creating an OpenGL 3.3 context"));
+        }
+        // Nothing is drawn to it, but not every driver can make a context current without one
+        let surfaceAttributes = [EGL_WIDTH, 1, EGL_HEIGHT, 1, EGL_NONE];
+        self.surface =
+            (egl.create_pbuffer_surface)(self.display, config, surfaceAttributes.as_ptr());
+        if self.surface.is_null() {
+            return Err(egl.fail("eglCreatePbufferSurface"));
+        }
+        if (egl.make_current)(self.display, self.surface, self.surface, self.context) == 0 {
+            return Err(egl.fail("eglMakeCurrent"));
+        }
+
+        // What InitWindow does once the platform has a context
+        rlLoadExtensions(egl.get_proc_address as *mut c_void);
+        rlglInit(width, height);
+        self.rlgl = true;
+        LoadFontDefault();
+        SetShapesTexture(
+            Texture2D {
+                id: rlGetTextureIdDefault(),
+                width: 1,
+                height: 1,
+                mipmaps: 1,
+                format: 7,
+            },
+            Rectangle {
+                x: 0.0,
+                y: 0.0,
+                width: 1.0,
+                height: 1.0,
+            },
+        );
+        Ok(())
+    }
+}
+
+// eglGetPlatformDisplayEXT is an extension, only reachable through eglGetProcAddress
+unsafe fn platform_display(egl: &Egl, platform: u32) -> Option<EGLDisplay> {
+    let function = (egl.get_proc_address)(c"eglGetPlatformDisplayEXT".as_ptr());
+    if function.is_null() {
+        return None;
+    }
+    let get_platform_display: unsafe extern "C" fn(u32, *mut c_void, *const i32) -> EGLDisplay =
+        std::mem::transmute(function);
+    let display = get_platform_display(platform, std::ptr::null_mut(), std::ptr::null());
+    (!display.is_null()).then_some(display)
+}
+
+impl Drop for HeadlessContext {
+    fn drop(&mut self) {
+        unsafe {
+            let egl = &self.egl;
+            if self.rlgl {
+                UnloadFontDefault();
+                rlglClose();
+            }
+            if !self.context.is_null() {
+                (egl.make_current)(
+                    self.display,
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                );
+                (egl.destroy_context)(self.display, self.context);
+            }
+            if !self.surface.is_null() {
+                (egl.destroy_surface)(self.display, self.surface);
+            }
+            (egl.terminate)(self.display);
+        }
+    }
+}
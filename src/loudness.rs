@@ -0,0 +1,566 @@
+use crate::biquad::Biquad;
+use crate::config::config_dir;
+use crate::playback::{Decoder, ENGINE_SAMPLE_RATE};
+use rsmpeg::avformat::AVFormatContextInput;
+use rsmpeg::avutil::AVDictionary;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+
+// Loudness ReplayGain 2 gains bring a track to. R128_* gains aim at -23 LUFS instead and are
+// shifted by the difference, so both kinds of tag play back at the same level.
+pub const REFERENCE_LUFS: f32 = -18.0;
+const R128_REFERENCE_LUFS: f32 = -23.0;
+
+// Highest sample level the limiter lets through, just under full scale.
+// -0.2 dBFS
+const LIMITER_CEILING: f32 = 0.977;
+// Seconds the limiter takes to recover most of the way after a peak
+const LIMITER_RELEASE: f32 = 0.15;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GainMode {
+    Off,
+    Track,
+    // Keeps the level differences within an album, falls back to the track gain
+    Album,
+}
+
+impl GainMode {
+    pub const NAMES: [&'static str; 3] = ["off", "track", "album"];
+
+    pub fn from_name(name: &str) -> Option<GainMode> {
+        match name.to_ascii_lowercase().as_str() {
+            "off" => Some(GainMode::Off),
+            "track" => Some(GainMode::Track),
+            "album" => Some(GainMode::Album),
+            _ => None,
+        }
+    }
+
+    pub fn next(self) -> GainMode {
+        match self {
+            GainMode::Off => GainMode::Track,
+            GainMode::Track => GainMode::Album,
+            GainMode::Album => GainMode::Off,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GainMode::Off => "Off",
+            GainMode::Track => "Track",
+            GainMode::Album => "Album",
+        }
+    }
+}
+
+// A gain to the ReplayGain reference and the sample peak it was measured with
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Gain {
+    pub db: f32,
+    pub peak: Option<f32>,
+}
+
+impl Gain {
+    // The factor samples are scaled by, with `preamp` dB added. Held down to what brings the
+    // peak to full scale when it is known, so only untagged peaks are left to the limiter.
+    pub fn linear(&self, preamp: f32) -> f32 {
+        let linear = db_to_linear(self.db + preamp);
+        match self.peak.filter(|peak| *peak > 0.0) {
+            Some(peak) => linear.min(1.0 / peak),
+            None => linear,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct LoudnessTags {
+    pub track: Option<Gain>,
+    pub album: Option<Gain>,
+}
+
+impl LoudnessTags {
+    // ReplayGain tags, or the R128 ones Opus files carry. Like the other tags they may sit on
+    // the container or on a stream.
+    pub fn read(format: &AVFormatContextInput) -> LoudnessTags {
+        let lookup = |key: &str| -> Option<String> {
+            if let Some(value) = format.metadata().and_then(|dict| tag(&dict, key)) {
+                return Some(value);
+            }
+            format
+                .streams()
+                .iter()
+                .find_map(|stream| stream.metadata().and_then(|dict| tag(&dict, key)))
+        };
+        let gain = |replaygain: &str, r128: &str| -> Option<Gain> {
+            let db = lookup(&format!("replaygain_{}_gain", replaygain))
+                .and_then(|value| parse_db(&value))
+                .or_else(|| lookup(r128).and_then(|value| parse_r128(&value)))?;
+            let peak = lookup(&format!("replaygain_{}_peak", replaygain))
+                .and_then(|value| value.trim().parse::<f32>().ok())
+                .filter(|peak| peak.is_finite() && *peak > 0.0);
+            Some(Gain { db, peak })
+        };
+        LoudnessTags {
+            track: gain("track", "r128_track_gain"),
+            album: gain("album", "r128_album_gain"),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.track.is_none() && self.album.is_none()
+    }
+
+    pub fn gain(&self, mode: GainMode) -> Option<Gain> {
+        match mode {
+            GainMode::Off => None,
+            GainMode::Track => self.track.or(self.album),
+            GainMode::Album => self.album.or(self.track),
+        }
+    }
+
+    // For the track info box, e.g. "-6.52 dB (album -5.10)"
+    pub fn describe(&self) -> String {
+        match (self.track, self.album) {
+            (Some(track), Some(album)) => format!("{:+.2} dB (album {:+.2})", track.db, album.db),
+            (Some(track), None) => format!("{:+.2} dB", track.db),
+            (None, Some(album)) => format!("album {:+.2} dB", album.db),
+            (None, None) => "None".to_string(),
+        }
+    }
+}
+
+fn tag(dict: &AVDictionary, key: &str) -> Option<String> {
+    let c_key = CString::new(key).ok()?;
+    // FFmpeg matches keys case-insensitively, so REPLAYGAIN_* and replaygain_* both work
+    let entry = dict.get(&c_key, None, 0)?;
+    let value = entry.value().to_string_lossy().trim().to_string();
+    (!value.is_empty()).then_some(value)
+}
+
+// "-6.54 dB", the unit is optional
+fn parse_db(value: &str) -> Option<f32> {
+    let number = value.trim();
+    let number = number
+        .strip_suffix("dB")
+        .or_else(|| number.strip_suffix("db"))
+        .unwrap_or(number);
+    number
+        .trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|db| db.is_finite())
+}
+
+// RFC 7845 R128 gains are Q7.8 fixed point in dB, e.g. "-1234"
+fn parse_r128(value: &str) -> Option<f32> {
+    let q78 = value.trim().parse::<i16>().ok()?;
+    Some(q78 as f32 / 256.0 + REFERENCE_LUFS - R128_REFERENCE_LUFS)
+}
+
+pub fn db_to_linear(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+// Keeps gained-up audio from clipping. The attack is instant, so no sample ever goes over the
+// ceiling, and it only acts at all when something would.
+pub struct Limiter {
+    gain: f32,
+    release: f32,
+}
+
+impl Limiter {
+    pub fn new(sample_rate: u32) -> Limiter {
+        Limiter {
+            gain: 1.0,
+            release: 1.0 - (-1.0 / (LIMITER_RELEASE * sample_rate as f32)).exp(),
+        }
+    }
+
+    // `samples` is interleaved stereo
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_exact_mut(2) {
+            let peak = frame[0].abs().max(frame[1].abs());
+            let allowed = if peak > LIMITER_CEILING {
+                LIMITER_CEILING / peak
+            } else {
+                1.0
+            };
+            self.gain = (self.gain + (1.0 - self.gain) * self.release).min(allowed);
+            frame[0] *= self.gain;
+            frame[1] *= self.gain;
+        }
+    }
+}
+
+// The two stages of the ITU-R BS.1770 K-weighting curve for `sample_rate`, derived from the
+// analog prototype the 48 kHz coefficients in the standard come from
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let fs = sample_rate as f64;
+
+    // High shelf modelling the acoustic effect of the head
+    let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (std::f64::consts::PI * f0 / fs).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    // High pass (the RLB curve)
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (std::f64::consts::PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let highpass = Biquad::new(
+        [1.0, -2.0, 1.0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+    );
+
+    [shelf, highpass]
+}
+
+// EBU R128 integrated loudness of stereo audio. Keeps the power of every 400 ms gating block
+// so the blocks of several tracks can be gated together into an album loudness.
+pub struct LoudnessMeter {
+    filters: [[Biquad; 2]; 2],
+    // Samples in each 100 ms step, blocks are four steps overlapping by three
+    step_length: usize,
+    step_position: usize,
+    step_energy: f64,
+    steps: [f64; 4],
+    steps_done: usize,
+    blocks: Vec<f64>,
+    peak: f32,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32) -> LoudnessMeter {
+        LoudnessMeter {
+            filters: [k_weighting(sample_rate), k_weighting(sample_rate)],
+            step_length: (sample_rate / 10).max(1) as usize,
+            step_position: 0,
+            step_energy: 0.0,
+            steps: [0.0; 4],
+            steps_done: 0,
+            blocks: Vec::new(),
+            peak: 0.0,
+        }
+    }
+
+    // `samples` is interleaved stereo
+    pub fn add(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(2) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                self.peak = self.peak.max(sample.abs());
+                let [shelf, highpass] = &mut self.filters[channel];
+                let weighted = highpass.process(shelf.process(sample as f64));
+                // Left and right both have weight 1
+                self.step_energy += weighted * weighted;
+            }
+            self.step_position += 1;
+            if self.step_position == self.step_length {
+                self.steps.rotate_left(1);
+                self.steps[3] = self.step_energy / self.step_length as f64;
+                self.step_energy = 0.0;
+                self.step_position = 0;
+                self.steps_done += 1;
+                if self.steps_done >= 4 {
+                    self.blocks.push(self.steps.iter().sum::<f64>() / 4.0);
+                }
+            }
+        }
+    }
+
+    pub fn peak(&self) -> f32 {
+        self.peak
+    }
+
+    pub fn blocks(&self) -> &[f64] {
+        &self.blocks
+    }
+
+    pub fn integrated(&self) -> Option<f32> {
+        integrated_loudness(&self.blocks)
+    }
+}
+
+fn block_loudness(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+// Gated loudness in LUFS of a set of block powers, None when all of it is near silence
+pub fn integrated_loudness(blocks: &[f64]) -> Option<f32> {
+    let mean = |gate: f64| -> Option<f64> {
+        let (sum, count) = blocks
+            .iter()
+            .filter(|&&power| block_loudness(power) > gate)
+            .fold((0.0, 0usize), |(sum, count), &power| {
+                (sum + power, count + 1)
+            });
+        (count > 0).then(|| sum / count as f64)
+    };
+    // Absolute gate at -70 LUFS, then a relative one 10 LU under what is left
+    let relative = block_loudness(mean(-70.0)?) - 10.0;
+    mean(relative).map(|power| block_loudness(power) as f32)
+}
+
+// Measures a file at the rate the player decodes to
+pub fn measure(decoder: &mut Decoder) -> LoudnessMeter {
+    const CHUNK: usize = 4096;
+    let mut meter = LoudnessMeter::new(ENGINE_SAMPLE_RATE);
+    let mut buffer = vec![0.0f32; CHUNK * 2];
+    loop {
+        let read = decoder.read(&mut buffer);
+        meter.add(&buffer[..read * 2]);
+        if read < CHUNK {
+            return meter;
+        }
+    }
+}
+
+// Gains measured by `--scan`, for files without tags. Kept in loudness.tsv next to the config
+// as "track gain, track peak, album gain, album peak, path" per line.
+#[derive(Default)]
+pub struct ScanCache {
+    entries: HashMap<PathBuf, LoudnessTags>,
+}
+
+fn cache_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("loudness.tsv"))
+}
+
+// The same file reached through different relative paths shares one entry
+fn cache_key(path: &str) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path))
+}
+
+impl ScanCache {
+    // A missing or unreadable cache is just empty
+    pub fn load() -> ScanCache {
+        let mut cache = ScanCache::default();
+        let Some(source) = cache_path().and_then(|path| std::fs::read_to_string(path).ok()) else {
+            return cache;
+        };
+        for line in source.lines() {
+            let fields: Vec<&str> = line.splitn(5, '\t').collect();
+            let [track, track_peak, album, album_peak, path] = fields[..] else {
+                continue;
+            };
+            let gain = |db: &str, peak: &str| {
+                Some(Gain {
+                    db: db.parse().ok()?,
+                    peak: peak.parse().ok(),
+                })
+            };
+            cache.entries.insert(
+                PathBuf::from(path),
+                LoudnessTags {
+                    track: gain(track, track_peak),
+                    album: gain(album, album_peak),
+                },
+            );
+        }
+        cache
+    }
+
+    pub fn get(&self, path: &str) -> Option<LoudnessTags> {
+        self.entries.get(&cache_key(path)).copied()
+    }
+
+    fn insert(&mut self, path: &str, tags: LoudnessTags) {
+        self.entries.insert(cache_key(path), tags);
+    }
+
+    fn save(&self) -> Result<PathBuf, String> {
+        let path = cache_path().ok_or("no config directory")?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        let mut contents = String::new();
+        for (file, tags) in entries {
+            let (Some(track), Some(album)) = (tags.track, tags.album) else {
+                continue;
+            };
+            contents.push_str(&format!(
+                "{:.2}\t{:.6}\t{:.2}\t{:.6}\t{}\n",
+                track.db,
+                track.peak.unwrap_or(1.0),
+                album.db,
+                album.peak.unwrap_or(1.0),
+                file.display()
+            ));
+        }
+        std::fs::write(&path, contents).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(path)
+    }
+}
+
+// Measures every track, treating the tracks of each directory as one album, prints the gains
+// and stores them for playback. Tagged tracks are skipped, the player never looks their scan up,
+// so an album's gain only covers its untagged tracks.
+pub fn scan(paths: &[String]) -> Result<(), String> {
+    let mut cache = ScanCache::load();
+    let mut albums: Vec<(&Path, Vec<(&str, LoudnessMeter)>)> = Vec::new();
+    for path in paths {
+        let mut decoder = match Decoder::open(path) {
+            Ok(decoder) => decoder,
+            Err(e) => {
+                println!("Cannot scan {}: {}", path, e);
+                continue;
+            }
+        };
+        if !decoder.tags.is_empty() {
+            println!("   tagged              {}", path);
+            continue;
+        }
+        let meter = measure(&mut decoder);
+        match meter.integrated() {
+            Some(lufs) => println!(
+                "{:6.1} LUFS {:+6.2} dB  peak {:.3}  {}",
+                lufs,
+                REFERENCE_LUFS - lufs,
+                meter.peak(),
+                path
+            ),
+            None => println!("   silent              {}", path),
+        }
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        match albums.iter_mut().find(|(d, _)| *d == dir) {
+            Some((_, tracks)) => tracks.push((path.as_str(), meter)),
+            None => albums.push((dir, vec![(path.as_str(), meter)])),
+        }
+    }
+
+    for (dir, tracks) in &albums {
+        let blocks: Vec<f64> = tracks
+            .iter()
+            .flat_map(|(_, meter)| meter.blocks().iter().copied())
+            .collect();
+        let Some(album_lufs) = integrated_loudness(&blocks) else {
+            continue;
+        };
+        let album = Gain {
+            db: REFERENCE_LUFS - album_lufs,
+            peak: Some(tracks.iter().map(|(_, m)| m.peak()).fold(0.0, f32::max)),
+        };
+        if tracks.len() > 1 {
+            println!(
+                "{:6.1} LUFS {:+6.2} dB  album {}",
+                album_lufs,
+                album.db,
+                dir.display()
+            );
+        }
+        for (path, meter) in tracks {
+            // Silent tracks keep no gain, there is nothing to bring up
+            let Some(lufs) = meter.integrated() else {
+                continue;
+            };
+            let track = Gain {
+                db: REFERENCE_LUFS - lufs,
+                peak: Some(meter.peak()),
+            };
+            cache.insert(
+                path,
+                LoudnessTags {
+                    track: Some(track),
+                    album: Some(album),
+                },
+            );
+        }
+    }
+
+    let saved = cache.save()?;
+    println!("Saved the gains to {}", saved.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f32, amplitude: f32, seconds: f32, sample_rate: u32) -> Vec<f32> {
+        let frames = (seconds * sample_rate as f32) as usize;
+        let mut samples = Vec::with_capacity(frames * 2);
+        for i in 0..frames {
+            let phase = 2.0 * std::f32::consts::PI * frequency * i as f32 / sample_rate as f32;
+            let sample = amplitude * phase.sin();
+            samples.push(sample);
+            samples.push(sample);
+        }
+        samples
+    }
+
+    #[test]
+    fn reads_reference_sine() {
+        // EBU Tech 3341 case 1: a 1 kHz sine at -23 dBFS in both channels reads -23 LUFS
+        for sample_rate in [44100, 48000] {
+            let mut meter = LoudnessMeter::new(sample_rate);
+            meter.add(&sine(1000.0, db_to_linear(-23.0), 20.0, sample_rate));
+            let lufs = meter.integrated().unwrap();
+            assert!((lufs + 23.0).abs() < 0.1, "{} Hz: {}", sample_rate, lufs);
+        }
+    }
+
+    #[test]
+    fn relative_gate_drops_quiet_parts() {
+        // EBU Tech 3341 case 3: -36, -23 and -36 dBFS for 10, 60 and 10 s gate the quiet
+        // parts out and read -23 LUFS
+        let mut meter = LoudnessMeter::new(48000);
+        meter.add(&sine(1000.0, db_to_linear(-36.0), 10.0, 48000));
+        meter.add(&sine(1000.0, db_to_linear(-23.0), 60.0, 48000));
+        meter.add(&sine(1000.0, db_to_linear(-36.0), 10.0, 48000));
+        let lufs = meter.integrated().unwrap();
+        assert!((lufs + 23.0).abs() < 0.1, "{}", lufs);
+    }
+
+    #[test]
+    fn silence_has_no_loudness() {
+        let mut meter = LoudnessMeter::new(48000);
+        meter.add(&vec![0.0; 48000 * 2]);
+        assert_eq!(meter.integrated(), None);
+    }
+
+    #[test]
+    fn parses_gain_tags() {
+        assert_eq!(parse_db("-6.54 dB"), Some(-6.54));
+        assert_eq!(parse_db("+2.1"), Some(2.1));
+        assert_eq!(parse_db("loud"), None);
+        // -5 dB to -23 LUFS is 0 dB to -18
+        assert_eq!(parse_r128("-1280"), Some(0.0));
+    }
+
+    #[test]
+    fn gain_is_capped_at_the_peak() {
+        let gain = |db, peak| Gain { db, peak }.linear(0.0);
+        assert_eq!(gain(10.0, Some(0.5)), 2.0);
+        assert_eq!(gain(-6.0, Some(0.5)), db_to_linear(-6.0));
+        assert_eq!(gain(10.0, None), db_to_linear(10.0));
+        // A broken peak tag doesn't turn the track off
+        assert_eq!(gain(10.0, Some(0.0)), db_to_linear(10.0));
+        // The preamp counts towards the cap too
+        let capped = Gain {
+            db: 0.0,
+            peak: Some(0.8),
+        }
+        .linear(6.0);
+        assert!((capped - 1.25).abs() < 1e-6, "{}", capped);
+    }
+
+    #[test]
+    fn limiter_holds_ceiling() {
+        let mut limiter = Limiter::new(48000);
+        let mut samples = sine(100.0, 2.0, 1.0, 48000);
+        limiter.process(&mut samples);
+        assert!(samples.iter().all(|s| s.abs() <= LIMITER_CEILING + 1e-6));
+    }
+}
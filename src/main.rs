@@ -1,94 +1,69 @@
 extern crate gtk;
+mod analysis;
+mod audio_format;
+mod biquad;
+mod capture;
+mod config;
+mod eq_overlay;
+mod equalizer;
+mod fft;
+mod headless;
+mod loudness;
+mod modes;
+mod playback;
+mod playlist;
+mod playlist_file;
+mod plugin;
+mod render;
+mod scope;
+mod smoothing;
+mod spectrogram;
+mod spectrum;
+mod speed;
+mod theme;
+mod visualizer;
+
+use analysis::{snapshot_channel, spawn_analysis_thread, SampleRing, SpectrumFrame};
+use audio_format::{for_each_frame, Downmix};
+use capture::{capture_screen, save_png, timestamp, Recorder};
+use config::{config_dir, Config, MAX_FFT_SIZE};
+use eq_overlay::EqOverlay;
+use equalizer::{EqControl, EqSettings, Equalizer, PRESETS as EQ_PRESETS};
 use gtk::prelude::*;
 use gtk::{FileChooserAction, FileChooserDialog, ResponseType, Window};
-use num_complex::*;
+use lazy_static::lazy_static;
+use loudness::{GainMode, LoudnessTags};
+use modes::builtin_visualizers;
+use playback::{Player, PlayerEvent, SourceFormat, Transition, ENGINE_SAMPLE_RATE};
+use playlist::{Playlist, RepeatMode};
+use playlist_file::save_m3u8;
+use plugin::load_plugin_dir;
 use raylib::consts::MouseButton::*;
 use raylib::ffi::{
-    AttachAudioStreamProcessor, BeginDrawing, BeginTextureMode, CheckCollisionPointRec,
-    ClearBackground, CloseAudioDevice, CloseWindow, ColorAlpha, DrawCircle, DrawCircleGradient,
-    DrawCircleLines, DrawLineEx, DrawRectangle, DrawRectangleLines, DrawRectangleRec, DrawTextEx,
-    DrawTextureRec, EndDrawing, EndTextureMode, FilePathList, Font, GetMousePosition,
-    GetMusicTimeLength, GetMusicTimePlayed, InitAudioDevice, InitWindow, IsFileDropped,
-    IsKeyPressed, IsMouseButtonPressed, IsMusicStreamPlaying, KeyboardKey::*, LoadDroppedFiles,
-    LoadFontEx, LoadMusicStream, LoadRenderTexture, MeasureTextEx, Music, PauseMusicStream,
-    PlayMusicStream, Rectangle, RenderTexture2D, ResumeMusicStream, SetMusicVolume, SetTargetFPS,
-    StopMusicStream, UnloadDroppedFiles, UnloadMusicStream, UpdateMusicStream, Vector2,
-    WindowShouldClose,
+    BeginDrawing, BeginTextureMode, CheckCollisionPointRec, ClearBackground, CloseAudioDevice,
+    CloseWindow, ColorAlpha, DrawCircle, DrawCircleGradient, DrawRectangle, DrawRectangleLines,
+    DrawRectangleRec, DrawTextEx, DrawTexturePro, DrawTextureRec, EndDrawing, EndTextureMode,
+    FilePathList, Font, GetFrameTime, GetMousePosition, InitAudioDevice, InitWindow, IsFileDropped,
+    IsKeyDown, IsKeyPressed, IsMouseButtonDown, IsMouseButtonPressed, KeyboardKey::*,
+    LoadDroppedFiles, LoadFontEx, LoadRenderTexture, LoadTextureFromImage, MeasureTextEx,
+    PixelFormat, Rectangle, RenderTexture2D, SetTargetFPS, SetTextureFilter, Texture2D,
+    TextureFilter, UnloadDroppedFiles, UnloadTexture, Vector2, WindowShouldClose,
 };
-use rsmpeg::ffi::{
-    av_dict_get, avformat_close_input, avformat_find_stream_info, avformat_open_input,
-    AVDictionaryEntry, AVFormatContext,
-};
-use rust_math::trigonometry::deg2rad;
-use std::f32::consts::PI;
+use rsmpeg::avcodec::{AVCodec, AVCodecContext, AVCodecParameters};
+use rsmpeg::avformat::AVFormatContextInput;
+use rsmpeg::avutil::{AVDictionary, AVFrame};
+use rsmpeg::ffi::{AV_DISPOSITION_ATTACHED_PIC, AV_PIX_FMT_RGBA, AV_TIME_BASE, SWS_BILINEAR};
+use rsmpeg::swscale::SwsContext;
+use scope::{Oscilloscope, SCOPE_WINDOW};
+use smoothing::{BandSmoother, SmoothingSettings};
+use spectrum::{band_count, BandAggregation, SpectrumSettings, BAND_LOW_HZ, BAND_STEP};
+use speed::Speed;
+use std::cell::UnsafeCell;
 use std::ffi::{CStr, CString};
-
-const N: usize = 1 << 13;
-// Color Palette for Gruvbox
-// lazy static is used to initialize the static variables only once at runtime
-
-pub const GRUVBOX_BG: raylib::ffi::Color = raylib::ffi::Color {
-    r: 40,
-    g: 40,
-    b: 40,
-    a: 255,
-}; // #282828
-
-pub const GRUVBOX_FG: raylib::ffi::Color = raylib::ffi::Color {
-    r: 235,
-    g: 219,
-    b: 178,
-    a: 255,
-}; // #ebdbb2
-
-pub const GRUVBOX_YELLOW: raylib::ffi::Color = raylib::ffi::Color {
-    r: 250,
-    g: 189,
-    b: 47,
-    a: 255,
-}; // #fabd2f
-
-pub const GRUVBOX_BLUE: raylib::ffi::Color = raylib::ffi::Color {
-    r: 131,
-    g: 165,
-    b: 152,
-    a: 255,
-}; // #83a598
-
-pub const GRUVBOX_GREEN: raylib::ffi::Color = raylib::ffi::Color {
-    r: 184,
-    g: 187,
-    b: 38,
-    a: 255,
-}; // #b8bb26
-
-pub const GRUVBOX_RED: raylib::ffi::Color = raylib::ffi::Color {
-    r: 251,
-    g: 73,
-    b: 52,
-    a: 255,
-}; // #fb4934
-
-pub const GRUVBOX_ORANGE: raylib::ffi::Color = raylib::ffi::Color {
-    r: 254,
-    g: 128,
-    b: 25,
-    a: 255,
-}; // #fe8019
-
-pub const GRUVBOX_AQUA: raylib::ffi::Color = raylib::ffi::Color {
-    r: 142,
-    g: 192,
-    b: 124,
-    a: 255,
-}; // #8ec07c
-
-pub const GRUVBOX_PURPLE: raylib::ffi::Color = raylib::ffi::Color {
-    r: 211,
-    g: 134,
-    b: 155,
-    a: 255,
-}; // #d3869b
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use theme::{GradientMode, Theme, GRUVBOX};
+use visualizer::{Registry, VisualFrame};
 
 pub const WHITE: raylib::ffi::Color = raylib::ffi::Color {
     r: 255,
@@ -111,179 +86,121 @@ pub const GRAY: raylib::ffi::Color = raylib::ffi::Color {
     a: 255,
 }; // #808080
 
-// This derive macro is used to implement the Copy, Clone and Debug traits for the enum
-#[derive(Copy, Clone, Debug)]
-enum VisualizationMode {
-    STANDARD,
-    PIXEL,
-    WAVEFORM,
-    STARBURST,
-    RADIAL_BARS,
-}
-
-// This implementation of the enum is used to convert the enum to usize and vice versa
-impl VisualizationMode {
-    fn from_usize(value: usize) -> VisualizationMode {
-        match value % NUM_MODES {
-            0 => VisualizationMode::STANDARD,
-            1 => VisualizationMode::PIXEL,
-            2 => VisualizationMode::WAVEFORM,
-            3 => VisualizationMode::STARBURST,
-            4 => VisualizationMode::RADIAL_BARS,
-            _ => VisualizationMode::STANDARD,
-        }
-    }
-
-    fn as_usize(&self) -> usize {
-        match self {
-            VisualizationMode::STANDARD => 0,
-            VisualizationMode::PIXEL => 1,
-            VisualizationMode::WAVEFORM => 2,
-            VisualizationMode::STARBURST => 3,
-            VisualizationMode::RADIAL_BARS => 4,
-        }
-    }
+// Decoded cover picture, always tightly packed RGBA8
+#[derive(Clone, Debug, Default)]
+struct CoverArt {
+    width: i32,
+    height: i32,
+    pixels: Vec<u8>,
 }
 
+#[derive(Clone, Debug, Default)]
 struct MusicMetadata {
     title: String,
     artist: String,
     album: String,
     duration: f32,
+    track: String,
+    year: String,
+    genre: String,
+    album_artist: String,
+    loudness: LoudnessTags,
+    cover: Option<CoverArt>,
 }
 
 // Global Variables
-static mut input: [f32; N] = [0.0; N];
-static mut output: [Complex<f32>; N] = [Complex::new(0.0, 0.0); N];
+// The audio callback only pushes into this ring, the analysis thread does the FFT
+lazy_static! {
+    // Sized for the largest FFT the config allows
+    static ref SAMPLE_RING: SampleRing = SampleRing::new(MAX_FFT_SIZE * 4);
+    // Raw per-channel samples, read by the render loop for the oscilloscope and by the
+    // analysis thread for the stereo spectra
+    static ref LEFT_RING: SampleRing = SampleRing::new(MAX_FFT_SIZE.max(SCOPE_WINDOW) * 4);
+    static ref RIGHT_RING: SampleRing = SampleRing::new(MAX_FFT_SIZE.max(SCOPE_WINDOW) * 4);
+}
+
+// Set once at startup, before the player attaches the stream processor, and from then on only
+// run by the audio callback. The render loop hands it new filter designs through its
+// EqControl, so no lock is needed.
+struct CallbackEqualizer(UnsafeCell<Equalizer>);
 
-static mut max_amp: f32 = 0.0;
-static mut selected_song: String = String::new();
-const NUM_MODES: usize = 5;
-static mut currentMode: VisualizationMode = VisualizationMode::STANDARD;
+// Only the audio thread ever reaches the equalizer inside
+unsafe impl Sync for CallbackEqualizer {}
 
-const helpCommands: [&str; 9] = [
-    "f            - Play a media file (GTK file dialog will open)\n",
+static EQUALIZER: OnceLock<CallbackEqualizer> = OnceLock::new();
+
+const QUEUE_EXPORT_PATH: &str = "queue.m3u8";
+// How the audio callback folds the channels into the signal the spectrum is computed from
+static mut downmixMode: Downmix = Downmix::Mid;
+// Colors everything is drawn with, switched at runtime from the config's theme list
+static mut currentTheme: Theme = GRUVBOX;
+static mut gradientMode: GradientMode = GradientMode::Off;
+
+const helpCommands: [&str; 42] = [
+    "f            - Add a media file and play it (GTK file dialog will open)\n",
     "<Space>      - Pause music\n",
+    "n            - Next track\n",
+    "p            - Previous track\n",
+    "s            - Toggle shuffle\n",
+    "r            - Cycle repeat mode (off, all, one)\n",
+    "z            - Toggle gapless / crossfade between tracks\n",
+    "j            - Cycle loudness normalization (off, track, album)\n",
+    "o            - Show / hide the equalizer\n",
+    "i            - Cycle equalizer presets\n",
+    "u            - Toggle equalizer bypass\n",
+    "[ / ]        - Tempo down / up 5%, keeping the pitch\n",
+    "- / =        - Pitch down / up a semitone, keeping the tempo\n",
+    ", / .        - Varispeed down / up 5%, tempo and pitch together\n",
+    "<BACKSPACE>  - Back to normal speed and pitch\n",
+    "e            - Save the queue to queue.m3u8\n",
     "m            - Toggle mute\n",
     "<UP-ARROW>   - Increase volume by 10%\n",
-    "<DOWN-ARROW> - Decrease volume by 10%\n\n",
+    "<DOWN-ARROW> - Decrease volume by 10%\n",
+    "<LEFT/RIGHT> - Skip 5s back / forward (30s with shift)\n",
+    "0-9          - Jump to that tenth of the track\n",
+    ";            - Set loop point A\n",
+    "'            - Set loop point B and loop between A and B\n",
+    "/            - Clear the loop\n",
+    "<RIGHT-CLICK> on the progress bar - Set A, then B\n\n",
     "----------------- VISUAL MODES ---------------------\n\n",
     "v            - Cycle through visual modes (forward)\n",
     "b            - Cycle through visual modes (backward)\n",
+    "x            - Cycle oscilloscope views (mono, split L/R, XY)\n",
+    "w            - Cycle through FFT windows\n",
+    "d            - Toggle dB / linear magnitude\n",
+    "t            - Toggle 1/f tilt compensation\n",
+    "a            - Toggle peak / average band aggregation\n",
+    "c            - Cycle channel downmix (mid, left, right, max)\n",
+    "l            - Cycle stereo spectra (off, left/right, mid/side)\n",
+    "h            - Cycle color themes\n",
+    "g            - Cycle bar colors (accents, gradient by frequency / amplitude)\n",
+    "k            - Cycle spectrogram colormaps (viridis, magma, theme gradient)\n",
+    "F12          - Save a screenshot\n",
+    "F11          - Start / stop recording a PNG sequence\n",
+    "F10          - Toggle the HUD in screenshots and recordings\n",
     "?            - Display the list of available commands",
 ];
 
-fn fft(inp: &[f32], stride: usize, out: &mut [Complex32], n: usize) {
-    std::assert!(n > 0);
-
-    if n == 1 {
-        out[0] = Complex32::new(inp[0], 0.0);
-        return;
-    }
-
-    fft(&inp, stride * 2, &mut out[..n / 2], n / 2);
-    fft(&inp[stride..], stride * 2, &mut out[n / 2..], n / 2);
-
-    for k in 0..n / 2 {
-        let t = k as f32 / n as f32;
-        let v = Complex32::new(0.0, -2.0 * PI * t).exp() * out[k + n / 2];
-        let e = out[k];
-        out[k] = e + v;
-        out[k + n / 2] = e - v;
-    }
-}
-
-fn amp(z: Complex32) -> f32 {
-    let a = z.re.abs();
-    let b = z.im;
-    if a < b {
-        a
-    } else {
-        b
-    }
-}
-
-fn SwitchVizualizationModeForward() {
-    unsafe {
-        let next_mode = (currentMode.as_usize() + 1) % NUM_MODES;
-        currentMode = VisualizationMode::from_usize(next_mode);
-    }
-}
-
-fn SwitchVizualizationModeBackward() {
-    unsafe {
-        let next_mode = (currentMode.as_usize() - 1) % NUM_MODES;
-        currentMode = VisualizationMode::from_usize(next_mode);
-    }
-}
-
 unsafe extern "C" fn callback(bufferData: *mut std::ffi::c_void, frames: u32) {
     unsafe {
-        // Cast bufferData to a raw pointer to f32 and then create a slice of it.
-        let data = bufferData as *mut f32;
-
-        // Number of elements (since we're dealing with stereo, it's frames * 2).
-        let num_samples = (frames * 2) as usize;
-
-        // Create a mutable slice from the raw pointer.
-        let fs: &mut [f32] = std::slice::from_raw_parts_mut(data, num_samples);
-
-        // Process the samples frame by frame (stereo).
-        for i in 0..frames as usize {
-            // For each frame, access left and right channels.
-            let left_channel = fs[i * 2]; // Left channel is at even index.
-            let right_channel = fs[i * 2 + 1]; // Right channel is at odd index.
-
-            // Copy the previous elements (shift left by 1) in the input array.
-            input.copy_within(1..N, 0);
-
-            // Average the stereo channels and store it at the last position in the input buffer.
-            input[N - 1] = (left_channel + right_channel) / 2.0;
-        }
-
-        // Perform FFT on the input.
-        fft(&input, 1, &mut output, N);
-
-        // Initialize max_amp.
-        max_amp = 0.0;
-
-        // Find the maximum amplitude from the FFT output.
-        for i in 0..frames as usize {
-            let a: f32 = amp(output[i]);
-            if a > max_amp {
-                max_amp = a;
-            }
-        }
-    }
-}
-
-fn is_song_file(filename: &str) -> bool {
-    let extensions = [".mp3", ".wav", ".ogg", ".flac", ".aac"];
-    for ext in extensions.iter() {
-        if filename.ends_with(ext) {
-            return true;
+        // The Player's stream only ever carries Decoder output, every file resampled to
+        // ENGINE_SAMPLE_RATE stereo f32, so mono and integer files arrive here converted. The EQ
+        // filters it in place, so the analysis below sees the same post-EQ signal that is heard.
+        let samples = std::slice::from_raw_parts_mut(bufferData as *mut f32, frames as usize * 2);
+        if let Some(equalizer) = EQUALIZER.get() {
+            (*equalizer.0.get()).process(samples);
         }
+        for_each_frame(samples, downmixMode, |mixed, left, right| {
+            SAMPLE_RING.push(mixed);
+            LEFT_RING.push(left);
+            RIGHT_RING.push(right);
+        });
     }
-    return false;
 }
 
-fn DrawCoolRectangle(x: f32, y: f32, width: f32, height: f32, color: raylib::ffi::Color) {
-    unsafe {
-        DrawRectangle(x as i32, y as i32, width as i32, height as i32, color);
-        DrawRectangleLines(
-            x as i32,
-            y as i32,
-            width as i32,
-            height as i32,
-            ColorAlpha(color, 0.3),
-        );
-        DrawCircle(
-            (x + width / 2.0) as i32,
-            y as i32,
-            width / 4.0,
-            ColorAlpha(color, 0.2),
-        );
-    }
+// Designs the filters for `settings` and hands them to the audio callback
+fn apply_equalizer(control: &mut EqControl, settings: &EqSettings) {
+    control.set(settings.design(ENGINE_SAMPLE_RATE));
 }
 
 fn isMouseOverRectangle(rect: Rectangle) -> bool {
@@ -326,176 +243,91 @@ fn OpenFileDialog() -> Option<String> {
     file_name
 }
 
-fn handleVisualization(cell_width: f32, screenHeight: i32, screenWidth: i32, m: usize) {
+// Horizontal -1..+1 meter for the stereo correlation, filled from the centre towards the value
+fn DrawCorrelationMeter(font: Font, correlation: f32, screenWidth: i32) {
     unsafe {
-        let center: Vector2 = Vector2 {
-            x: (screenWidth / 2) as f32,
-            y: (screenHeight / 2) as f32,
-        }; // Calculating the
-           // center point for drawing
-        let mut step = 0.4;
-        let mut maxAmplitude: f32 = 0.0;
-        if max_amp > 0.0 {
-            maxAmplitude = max_amp;
-        } else {
-            maxAmplitude = 1.0;
-        }
-
-        // Calculate amplitude for all points at once
-        let mut amplitudes: [f32; N] = [0.0; N];
-        for i in 0..N {
-            amplitudes[i] = amp(output[i]) / maxAmplitude; // Normalize amplitude
-        }
-
-        // For storing previous amplitudes for smoothign
-        static mut previousAmplitudes: [f32; N] = [0.0; N];
-
-        for i in 0..N - 1 {
-            if amplitudes[i] > 0.01 {
-                match currentMode {
-                    VisualizationMode::STANDARD => DrawCoolRectangle(
-                        (i as f32) * cell_width,
-                        (screenHeight as f32) - (screenHeight as f32) * amplitudes[i],
-                        cell_width * step,
-                        (screenHeight as f32) * amplitudes[i],
-                        GRUVBOX_RED,
-                    ),
-
-                    VisualizationMode::PIXEL => {
-                        step = 1.06;
-                        DrawCoolRectangle(
-                            (i as f32) * cell_width,
-                            (screenHeight as f32) - (screenHeight as f32) * amplitudes[i],
-                            cell_width * step,
-                            (screenHeight as f32) * amplitudes[i],
-                            GRUVBOX_PURPLE,
-                        );
-                    }
-
-                    // TODO: Fix this somehow``
-                    VisualizationMode::WAVEFORM => {
-                        let start: Vector2 = Vector2 {
-                            x: (i as f32) * cell_width,
-                            y: center.y + ((screenHeight / 2) as f32) * amplitudes[i],
-                        };
-                        let end: Vector2 = Vector2 {
-                            x: (i as f32 + 1.0) * cell_width,
-                            y: center.y + ((screenHeight / 2) as f32) * amplitudes[i + 1],
-                        };
-                        DrawLineEx(start, end, 2.0, GRUVBOX_BLUE);
-                    }
-
-                    VisualizationMode::STARBURST => {
-                        let angle: f32 = i as f32 * 360.0 / m as f32; // Calculate angle for each ray wrt freuency
-                                                                      // range
-                        let end: Vector2 = Vector2 {
-                            x: center.x
-                                + deg2rad(angle).cos()
-                                    * amplitudes[i]
-                                    * ((screenHeight / 2) as f32),
-                            y: center.y
-                                + deg2rad(angle).sin()
-                                    * amplitudes[i]
-                                    * ((screenHeight / 2) as f32),
-                        };
-
-                        // Selecting a color based on the index
-                        let mut rayColor: raylib::ffi::Color = GRUVBOX_YELLOW;
-                        match i % 6 {
-                            0 => rayColor = GRUVBOX_YELLOW,
-                            1 => rayColor = GRUVBOX_BLUE,
-                            2 => rayColor = GRUVBOX_GREEN,
-                            3 => rayColor = GRUVBOX_RED,
-                            4 => rayColor = GRUVBOX_ORANGE,
-                            5 => rayColor = GRUVBOX_PURPLE,
-                            _ => rayColor = GRUVBOX_YELLOW,
-                        }
-
-                        DrawLineEx(center, end, 2.0, rayColor);
-                    }
+        let bar = Rectangle {
+            x: (screenWidth - 260) as f32,
+            y: 150.0,
+            width: 240.0,
+            height: 10.0,
+        };
+        let middle = bar.x + bar.width / 2.0;
+        let end = middle + correlation.clamp(-1.0, 1.0) * bar.width / 2.0;
 
-                    VisualizationMode::RADIAL_BARS => {
-                        let angle = i as f32 * 360.0 / m as f32; // Calculate angle for each bar wrt audio
-                                                                 // frequency range
-                        let innerRadius = screenHeight / 8; // Radius for inner circle
-                        let outerRadius = screenHeight / 4; // Base radius for bars
-                        let amplitudeScale = screenHeight / 4; // Scaling factor for amplitude
-
-                        // Draw the inner circle
-                        DrawCircle(
-                            center.x as i32,
-                            center.y as i32,
-                            innerRadius as f32,
-                            GRUVBOX_FG,
-                        );
-                        DrawCircleLines(
-                            center.x as i32,
-                            center.y as i32,
-                            innerRadius as f32,
-                            GRUVBOX_FG,
-                        );
-                        let start: Vector2 = Vector2 {
-                            x: center.x + deg2rad(angle).cos() * (outerRadius as f32),
-                            y: center.y + deg2rad(angle).sin() * (outerRadius as f32),
-                        };
-
-                        // Use a smoothed amplitude value - by taking the average of previous and current amplitudes
-                        let smoothedAmplitude = (previousAmplitudes[i] + amplitudes[i]) * 0.5; // Simple
-                                                                                               // averaging
-                        previousAmplitudes[i] = smoothedAmplitude; // Store for next frame
-
-                        let end: Vector2 = Vector2 {
-                            x: center.x
-                                + deg2rad(angle).cos()
-                                    * (outerRadius as f32
-                                        + smoothedAmplitude as f32 * amplitudeScale as f32),
-                            y: center.y
-                                + deg2rad(angle).sin()
-                                    * (outerRadius as f32
-                                        + smoothedAmplitude as f32 * amplitudeScale as f32),
-                        };
-
-                        let mut barColor: raylib::ffi::Color = GRUVBOX_YELLOW;
-                        match i % 6 {
-                            0 => barColor = GRUVBOX_YELLOW,
-                            1 => barColor = GRUVBOX_BLUE,
-                            2 => barColor = GRUVBOX_GREEN,
-                            3 => barColor = GRUVBOX_RED,
-                            4 => barColor = GRUVBOX_ORANGE,
-                            5 => barColor = GRUVBOX_PURPLE,
-                            _ => barColor = GRUVBOX_YELLOW,
-                        }
-                        DrawLineEx(start, end, cell_width * step, barColor); // Draw the radial bar
-                    }
-                }
-            }
-        }
+        DrawTextEx(
+            font,
+            CString::new(format!("Correlation {:+.2}", correlation))
+                .expect("CString new failed")
+                .as_ptr(),
+            Vector2 {
+                x: bar.x,
+                y: bar.y - 22.0,
+            },
+            20.0,
+            1.0,
+            currentTheme.foreground,
+        );
+        DrawRectangleRec(bar, ColorAlpha(currentTheme.foreground, 0.2));
+        DrawRectangleRec(
+            Rectangle {
+                x: middle.min(end),
+                width: (end - middle).abs(),
+                ..bar
+            },
+            // Negative correlation means phase trouble, e.g. cancellation when summed to mono
+            if correlation < 0.0 {
+                currentTheme.red
+            } else {
+                currentTheme.green
+            },
+        );
+        DrawRectangle(
+            middle as i32,
+            bar.y as i32 - 2,
+            1,
+            bar.height as i32 + 4,
+            currentTheme.foreground,
+        );
     }
 }
 
 // TODO: Draw help box
 
 // This is for metadata
+// Truncates on character boundaries since tags are frequently non-ASCII
 fn limit_text(dest: &mut String, src: &str, max_length: usize) {
-    if src.len() > max_length {
-        dest.clear();
-        dest.push_str(&src[..max_length - 3]);
+    dest.clear();
+    if src.chars().count() > max_length {
+        dest.extend(src.chars().take(max_length - 3));
         dest.push_str("...");
     } else {
-        dest.clear();
         dest.push_str(src);
     }
 }
 
-fn DrawSpaceTheme(font: Font, music: Music) {
+fn DrawSpaceTheme(
+    font: Font,
+    source: &SourceFormat,
+    metadata: &MusicMetadata,
+    cover: Option<Texture2D>,
+) {
     unsafe {
-        let boxWidth = 400;
+        let boxWidth = 600;
+        let boxHeight = 340;
         let padding = 40.0;
         let charLimit = 30;
+        let lineHeight = 22.0;
 
         // Draw the outer glowing rectangle for space-themed effect
-        DrawRectangle(15, 95, 410, 210, GRUVBOX_BLUE);
-        DrawRectangle(20, 100, boxWidth, 200, ColorAlpha(GRUVBOX_BG, 0.85));
+        DrawRectangle(15, 95, boxWidth + 10, boxHeight + 10, currentTheme.blue);
+        DrawRectangle(
+            20,
+            100,
+            boxWidth,
+            boxHeight,
+            ColorAlpha(currentTheme.background, 0.85),
+        );
 
         // Draw the title with a Gruvbox-style glowing effect
         DrawTextEx(
@@ -509,159 +341,520 @@ fn DrawSpaceTheme(font: Font, music: Music) {
             },
             24.0,
             2.0,
-            GRUVBOX_YELLOW,
+            currentTheme.yellow,
         );
 
-        // TODO: Display metadata
-
-        //let mut title = String::new();
-        //        let mut artist = String::new();
-        //        let mut album = String::new();
-
-        //        limit_text(
-        //            &mut title,
-        //            if !metadata.title.is_empty() {
-        //                &metadata.titlee
-        //            } else {
-        //                "Unknown"
-        //            },
-        //            charLimit,
-        //        );
-        //        limit_text(
-        //           &mut artist,
-        //            if !metadata.artist.is_empty() {
-        //                &metadata.artist
-        //            } else {
-        //                "Unknown"
-        //            },
-        //            charLimit,
-        //        );
-        //        limit_text(
-        //            &mut album,
-        //            if !metadata.album.is_empty() {
-        //                &metadata.album
-        //            } else {
-        //
-        //"Unknown"
-        //            },
-        //            charLimit,
-        //        );
-
-        let info_text = format!(
-            "Sample Rate: {} Hz\nChannels: {}\nSample Size: {}-bit",
-            music.stream.sampleRate, music.stream.channels, music.stream.sampleSize
-        );
+        let fields: [(&str, &str); 7] = [
+            ("Title", &metadata.title),
+            ("Artist", &metadata.artist),
+            ("Album", &metadata.album),
+            ("Album Artist", &metadata.album_artist),
+            ("Track", &metadata.track),
+            ("Year", &metadata.year),
+            ("Genre", &metadata.genre),
+        ];
+
+        let mut lines: Vec<String> = Vec::new();
+        for &(label, value) in fields.iter() {
+            let mut limited = String::new();
+            limit_text(
+                &mut limited,
+                if !value.is_empty() { value } else { "Unknown" },
+                charLimit - label.len() - 2,
+            );
+            lines.push(format!("{}: {}", label, limited));
+        }
 
-        let c_info_text = CString::new(info_text.clone()).expect("CString::new failed");
+        let totalSeconds = metadata.duration as u32;
+        lines.push(format!(
+            "Duration: {}:{:02}",
+            totalSeconds / 60,
+            totalSeconds % 60
+        ));
+        lines.push(format!("Sample Rate: {} Hz", source.sample_rate));
+        lines.push(format!("Channels: {}", source.channels));
+        lines.push(format!("Sample Format: {}", source.sample_format));
+        lines.push(format!("ReplayGain: {}", metadata.loudness.describe()));
+
+        // raylib's line spacing is smaller than our font size, so each line is drawn on its own
+        for (i, line) in lines.iter().enumerate() {
+            let c_line = CString::new(line.clone()).unwrap_or_default();
+            DrawTextEx(
+                font,
+                c_line.as_ptr(),
+                Vector2 {
+                    x: padding,
+                    y: 150.0 + i as f32 * lineHeight,
+                },
+                20.0,
+                1.0,
+                if i < fields.len() {
+                    currentTheme.foreground
+                } else {
+                    currentTheme.aqua
+                },
+            );
+        }
 
-        DrawTextEx(
-            font,
-            c_info_text.as_ptr(),
-            Vector2 {
-                x: padding,
-                y: 150.0,
-            },
-            20.0,
-            1.0,
-            GRUVBOX_FG,
-        );
+        // Cover art sits to the right of the text, scaled to fit a square slot
+        if let Some(texture) = cover {
+            let slot = 160.0;
+            let scale = slot / (texture.width.max(texture.height) as f32);
+            let width = texture.width as f32 * scale;
+            let height = texture.height as f32 * scale;
+            let dest = Rectangle {
+                x: 20.0 + boxWidth as f32 - padding - slot + (slot - width) / 2.0,
+                y: 150.0 + (slot - height) / 2.0,
+                width,
+                height,
+            };
+            DrawRectangleLines(
+                dest.x as i32 - 2,
+                dest.y as i32 - 2,
+                dest.width as i32 + 4,
+                dest.height as i32 + 4,
+                currentTheme.blue,
+            );
+            DrawTexturePro(
+                texture,
+                Rectangle {
+                    x: 0.0,
+                    y: 0.0,
+                    width: texture.width as f32,
+                    height: texture.height as f32,
+                },
+                dest,
+                Vector2 { x: 0.0, y: 0.0 },
+                0.0,
+                WHITE,
+            );
+        }
 
         // TODO: Stars
 
         // Glowing nebula
         DrawCircleGradient(
-            380,
-            280,
+            580,
+            100 + boxHeight - 20,
             50.0,
-            ColorAlpha(GRUVBOX_AQUA, 0.2),
-            ColorAlpha(GRUVBOX_AQUA, 0.0),
+            ColorAlpha(currentTheme.aqua, 0.2),
+            ColorAlpha(currentTheme.aqua, 0.0),
         );
     }
 }
 
-// TODO: Implement metadata extraction
-
-//fn extract_metadata(filename: String, metadata: &mut Metadata) {
-//    let mut fmt_ctx: AVFormatContext = std::ptr::null();
-//
-//    if avformat_open_input(&mut fmt_ctx, filename, NULL, NULL) < 0{{
-//        println!("Could not open file {}\n", filename);
-//    }
-//
-// Retrieve stream information
-//    if avformat_find_stream_info(&mut fmt_ctx, &mut std::ptr::null()) < 0 {
-//        println!("Could not find stream info\n");
-//        avformat_close_input(&mut fmt_ctx);
-//        return;
-//    }
-
-//   let mut tag: AVDictionaryEntry = std::ptr::null();
-
-// Extract metadata - title, artist, album
-
-//   if tag
-//        == av_dict_get(
-//            &fmt_ctx.metadata,
-//            CString::new("title").expect("CString new failed").as_ptr(),
-//           std::ptr::null,
-//            0,
-//        )
-//    {
-//        metadata.title = String::from(CString::from_ptr(tag.value).to_string_lossy().into_owned());
-//    } else {
-//        metadata.title = String::from("Unknown Title");
-//    }
-
-//    if tag
-//        == av_dict_get(
-//            &fmt_ctx.metadata,
-//            CString::new("artist").expect("CString new failed").as_ptr(),
-//           std::ptr::null(),
-//           0,
-//       )
-//    {
-//        metadata.artist = String::from(CString::from_ptr(tag.value).to_string_lossy().into_owned());
-//    } else {
-//       metadata.artist = String::from("Unknown Artist");
-//  }
-
-//    if tag
-//        == av_dict_get(
-//            &fmt_ctx.metadata,
-//            CString::new("album").expect("CString new failed").as_ptr(),
-//            std::ptr::null(),
-//            0,
-//        )
-//    {
-//        metadata.album = String::from(CString::from_ptr(tag.value).to_string_lossy().into_owned());
-//    } else {
-//        metadata.album = String::from("Unknown Album");
-//    }// Create a CString for the file path
-
-//   metadata.duration = fmt_ctx.duration as f32 / 1000.0;
-
-//    avformat_close_input(*mut *mut fmt_ctx);
-//}
+// Returns the first non-empty value among `keys` (av_dict_get matches case-insensitively)
+fn dict_lookup(dict: &AVDictionary, keys: &[&str]) -> Option<String> {
+    for key in keys {
+        let c_key = CString::new(*key).expect("CString new failed");
+        if let Some(entry) = dict.get(&c_key, None, 0) {
+            let value = entry.value().to_string_lossy().trim().to_string();
+            if !value.is_empty() {
+                return Some(value);
+            }
+        }
+    }
+    None
+}
 
-fn main() {
+// Cover art larger than this is downscaled, the info box never draws it bigger
+const COVER_MAX_SIZE: i32 = 512;
+
+// Decodes the first packet of `stream_index` (the attached picture for audio files, or the
+// only frame of an image file) into RGBA pixels.
+fn decode_picture(fmt_ctx: &mut AVFormatContextInput, stream_index: usize) -> Option<CoverArt> {
+    let codecpar = AVCodecParameters::clone(&fmt_ctx.streams()[stream_index].codecpar());
+    let decoder = AVCodec::find_decoder(codecpar.codec_id)?;
+    let mut decode_ctx = AVCodecContext::new(&decoder);
+    decode_ctx.apply_codecpar(&codecpar).ok()?;
+    decode_ctx.open(None).ok()?;
+
+    // Attached pictures are queued ahead of any audio packet, so this stops almost immediately
+    let packet = loop {
+        match fmt_ctx.read_packet() {
+            Ok(Some(packet)) if packet.stream_index as usize == stream_index => break packet,
+            Ok(Some(_)) => continue,
+            _ => return None,
+        }
+    };
+    decode_ctx.send_packet(Some(&packet)).ok()?;
+    decode_ctx.send_packet(None).ok()?;
+    let frame = decode_ctx.receive_frame().ok()?;
+
+    if frame.width <= 0 || frame.height <= 0 {
+        return None;
+    }
+    let scale = (COVER_MAX_SIZE as f32 / frame.width.max(frame.height) as f32).min(1.0);
+    let width = ((frame.width as f32 * scale) as i32).max(1);
+    let height = ((frame.height as f32 * scale) as i32).max(1);
+
+    let mut rgba = AVFrame::new();
+    rgba.set_width(width);
+    rgba.set_height(height);
+    rgba.set_format(AV_PIX_FMT_RGBA);
+    rgba.alloc_buffer().ok()?;
+
+    let mut sws_ctx = SwsContext::get_context(
+        frame.width,
+        frame.height,
+        frame.format,
+        width,
+        height,
+        AV_PIX_FMT_RGBA,
+        SWS_BILINEAR,
+        None,
+        None,
+        None,
+    )?;
+    sws_ctx
+        .scale_frame(&frame, 0, frame.height, &mut rgba)
+        .ok()?;
+
+    // The frame rows may be padded, raylib expects them packed
+    let row_bytes = width as usize * 4;
+    let mut pixels = Vec::with_capacity(row_bytes * height as usize);
+    for y in 0..height as usize {
+        let row = unsafe {
+            std::slice::from_raw_parts(rgba.data[0].add(y * rgba.linesize[0] as usize), row_bytes)
+        };
+        pixels.extend_from_slice(row);
+    }
+
+    Some(CoverArt {
+        width,
+        height,
+        pixels,
+    })
+}
+
+// Looks for the usual cover image next to the song when it has no embedded picture
+fn load_folder_cover(filename: &str) -> Option<CoverArt> {
+    let dir = std::path::Path::new(filename).parent()?;
+    let candidates = [
+        "cover.jpg",
+        "cover.jpeg",
+        "cover.png",
+        "folder.jpg",
+        "folder.jpeg",
+        "folder.png",
+        "Cover.jpg",
+        "Cover.png",
+        "Folder.jpg",
+        "Folder.png",
+    ];
+
+    for name in candidates.iter() {
+        let path = dir.join(name);
+        if !path.is_file() {
+            continue;
+        }
+        let c_path = CString::new(path.to_string_lossy().into_owned()).ok()?;
+        if let Ok(mut fmt_ctx) = AVFormatContextInput::open(&c_path, None, &mut None) {
+            if fmt_ctx.streams().is_empty() {
+                continue;
+            }
+            if let Some(cover) = decode_picture(&mut fmt_ctx, 0) {
+                return Some(cover);
+            }
+        }
+    }
+    None
+}
+
+fn extract_metadata(filename: &str) -> Option<MusicMetadata> {
+    let c_filename = CString::new(filename).ok()?;
+    let mut fmt_ctx = match AVFormatContextInput::open(&c_filename, None, &mut None) {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            println!("Could not read metadata from {}: {}\n", filename, e);
+            return None;
+        }
+    };
+
+    // Containers like MP3/MP4 keep tags globally while Ogg/FLAC keep them on the audio
+    // stream, so look at the container first and fall back to each stream.
+    let lookup = |keys: &[&str]| -> String {
+        if let Some(value) = fmt_ctx.metadata().and_then(|dict| dict_lookup(&dict, keys)) {
+            return value;
+        }
+        for stream in fmt_ctx.streams() {
+            if let Some(value) = stream.metadata().and_then(|dict| dict_lookup(&dict, keys)) {
+                return value;
+            }
+        }
+        String::new()
+    };
+
+    let mut metadata = MusicMetadata {
+        title: lookup(&["title"]),
+        artist: lookup(&["artist"]),
+        album: lookup(&["album"]),
+        duration: 0.0,
+        track: lookup(&["track", "tracknumber"]),
+        year: lookup(&["date", "year", "originaldate"]),
+        genre: lookup(&["genre"]),
+        album_artist: lookup(&["album_artist", "albumartist", "album artist"]),
+        loudness: LoudnessTags::read(&fmt_ctx),
+        cover: None,
+    };
+
+    // Dates are usually full timestamps (2004-05-01), only the year is interesting here
+    if metadata.year.len() > 4 && metadata.year.is_char_boundary(4) {
+        metadata.year.truncate(4);
+    }
+
+    if fmt_ctx.duration > 0 {
+        metadata.duration = fmt_ctx.duration as f32 / AV_TIME_BASE as f32;
+    }
+
+    // ID3 APIC, FLAC PICTURE and MP4 covr all show up as a stream flagged as an attached picture
+    let cover_stream = fmt_ctx
+        .streams()
+        .iter()
+        .position(|stream| stream.disposition & AV_DISPOSITION_ATTACHED_PIC as i32 != 0);
+    metadata.cover = cover_stream
+        .and_then(|index| decode_picture(&mut fmt_ctx, index))
+        .or_else(|| load_folder_cover(filename));
+
+    Some(metadata)
+}
+
+// Uploads the decoded cover to the GPU, must be called after InitWindow
+fn LoadCoverTexture(cover: &CoverArt) -> Texture2D {
     unsafe {
-        const screenWidth: i32 = 1280;
-        const screenHeight: i32 = 720;
+        let image = raylib::ffi::Image {
+            data: cover.pixels.as_ptr() as *mut std::ffi::c_void,
+            width: cover.width,
+            height: cover.height,
+            mipmaps: 1,
+            format: PixelFormat::PIXELFORMAT_UNCOMPRESSED_R8G8B8A8 as i32,
+        };
+        // The image data stays owned by `cover`, raylib only copies it into the texture
+        let texture = LoadTextureFromImage(image);
+        SetTextureFilter(texture, TextureFilter::TEXTURE_FILTER_BILINEAR as i32);
+        texture
+    }
+}
 
-        let args: Vec<String> = std::env::args().collect();
+// Reads the metadata of `filename` and swaps the cover texture for the new track's
+fn LoadTrackInfo(filename: &str, coverTexture: &mut Option<Texture2D>) -> MusicMetadata {
+    let metadata = extract_metadata(filename).unwrap_or_default();
+    unsafe {
+        if let Some(texture) = coverTexture.take() {
+            UnloadTexture(texture);
+        }
+    }
+    *coverTexture = metadata.cover.as_ref().map(LoadCoverTexture);
+    metadata
+}
 
-        if args.len() > 1 {
-            if is_song_file(&args[1]) {
-                selected_song = args[1].clone();
-                println!("Selected song {}\n", selected_song);
-            } else {
-                println!("Invalid file format. Please select a valid audio file\n");
+// Strip along the top edge of the bottom bar that shows and scrubs the playback position
+fn ProgressBarRect(screenWidth: i32, screenHeight: i32) -> Rectangle {
+    Rectangle {
+        x: 10.0,
+        y: (screenHeight - 38) as f32,
+        width: (screenWidth - 20) as f32,
+        height: 6.0,
+    }
+}
+
+// The looped region over the progress bar, with a marker and its letter at A and B
+fn DrawLoopMarkers(
+    font: Font,
+    progressBar: Rectangle,
+    points: (Option<f32>, Option<f32>),
+    length: f32,
+) {
+    if length <= 0.0 {
+        return;
+    }
+    let x = |seconds: f32| progressBar.x + progressBar.width * (seconds / length).clamp(0.0, 1.0);
+    unsafe {
+        if let (Some(start), Some(end)) = points {
+            DrawRectangleRec(
+                Rectangle {
+                    x: x(start),
+                    width: x(end) - x(start),
+                    ..progressBar
+                },
+                ColorAlpha(currentTheme.yellow, 0.45),
+            );
+        }
+        for (point, label) in [(points.0, "A"), (points.1, "B")] {
+            let Some(seconds) = point else {
+                continue;
+            };
+            DrawRectangleRec(
+                Rectangle {
+                    x: x(seconds) - 1.0,
+                    y: progressBar.y - 6.0,
+                    width: 2.0,
+                    height: progressBar.height + 12.0,
+                },
+                currentTheme.yellow,
+            );
+            DrawTextEx(
+                font,
+                CString::new(label).expect("CString new failed").as_ptr(),
+                Vector2 {
+                    x: x(seconds) - 4.0,
+                    y: progressBar.y - 26.0,
+                },
+                20.0,
+                1.0,
+                currentTheme.yellow,
+            );
+        }
+    }
+}
+
+// Drops everything analysed before a jump in the stream, so the old position's spectrum
+// doesn't flash after the seek
+fn ResetAnalysis(smoothers: &mut [BandSmoother]) {
+    SAMPLE_RING.clear();
+    LEFT_RING.clear();
+    RIGHT_RING.clear();
+    for smoother in smoothers.iter_mut() {
+        smoother.reset();
+    }
+}
+
+fn SeekTo(player: &mut Player, seconds: f32, smoothers: &mut [BandSmoother]) {
+    let length = player.length();
+    // Stop just short of the end, so the seek itself doesn't end the track
+    let target = if length > 0.0 {
+        seconds.clamp(0.0, (length - 0.1).max(0.0))
+    } else {
+        seconds.max(0.0)
+    };
+    player.seek(target);
+    ResetAnalysis(smoothers);
+}
+
+// Plays `path` right away and reloads everything derived from the track. Returns false when
+// the track can't be played, the player then reports it ended on its next update.
+fn SwitchTrack(
+    path: &str,
+    player: &mut Player,
+    metadata: &mut MusicMetadata,
+    coverTexture: &mut Option<Texture2D>,
+) -> bool {
+    println!("Playing {}", path);
+    *metadata = LoadTrackInfo(path, coverTexture);
+    match player.play(path) {
+        Ok(()) => true,
+        Err(e) => {
+            println!("Cannot play {}: {}", path, e);
+            false
+        }
+    }
+}
+
+// The built-in modes, then extra ones from shared objects in the plugins/ directory next to
+// the config file, showing the configured startup mode. Plugins run their setup code on load,
+// so this is called after InitWindow.
+fn LoadVisualizers(config: &Config) -> Registry {
+    let mut visualizers = Registry::default();
+    for visualizer in builtin_visualizers(config.colormap, config.spectrogram_history) {
+        visualizers.register(visualizer);
+    }
+    if let Some(dir) = config_dir() {
+        for plugin in load_plugin_dir(&dir.join("plugins")) {
+            visualizers.register(Box::new(plugin));
+        }
+    }
+    visualizers.select(&config.startup_mode);
+    visualizers
+}
+
+// Saves the requested screenshot and hands the frame to the running recording. Called at the
+// point of the frame the captures should show.
+fn StoreCapture(screenshotPending: &mut bool, recorder: &mut Option<Recorder>) {
+    if !*screenshotPending && recorder.is_none() {
+        return;
+    }
+    let frame = capture_screen();
+    if *screenshotPending {
+        *screenshotPending = false;
+        let path = format!("raven-{}.png", timestamp());
+        match save_png(&frame, std::path::Path::new(&path)) {
+            Ok(()) => println!("Saved screenshot {}", path),
+            Err(e) => println!("Could not save screenshot: {}", e),
+        }
+    }
+    if let Some(recorder) = recorder {
+        recorder.push(frame);
+    }
+}
+
+fn main() {
+    unsafe {
+        // Falls back to the defaults when there is no config file, but a broken one is fatal
+        let config = match Config::load() {
+            Ok(config) => config,
+            Err(e) => {
+                println!("Invalid config file {}", e);
                 return;
             }
-        } else {
+        };
+        let keys = config.keys;
+        let screenWidth: i32 = config.width;
+        let screenHeight: i32 = config.height;
+        let fftSize: usize = config.fft_size;
+        let themes = config.themes.clone();
+        let mut themeIndex = config.startup_theme;
+        currentTheme = themes[themeIndex].1;
+
+        let mut args: Vec<String> = std::env::args().skip(1).collect();
+        // `--render out.mp4` writes the first track's visualization to a video instead of playing
+        let renderPath = match args.iter().position(|arg| arg == "--render") {
+            Some(i) if i + 1 < args.len() => Some(args.drain(i..i + 2).nth(1).unwrap_or_default()),
+            Some(_) => {
+                println!("--render needs an output file, e.g. --render out.mp4");
+                return;
+            }
+            None => None,
+        };
+        // `--scan` measures the loudness of files without ReplayGain tags instead of playing
+        let scanOnly = match args.iter().position(|arg| arg == "--scan") {
+            Some(i) => {
+                args.remove(i);
+                true
+            }
+            None => false,
+        };
+
+        // Every other argument may be a song file or a directory of them
+        let mut playlist = Playlist::new();
+        for arg in args.iter() {
+            if playlist.add_path(arg) == 0 {
+                println!("Skipping {}: not a supported audio file or directory", arg);
+            }
+        }
+
+        if playlist.is_empty() {
             println!("No file selected. Please select a valid audio file\n");
             return;
         }
+        println!("Loaded {} track(s)\n", playlist.len());
+
+        if scanOnly {
+            let paths: Vec<String> = playlist.queue().into_iter().map(|t| t.path).collect();
+            if let Err(e) = loudness::scan(&paths) {
+                println!("Cannot save the scan: {}", e);
+            }
+            return;
+        }
+
+        if let Some(path) = renderPath {
+            let track = playlist.current().unwrap_or_default().to_string();
+            println!("Rendering {} to {}", track, path);
+            match render::render_to_file(&track, &path, &config) {
+                Ok(()) => println!("Wrote {}", path),
+                Err(e) => println!("Cannot render {}: {}", track, e),
+            }
+            return;
+        }
 
         InitWindow(
             screenWidth,
@@ -670,26 +863,62 @@ fn main() {
                 .expect("CString new failed")
                 .as_ptr(),
         );
-        SetTargetFPS(60);
+        SetTargetFPS(config.fps);
         InitAudioDevice();
 
-        let mut music: Music = LoadMusicStream(
-            CString::new(selected_song.clone())
-                .expect("CString new failed")
-                .as_ptr(),
-        );
-        std::assert!(music.stream.sampleSize == 32);
-        std::assert!(music.stream.channels == 2);
+        let mut visualizers = LoadVisualizers(&config);
+
+        // In place before the player attaches the stream processor that runs it
+        let (equalizer, mut eqControl) = Equalizer::new();
+        EQUALIZER
+            .set(CallbackEqualizer(UnsafeCell::new(equalizer)))
+            .ok();
+        let mut player = Player::new(config.transition, config.gain_mode, config.preamp);
+        player.set_volume(config.volume);
+
+        let mut eqSettings = config.equalizer.clone();
+        let mut eqPreset = config.eq_preset;
+        let mut eqOverlay = EqOverlay::default();
+        apply_equalizer(&mut eqControl, &eqSettings);
+
+        let firstTrack = playlist.current().unwrap_or_default().to_string();
+        let mut coverTexture: Option<Texture2D> = None;
+        let mut metadata = MusicMetadata::default();
+        // Tracks that failed to load in a row, so a queue of unplayable files doesn't spin.
+        // An unplayable first track is skipped by auto-advance.
+        let mut failedTracks: usize =
+            if SwitchTrack(&firstTrack, &mut player, &mut metadata, &mut coverTexture) {
+                0
+            } else {
+                1
+            };
 
-        let mut currentVolume: f32 = 0.8;
+        let mut currentVolume: f32 = config.volume;
         let mut isMuted: bool = false;
-
-        SetMusicVolume(music, currentVolume);
-        PlayMusicStream(music);
-        AttachAudioStreamProcessor(music.stream, Some(callback));
+        let mut isPaused: bool = false;
+
+        // Bars are log-spaced from 20 Hz, each one 6% above the previous
+        let m: usize = band_count(BAND_LOW_HZ, BAND_STEP, fftSize);
+
+        let spectrumSettings = Arc::new(Mutex::new(SpectrumSettings {
+            sample_rate: ENGINE_SAMPLE_RATE,
+            bands: m,
+            ..SpectrumSettings::default()
+        }));
+        let (spectrumWriter, mut spectrumReader) = snapshot_channel(SpectrumFrame::new(fftSize));
+        let stopAnalysis = Arc::new(AtomicBool::new(false));
+        let analysisThread = spawn_analysis_thread(
+            &SAMPLE_RING,
+            &LEFT_RING,
+            &RIGHT_RING,
+            fftSize,
+            spectrumWriter,
+            spectrumSettings.clone(),
+            stopAnalysis.clone(),
+        );
 
         // Create a CString for the file path
-        let font_path = CString::new("resources/fonts/monogram.ttf").expect("CString failed");
+        let font_path = CString::new(config.font_path.clone()).expect("CString failed");
 
         // Load the font using the correct arguments
         let font = LoadFontEx(
@@ -712,47 +941,104 @@ fn main() {
             width: 60.0,
             height: 30.0,
         };
+        // Downmix, then the two channels of the stereo view
+        let mut smoothers = [
+            BandSmoother::new(SmoothingSettings::default()),
+            BandSmoother::new(SmoothingSettings::default()),
+            BandSmoother::new(SmoothingSettings::default()),
+        ];
+        // Eased towards the analysed value so the meter is readable
+        let mut correlation: f32 = 0.0;
+        let mut scope = Oscilloscope::new();
         let mut showInfo: bool = false;
         let mut showHelp: bool = false;
+        // Set while the progress bar is being dragged, holds the previewed position
+        let mut scrubPosition: Option<f32> = None;
+        // Whether screenshots and recordings show the title, time bar and buttons
+        let mut captureHud: bool = true;
+        let mut screenshotPending: bool = false;
+        let mut recorder: Option<Recorder> = None;
 
         while !WindowShouldClose() {
-            UpdateMusicStream(music);
+            let playerEvent = player.update();
 
-            if IsKeyPressed(KEY_SPACE as i32) {
-                if IsMusicStreamPlaying(music) {
-                    PauseMusicStream(music);
+            if IsKeyPressed(keys.pause) {
+                if player.is_playing() {
+                    player.pause();
+                    isPaused = true;
                 } else {
-                    ResumeMusicStream(music);
+                    player.resume();
+                    isPaused = false;
                 }
             }
 
-            if IsKeyPressed(KEY_Q as i32) {
-                break;
+            // The track to switch to this frame, if any
+            let mut nextTrack: Option<String> = None;
+
+            match playerEvent {
+                // The player went on to the preloaded track by itself, the playlist follows.
+                // The analysis and smoothing carry on through the transition.
+                Some(PlayerEvent::Advanced) => {
+                    if let Some(path) = playlist.advance() {
+                        println!("Playing {}", path);
+                        metadata = LoadTrackInfo(path, &mut coverTexture);
+                    }
+                    failedTracks = 0;
+                }
+                Some(PlayerEvent::Ended) if !isPaused => match playlist.advance() {
+                    Some(path) => nextTrack = Some(path.to_string()),
+                    None => {
+                        // End of the playlist, stay on the last track until the user acts
+                        player.pause();
+                        isPaused = true;
+                    }
+                },
+                _ => {}
             }
 
-            if IsFileDropped() {
-                PauseMusicStream(music);
-                let droppedFiles: FilePathList = LoadDroppedFiles();
-                println!("File Dropped\n");
+            if IsKeyPressed(keys.next_track) {
+                nextTrack = playlist.next_track().map(String::from);
+            }
 
-                let file_path_ptr = *droppedFiles.paths;
-                let c_str = CStr::from_ptr(file_path_ptr.clone());
-                let file_path = c_str.to_string_lossy().into_owned();
+            if IsKeyPressed(keys.previous_track) {
+                nextTrack = playlist.previous_track().map(String::from);
+            }
 
-                println!("Dropped File Path: {}", file_path);
+            if IsKeyPressed(keys.shuffle) {
+                playlist.toggle_shuffle();
+            }
 
-                // Load new music stream
-                StopMusicStream(music);
-                UnloadMusicStream(music);
+            if IsKeyPressed(keys.repeat) {
+                playlist.repeat = playlist.repeat.next();
+            }
 
-                let c_string = CString::new(file_path.clone()).expect("CString failed");
-                music = LoadMusicStream(c_string.as_ptr());
+            if IsKeyPressed(keys.export_queue) {
+                match save_m3u8(QUEUE_EXPORT_PATH, &playlist.queue()) {
+                    Ok(()) => println!("Saved queue to {}", QUEUE_EXPORT_PATH),
+                    Err(e) => println!("Could not save queue to {}: {}", QUEUE_EXPORT_PATH, e),
+                }
+            }
 
-                PlayMusicStream(music);
-                SetMusicVolume(music, currentVolume);
+            if IsKeyPressed(keys.quit) {
+                break;
+            }
+
+            if IsFileDropped() {
+                let droppedFiles: FilePathList = LoadDroppedFiles();
+                println!("File Dropped\n");
+
+                // Queue every dropped file (or directory) and start the first one
+                let mut added = 0;
+                for i in 0..droppedFiles.count as usize {
+                    let c_str = CStr::from_ptr(*droppedFiles.paths.add(i));
+                    let file_path = c_str.to_string_lossy().into_owned();
+                    println!("Dropped File Path: {}", file_path);
+                    added += playlist.add_path(&file_path);
+                }
+                if let Some(path) = playlist.jump_to_added(added) {
+                    nextTrack = Some(path.to_string());
+                }
 
-                // Attach the callback processor
-                AttachAudioStreamProcessor(music.stream, Some(callback));
                 UnloadDroppedFiles(droppedFiles);
             }
 
@@ -760,68 +1046,303 @@ fn main() {
                 showInfo = !showInfo;
             }
 
-            if IsKeyPressed(KEY_F as i32) {
-                PauseMusicStream(music);
-                OpenFileDialog();
-                if is_song_file(&selected_song) {
-                    UnloadMusicStream(music);
-                    music = LoadMusicStream(
-                        CString::new(selected_song.clone())
-                            .expect("CString new failed")
-                            .as_ptr(),
-                    );
-                    PlayMusicStream(music);
-                    SetMusicVolume(music, currentVolume);
-                    AttachAudioStreamProcessor(music.stream, Some(callback));
+            if IsKeyPressed(keys.add_file) {
+                player.pause();
+                let added = OpenFileDialog()
+                    .map(|path| playlist.add_path(&path))
+                    .unwrap_or(0);
+                if let Some(path) = playlist.jump_to_added(added) {
+                    nextTrack = Some(path.to_string());
                 } else {
                     println!("Invalid file format. Please select a valid audio file\n");
-                    ResumeMusicStream(music);
+                    if !isPaused {
+                        player.resume();
+                    }
                 }
             }
 
+            if let Some(path) = nextTrack {
+                let played = SwitchTrack(&path, &mut player, &mut metadata, &mut coverTexture);
+                for smoother in smoothers.iter_mut() {
+                    smoother.reset();
+                }
+                // A failed track is skipped by auto-advance on the next frame, unless every
+                // track in the queue has failed in a row. Repeat one would only retry it.
+                failedTracks = if played { 0 } else { failedTracks + 1 };
+                if failedTracks > 0 && playlist.repeat == RepeatMode::One {
+                    println!("The repeated track can't be played");
+                    isPaused = true;
+                    player.pause();
+                } else {
+                    isPaused = failedTracks >= playlist.len();
+                    if isPaused {
+                        println!("None of the queued tracks can be played");
+                        player.pause();
+                    }
+                }
+            }
+            // Opened ahead so the player can go on without a gap
+            player.preload(playlist.peek_next());
+
             if IsMouseButtonPressed(MOUSE_BUTTON_LEFT as i32) && isMouseOverRectangle(helpButton) {
                 showHelp = !showHelp;
             }
 
-            if IsKeyPressed(KEY_UP as i32) {
+            // Progress bar: click or drag to scrub, the seek happens on release
+            let progressBar = ProgressBarRect(screenWidth, screenHeight);
+            let progressHitbox = Rectangle {
+                y: progressBar.y - 6.0,
+                height: progressBar.height + 12.0,
+                ..progressBar
+            };
+            let mouseFraction =
+                ((GetMousePosition().x - progressBar.x) / progressBar.width).clamp(0.0, 1.0);
+            if IsMouseButtonPressed(MOUSE_BUTTON_LEFT as i32)
+                && isMouseOverRectangle(progressHitbox)
+            {
+                scrubPosition = Some(mouseFraction);
+            }
+            if scrubPosition.is_some() {
+                if IsMouseButtonDown(MOUSE_BUTTON_LEFT as i32) {
+                    scrubPosition = Some(mouseFraction);
+                } else if let Some(fraction) = scrubPosition.take() {
+                    let target = fraction * player.length();
+                    SeekTo(&mut player, target, &mut smoothers);
+                }
+            }
+            // Right clicks place A, then B. Once both are set the next one starts a new loop.
+            if IsMouseButtonPressed(MOUSE_BUTTON_RIGHT as i32)
+                && isMouseOverRectangle(progressHitbox)
+            {
+                let target = mouseFraction * player.length();
+                match player.loop_points() {
+                    (Some(_), None) => player.set_loop_end(target),
+                    _ => {
+                        player.clear_loop();
+                        player.set_loop_start(target);
+                    }
+                }
+            }
+
+            // Arrows skip 5 seconds, 30 with shift held
+            let skip = if IsKeyDown(KEY_LEFT_SHIFT as i32) || IsKeyDown(KEY_RIGHT_SHIFT as i32) {
+                30.0
+            } else {
+                5.0
+            };
+            if IsKeyPressed(keys.seek_forward) {
+                let target = player.position() + skip;
+                SeekTo(&mut player, target, &mut smoothers);
+            }
+            if IsKeyPressed(keys.seek_back) {
+                let target = player.position() - skip;
+                SeekTo(&mut player, target, &mut smoothers);
+            }
+
+            // Number keys jump to that tenth of the track
+            for digit in 0..10 {
+                if IsKeyPressed(KEY_ZERO as i32 + digit) {
+                    let target = player.length() * digit as f32 / 10.0;
+                    SeekTo(&mut player, target, &mut smoothers);
+                }
+            }
+
+            if IsKeyPressed(keys.volume_up) {
                 currentVolume += 0.1;
                 if currentVolume > 1.0 {
                     currentVolume = 1.0;
                 }
-                SetMusicVolume(music, currentVolume);
+                player.set_volume(currentVolume);
                 isMuted = false;
             }
 
-            if IsKeyPressed(KEY_DOWN as i32) {
+            if IsKeyPressed(keys.volume_down) {
                 currentVolume -= 0.1;
                 if currentVolume < 0.0 {
                     currentVolume = 0.0;
                 }
-                SetMusicVolume(music, currentVolume);
+                player.set_volume(currentVolume);
                 isMuted = false;
             }
 
-            if IsKeyPressed(KEY_V as i32) {
-                SwitchVizualizationModeForward();
+            if IsKeyPressed(keys.next_mode) {
+                visualizers.select_next();
+            }
+
+            if IsKeyPressed(keys.previous_mode) {
+                visualizers.select_previous();
+            }
+
+            if IsKeyPressed(keys.scope_view) {
+                scope.view = scope.view.next();
+            }
+
+            if IsKeyPressed(keys.window) {
+                let mut settings = spectrumSettings.lock().unwrap();
+                settings.window = settings.window.next();
+            }
+
+            if IsKeyPressed(keys.db_scale) {
+                let mut settings = spectrumSettings.lock().unwrap();
+                settings.db_scale = !settings.db_scale;
+            }
+
+            if IsKeyPressed(keys.tilt) {
+                let mut settings = spectrumSettings.lock().unwrap();
+                settings.tilt = if settings.tilt != 0.0 {
+                    0.0
+                } else {
+                    SpectrumSettings::default().tilt
+                };
+            }
+
+            if IsKeyPressed(keys.stereo) {
+                let mut settings = spectrumSettings.lock().unwrap();
+                settings.stereo = settings.stereo.next();
+            }
+
+            if IsKeyPressed(keys.theme) {
+                themeIndex = (themeIndex + 1) % themes.len();
+                currentTheme = themes[themeIndex].1;
+            }
+
+            if IsKeyPressed(keys.gradient) {
+                gradientMode = gradientMode.next();
+            }
+
+            if IsKeyPressed(keys.colormap) {
+                visualizers.next_colormap();
+            }
+
+            if IsKeyPressed(keys.screenshot) {
+                screenshotPending = true;
+            }
+
+            if IsKeyPressed(keys.record) {
+                match recorder.take() {
+                    Some(finished) => {
+                        println!(
+                            "Recorded {} frames to {}",
+                            finished.frames,
+                            finished.dir.display()
+                        );
+                        finished.finish();
+                    }
+                    None => match Recorder::start(format!("raven-{}", timestamp()).into()) {
+                        Ok(started) => {
+                            println!("Recording to {}", started.dir.display());
+                            recorder = Some(started);
+                        }
+                        Err(e) => println!("Could not start recording: {}", e),
+                    },
+                }
+            }
+
+            if IsKeyPressed(keys.capture_hud) {
+                captureHud = !captureHud;
+                println!(
+                    "Captures {} the HUD",
+                    if captureHud { "include" } else { "leave out" }
+                );
+            }
+
+            if IsKeyPressed(keys.transition) {
+                player.transition = match player.transition {
+                    Transition::Gapless => Transition::Crossfade(config.crossfade),
+                    Transition::Crossfade(_) => Transition::Gapless,
+                };
+            }
+
+            if IsKeyPressed(keys.equalizer) {
+                eqOverlay.visible = !eqOverlay.visible;
+            }
+
+            if IsKeyPressed(keys.eq_preset) {
+                let next = eqPreset.map_or(0, |i| (i + 1) % EQ_PRESETS.len());
+                eqSettings.graphic = EQ_PRESETS[next].1;
+                eqPreset = Some(next);
+                apply_equalizer(&mut eqControl, &eqSettings);
+            }
+
+            if IsKeyPressed(keys.eq_bypass) {
+                eqSettings.enabled = !eqSettings.enabled;
+                apply_equalizer(&mut eqControl, &eqSettings);
+            }
+
+            if eqOverlay.update(&mut eqSettings, screenWidth, screenHeight) {
+                // A graphic curve changed by hand is no longer the preset
+                if eqPreset.is_some_and(|i| EQ_PRESETS[i].1 != eqSettings.graphic) {
+                    eqPreset = None;
+                }
+                apply_equalizer(&mut eqControl, &eqSettings);
+            }
+
+            if IsKeyPressed(keys.loudness) {
+                player.set_gain_mode(player.gain_mode().next());
+            }
+
+            let speed = player.speed();
+            let changedSpeed = if IsKeyPressed(keys.tempo_down) {
+                speed.step_tempo(-1)
+            } else if IsKeyPressed(keys.tempo_up) {
+                speed.step_tempo(1)
+            } else if IsKeyPressed(keys.pitch_down) {
+                speed.step_pitch(-1)
+            } else if IsKeyPressed(keys.pitch_up) {
+                speed.step_pitch(1)
+            } else if IsKeyPressed(keys.varispeed_down) {
+                speed.step_varispeed(-1)
+            } else if IsKeyPressed(keys.varispeed_up) {
+                speed.step_varispeed(1)
+            } else if IsKeyPressed(keys.speed_reset) {
+                Speed::default()
+            } else {
+                speed
+            };
+            if changedSpeed != speed {
+                player.set_speed(changedSpeed);
+            }
+
+            if IsKeyPressed(keys.loop_start) {
+                player.set_loop_start(player.position());
+            }
+
+            if IsKeyPressed(keys.loop_end) {
+                player.set_loop_end(player.position());
             }
 
-            if IsKeyPressed(KEY_B as i32) {
-                SwitchVizualizationModeBackward();
+            if IsKeyPressed(keys.loop_clear) {
+                player.clear_loop();
             }
 
-            if IsKeyPressed(KEY_M as i32) {
+            if IsKeyPressed(keys.downmix) {
+                downmixMode = downmixMode.next();
+            }
+
+            if IsKeyPressed(keys.aggregation) {
+                let mut settings = spectrumSettings.lock().unwrap();
+                settings.aggregation = match settings.aggregation {
+                    BandAggregation::Peak => BandAggregation::Average,
+                    BandAggregation::Average => BandAggregation::Peak,
+                };
+            }
+
+            if IsKeyPressed(keys.mute) {
                 isMuted = !isMuted;
                 if isMuted {
-                    SetMusicVolume(music, 0.0);
+                    player.set_volume(0.0);
                 } else {
-                    SetMusicVolume(music, currentVolume);
+                    player.set_volume(currentVolume);
                 }
             }
 
             BeginDrawing();
-            ClearBackground(BLACK);
+            ClearBackground(currentTheme.background);
 
             BeginTextureMode(overlay);
+            // Cleared first, otherwise the translucent fill builds up over the frames into
+            // opaque gray that hides the theme's background
+            ClearBackground(ColorAlpha(BLACK, 0.0));
             DrawRectangle(0, 0, screenWidth, screenHeight, ColorAlpha(GRAY, 0.2));
             EndTextureMode();
             DrawTextureRec(
@@ -836,17 +1357,30 @@ fn main() {
                 WHITE,
             );
 
-            let mut m: usize = 0;
-            let mut step: f32 = 1.06;
-            let mut i: f32 = 20.0; // Declare i as a mutable f32
-
-            while i < N as f32 {
-                m += 1; // Increment m
-                i *= step; // Update i
+            let spectrum = spectrumReader.read();
+            smoothers[0].update(&spectrum.bands, GetFrameTime());
+            smoothers[1].update(&spectrum.channel_bands[0], GetFrameTime());
+            smoothers[2].update(&spectrum.channel_bands[1], GetFrameTime());
+            correlation +=
+                (spectrum.correlation - correlation) * (1.0 - (-GetFrameTime() / 0.15).exp());
+            if visualizers.wants_scope() {
+                scope.capture(&LEFT_RING, &RIGHT_RING);
+            }
+            visualizers.draw(&VisualFrame {
+                spectrum,
+                smoothers: &smoothers,
+                scope: &scope,
+                band_count: m,
+                sample_rate: spectrumSettings.lock().unwrap().sample_rate,
+                width: screenWidth,
+                height: screenHeight,
+                delta: GetFrameTime(),
+                theme: currentTheme,
+                gradient: gradientMode,
+            });
+            if !captureHud {
+                StoreCapture(&mut screenshotPending, &mut recorder);
             }
-
-            let cell_width: f32 = screenWidth as f32 / m as f32;
-            handleVisualization(cell_width, screenHeight, screenWidth, m);
 
             let mainTitle = String::from("Rusty rAVen");
             let titleSize: Vector2 = MeasureTextEx(
@@ -868,11 +1402,11 @@ fn main() {
                 },
                 40.0,
                 2.0,
-                GRUVBOX_BLUE,
+                currentTheme.blue,
             );
 
-            let totalDuration = GetMusicTimeLength(music) as f32;
-            let currentDuration = GetMusicTimePlayed(music) as f32;
+            let totalDuration = player.length();
+            let currentDuration = player.position();
             let time_buffer = format!("{:.2} / {:.2} sec", currentDuration, totalDuration);
             let details_size: Vector2 = MeasureTextEx(
                 font,
@@ -889,22 +1423,85 @@ fn main() {
                 40,
                 ColorAlpha(BLACK, 0.7),
             );
+
+            // Draw the progress bar, following the mouse while it is dragged
+            let progressBar = ProgressBarRect(screenWidth, screenHeight);
+            let progress = match scrubPosition {
+                Some(fraction) => fraction,
+                None if totalDuration > 0.0 => (currentDuration / totalDuration).clamp(0.0, 1.0),
+                None => 0.0,
+            };
+            DrawRectangleRec(progressBar, ColorAlpha(currentTheme.foreground, 0.2));
+            DrawRectangleRec(
+                Rectangle {
+                    width: progressBar.width * progress,
+                    ..progressBar
+                },
+                currentTheme.blue,
+            );
+            DrawLoopMarkers(font, progressBar, player.loop_points(), totalDuration);
+            DrawCircle(
+                (progressBar.x + progressBar.width * progress) as i32,
+                (progressBar.y + progressBar.height / 2.0) as i32,
+                6.0,
+                if scrubPosition.is_some() {
+                    currentTheme.yellow
+                } else {
+                    currentTheme.foreground
+                },
+            );
+
             DrawTextEx(
                 font,
                 CString::new(time_buffer.clone())
                     .expect("CString new failed")
                     .as_ptr(),
                 Vector2 {
-                    x: screenWidth as f32,
-                    y: screenHeight as f32 - 30.0,
+                    x: screenWidth as f32 - details_size.x - 10.0,
+                    y: screenHeight as f32 - 26.0,
                 },
                 20.0,
                 1.0,
                 WHITE,
             );
 
+            // Draw the queue position and playback order
+            let gainLabel = match (player.gain_mode(), player.gain_db()) {
+                (GainMode::Off, _) => "ReplayGain Off".to_string(),
+                (mode, Some(db)) => format!("ReplayGain {} {:+.1} dB", mode.name(), db),
+                (mode, None) => format!("ReplayGain {}", mode.name()),
+            };
+            let loopLabel = match player.loop_points() {
+                (Some(start), Some(end)) => format!(" | Loop {:.2}-{:.2}", start, end),
+                (Some(start), None) => format!(" | Loop from {:.2}", start),
+                _ => String::new(),
+            };
+            let playlist_buffer = format!(
+                "Track {}/{}{} | Repeat: {} | {} | {}{}",
+                playlist.position(),
+                playlist.len(),
+                if playlist.shuffle { " | Shuffle" } else { "" },
+                playlist.repeat.name(),
+                player.transition.name(),
+                gainLabel,
+                loopLabel
+            );
+            DrawTextEx(
+                font,
+                CString::new(playlist_buffer)
+                    .expect("CString new failed")
+                    .as_ptr(),
+                Vector2 {
+                    x: 10.0,
+                    y: screenHeight as f32 - 26.0,
+                },
+                20.0,
+                1.0,
+                currentTheme.foreground,
+            );
+
             // Draw play/pause status
-            let status = if IsMusicStreamPlaying(music) {
+            let status = if player.is_playing() {
                 "Playing"
             } else {
                 "Paused"
@@ -917,10 +1514,10 @@ fn main() {
                 Vector2 { x: 10.0, y: 10.0 },
                 20.0,
                 1.0,
-                if IsMusicStreamPlaying(music) {
-                    GRUVBOX_GREEN
+                if player.is_playing() {
+                    currentTheme.green
                 } else {
-                    GRUVBOX_RED
+                    currentTheme.red
                 },
             );
 
@@ -934,16 +1531,70 @@ fn main() {
                 Vector2 { x: 10.0, y: 40.0 },
                 20.0,
                 1.0,
-                GRUVBOX_AQUA,
+                currentTheme.aqua,
+            );
+
+            // Draw the speed and pitch next to it, only while they differ from normal
+            if !player.speed().is_normal() {
+                let volumeSize = MeasureTextEx(
+                    font,
+                    CString::new(volume_buffer)
+                        .expect("CString new failed")
+                        .as_ptr(),
+                    20.0,
+                    1.0,
+                );
+                DrawTextEx(
+                    font,
+                    CString::new(player.speed().describe())
+                        .expect("CString new failed")
+                        .as_ptr(),
+                    Vector2 {
+                        x: 30.0 + volumeSize.x,
+                        y: 40.0,
+                    },
+                    20.0,
+                    1.0,
+                    currentTheme.yellow,
+                );
+            }
+
+            // Draw the current spectrum settings
+            let settings = *spectrumSettings.lock().unwrap();
+            let spectrum_buffer = format!(
+                "{} | {} | {} | {} | {}{} | {} | {}{}",
+                settings.window.name(),
+                settings.aggregation.name(),
+                downmixMode.name(),
+                settings.stereo.name(),
+                if settings.db_scale { "dB" } else { "Linear" },
+                if settings.tilt != 0.0 { " | Tilt" } else { "" },
+                themes[themeIndex].0,
+                gradientMode.name(),
+                visualizers
+                    .current()
+                    .and_then(|v| v.colormap())
+                    .map(|colormap| format!(" | {}", colormap.name()))
+                    .unwrap_or_default()
+            );
+            DrawTextEx(
+                font,
+                CString::new(spectrum_buffer)
+                    .expect("CString new failed")
+                    .as_ptr(),
+                Vector2 { x: 10.0, y: 70.0 },
+                20.0,
+                1.0,
+                currentTheme.foreground,
             );
 
             // Draw info button
             DrawRectangleRec(
                 infoButton,
                 if showInfo {
-                    GRUVBOX_ORANGE
+                    currentTheme.orange
                 } else {
-                    GRUVBOX_PURPLE
+                    currentTheme.purple
                 },
             );
             DrawTextEx(
@@ -962,9 +1613,9 @@ fn main() {
             DrawRectangleRec(
                 helpButton,
                 if showHelp {
-                    GRUVBOX_ORANGE
+                    currentTheme.orange
                 } else {
-                    GRUVBOX_PURPLE
+                    currentTheme.purple
                 },
             );
             DrawTextEx(
@@ -979,18 +1630,56 @@ fn main() {
                 WHITE,
             );
 
+            DrawCorrelationMeter(font, correlation, screenWidth);
+
+            eqOverlay.draw(
+                font,
+                &eqSettings,
+                eqPreset.map_or("Custom", |i| EQ_PRESETS[i].0),
+                screenWidth,
+                screenHeight,
+            );
+
             // Display info box if toggled
             if showInfo {
-                DrawSpaceTheme(font, music);
+                DrawSpaceTheme(font, &player.source(), &metadata, coverTexture);
             }
 
             if showHelp {
                 println!("Work in Progress");
             }
+
+            if captureHud {
+                StoreCapture(&mut screenshotPending, &mut recorder);
+            }
+            // Drawn after the capture so it never shows up in the recording itself
+            if let Some(recording) = &recorder {
+                let recordingText =
+                    CString::new(format!("REC {}", recording.frames)).expect("CString new failed");
+                DrawTextEx(
+                    font,
+                    recordingText.as_ptr(),
+                    Vector2 { x: 10.0, y: 100.0 },
+                    20.0,
+                    1.0,
+                    currentTheme.red,
+                );
+            }
             EndDrawing();
         }
 
-        UnloadMusicStream(music);
+        if let Some(recording) = recorder.take() {
+            recording.finish();
+        }
+        if let Some(texture) = coverTexture {
+            UnloadTexture(texture);
+        }
+        stopAnalysis.store(true, Ordering::Relaxed);
+        analysisThread.join().ok();
+        // Lets the plugins free their state while the window still exists
+        drop(visualizers);
+        // Detaches the stream processor before the device goes away
+        drop(player);
         CloseAudioDevice();
         CloseWindow();
     }
@@ -0,0 +1,502 @@
+use crate::scope::ScopeView;
+use crate::spectrogram::Colormap;
+use crate::spectrogram::Spectrogram;
+use crate::spectrum::StereoView;
+use crate::theme::{GradientMode, Theme};
+use crate::visualizer::{VisualFrame, Visualizer};
+use raylib::ffi::{
+    ColorAlpha, DrawCircle, DrawCircleLines, DrawLineEx, DrawRectangle, DrawRectangleGradientV,
+    DrawRectangleLines, Vector2,
+};
+use rust_math::trigonometry::deg2rad;
+
+// Color of bar `i` of `m` at `amplitude` under the frame's gradient mode, `accent` when off
+fn BarColor(
+    frame: &VisualFrame,
+    i: usize,
+    m: usize,
+    amplitude: f32,
+    accent: raylib::ffi::Color,
+) -> raylib::ffi::Color {
+    match frame.gradient {
+        GradientMode::Off => accent,
+        GradientMode::Frequency => frame.theme.gradient_at(i as f32 / (m - 1).max(1) as f32),
+        GradientMode::Amplitude => frame.theme.gradient_at(amplitude),
+    }
+}
+
+// Color at the foot of a bar whose tip is `tip`. By amplitude, bars fade in from the quiet end
+// of the gradient, so the gradient shows within each bar.
+fn BarBaseColor(frame: &VisualFrame, tip: raylib::ffi::Color) -> raylib::ffi::Color {
+    match frame.gradient {
+        GradientMode::Amplitude => frame.theme.gradient_at(0.0),
+        _ => tip,
+    }
+}
+
+fn DrawCoolRectangle(
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    top: raylib::ffi::Color,
+    bottom: raylib::ffi::Color,
+) {
+    unsafe {
+        DrawRectangleGradientV(x as i32, y as i32, width as i32, height as i32, top, bottom);
+        DrawRectangleLines(
+            x as i32,
+            y as i32,
+            width as i32,
+            height as i32,
+            ColorAlpha(top, 0.3),
+        );
+        DrawCircle(
+            (x + width / 2.0) as i32,
+            y as i32,
+            width / 4.0,
+            ColorAlpha(top, 0.2),
+        );
+    }
+}
+
+// Draws `samples` (-1..=1) as a trace across the screen, centered on `center_y`
+fn DrawScopeTrace(
+    samples: &[f32],
+    center_y: f32,
+    half_height: f32,
+    screenWidth: i32,
+    color: raylib::ffi::Color,
+) {
+    unsafe {
+        // One segment per pixel column is plenty, the window holds more samples than that
+        let columns = (screenWidth as usize).min(samples.len());
+        let point = |column: usize| -> Vector2 {
+            let index = column * (samples.len() - 1) / (columns - 1).max(1);
+            Vector2 {
+                x: column as f32 * screenWidth as f32 / (columns - 1).max(1) as f32,
+                y: center_y - samples[index].clamp(-1.0, 1.0) * half_height,
+            }
+        };
+        for column in 0..columns - 1 {
+            DrawLineEx(point(column), point(column + 1), 2.0, color);
+        }
+    }
+}
+
+fn DrawOscilloscope(frame: &VisualFrame) {
+    let (scope, theme, screenWidth) = (frame.scope, frame.theme, frame.width);
+    unsafe {
+        let height = frame.height as f32;
+        match scope.view {
+            ScopeView::Mono => {
+                DrawScopeTrace(
+                    scope.mid(),
+                    height / 2.0,
+                    height * 0.45,
+                    screenWidth,
+                    theme.blue,
+                );
+            }
+
+            ScopeView::Split => {
+                DrawScopeTrace(
+                    scope.left(),
+                    height / 4.0,
+                    height * 0.22,
+                    screenWidth,
+                    theme.blue,
+                );
+                DrawScopeTrace(
+                    scope.right(),
+                    height * 3.0 / 4.0,
+                    height * 0.22,
+                    screenWidth,
+                    theme.aqua,
+                );
+                DrawLineEx(
+                    Vector2 {
+                        x: 0.0,
+                        y: height / 2.0,
+                    },
+                    Vector2 {
+                        x: screenWidth as f32,
+                        y: height / 2.0,
+                    },
+                    1.0,
+                    ColorAlpha(theme.foreground, 0.3),
+                );
+            }
+
+            ScopeView::XY => {
+                let center = Vector2 {
+                    x: (screenWidth / 2) as f32,
+                    y: height / 2.0,
+                };
+                let radius = height * 0.4;
+                let point = |l: f32, r: f32| -> Vector2 {
+                    // Side on the horizontal axis, mid on the vertical one
+                    let x = (r - l) * std::f32::consts::FRAC_1_SQRT_2;
+                    let y = (l + r) * std::f32::consts::FRAC_1_SQRT_2;
+                    Vector2 {
+                        x: center.x + x.clamp(-1.0, 1.0) * radius,
+                        y: center.y - y.clamp(-1.0, 1.0) * radius,
+                    }
+                };
+
+                DrawCircleLines(
+                    center.x as i32,
+                    center.y as i32,
+                    radius,
+                    ColorAlpha(theme.foreground, 0.2),
+                );
+                let (left, right) = (scope.left(), scope.right());
+                for i in 0..left.len() - 1 {
+                    DrawLineEx(
+                        point(left[i], right[i]),
+                        point(left[i + 1], right[i + 1]),
+                        1.0,
+                        ColorAlpha(theme.green, 0.6),
+                    );
+                }
+            }
+        }
+    }
+}
+
+// Smoothed values and peaks of the two channels of a stereo view
+type StereoBands<'a> = ([&'a [f32]; 2], [&'a [f32]; 2]);
+
+// The stereo pair, None while the spectrum is mono or the bands lag behind a settings change
+fn StereoPair<'a>(frame: &VisualFrame<'a>) -> Option<StereoBands<'a>> {
+    let [_, first, second] = frame.smoothers;
+    if frame.spectrum.stereo == StereoView::Mono
+        || first.values().len() != frame.band_count
+        || second.values().len() != frame.band_count
+    {
+        return None;
+    }
+    Some((
+        [first.values(), second.values()],
+        [first.peaks(), second.peaks()],
+    ))
+}
+
+// Mirrored pair of spectra for the stereo views in the bar modes: the first channel grows up
+// and the second down from the middle of the screen
+fn DrawStereoBars(
+    frame: &VisualFrame,
+    values: [&[f32]; 2],
+    peaks: [&[f32]; 2],
+    cell_width: f32,
+    width: f32,
+) {
+    let theme = frame.theme;
+    unsafe {
+        let colors = [theme.blue, theme.aqua];
+        let half = (frame.height / 2) as f32;
+        for (side, (values, peaks)) in values.iter().zip(peaks).enumerate() {
+            for (i, &amplitude) in values.iter().enumerate() {
+                if amplitude > 0.01 {
+                    let height = half * amplitude;
+                    let tip = BarColor(frame, i, values.len(), amplitude, colors[side]);
+                    // The lower channel grows down, so its tip is at the bottom
+                    let (top, bottom) = if side == 0 {
+                        (tip, BarBaseColor(frame, tip))
+                    } else {
+                        (BarBaseColor(frame, tip), tip)
+                    };
+                    DrawCoolRectangle(
+                        i as f32 * cell_width,
+                        if side == 0 { half - height } else { half },
+                        width,
+                        height,
+                        top,
+                        bottom,
+                    );
+                }
+            }
+            for (i, &peak) in peaks.iter().enumerate() {
+                if peak > 0.01 {
+                    let y = if side == 0 {
+                        half - half * peak - 3.0
+                    } else {
+                        half + half * peak
+                    };
+                    DrawRectangle(
+                        (i as f32 * cell_width) as i32,
+                        y as i32,
+                        width.max(1.0) as i32,
+                        3,
+                        theme.foreground,
+                    );
+                }
+            }
+        }
+        DrawLineEx(
+            Vector2 { x: 0.0, y: half },
+            Vector2 {
+                x: frame.width as f32,
+                y: half,
+            },
+            1.0,
+            ColorAlpha(theme.foreground, 0.3),
+        );
+    }
+}
+
+// The stereo views in RADIAL_BARS: the pair on the left and right half of the ring with the
+// lowest band at the bottom
+fn DrawStereoRing(frame: &VisualFrame, values: [&[f32]; 2], peaks: [&[f32]; 2], cell_width: f32) {
+    let theme = frame.theme;
+    unsafe {
+        let center = Vector2 {
+            x: (frame.width / 2) as f32,
+            y: (frame.height / 2) as f32,
+        };
+        let colors = [theme.blue, theme.aqua];
+        let innerRadius = (frame.height / 8) as f32;
+        let outerRadius = (frame.height / 4) as f32;
+        DrawCircle(
+            center.x as i32,
+            center.y as i32,
+            innerRadius,
+            theme.foreground,
+        );
+
+        for (side, (values, peaks)) in values.iter().zip(peaks).enumerate() {
+            let m = values.len();
+            let point = |i: usize, radius: f32| -> Vector2 {
+                let sweep = i as f32 * 180.0 / m as f32;
+                let angle = if side == 0 {
+                    90.0 + sweep
+                } else {
+                    90.0 - sweep
+                };
+                Vector2 {
+                    x: center.x + deg2rad(angle).cos() * radius,
+                    y: center.y + deg2rad(angle).sin() * radius,
+                }
+            };
+            for (i, &amplitude) in values.iter().enumerate() {
+                if amplitude > 0.01 {
+                    DrawLineEx(
+                        point(i, outerRadius),
+                        point(i, outerRadius * (1.0 + amplitude)),
+                        // Each half holds every band, so the bars are half as wide
+                        cell_width * 0.2,
+                        BarColor(frame, i, m, amplitude, colors[side]),
+                    );
+                }
+            }
+            for (i, &peak) in peaks.iter().enumerate() {
+                if peak > 0.01 {
+                    let p = point(i, outerRadius * (1.0 + peak));
+                    DrawCircle(p.x as i32, p.y as i32, 2.0, theme.foreground);
+                }
+            }
+        }
+    }
+}
+
+// STANDARD and PIXEL: one bar per band growing up from the bottom, with peak-hold markers
+struct Bars {
+    name: &'static str,
+    // Bar width as a fraction of the space each band gets
+    width: f32,
+    color: fn(Theme) -> raylib::ffi::Color,
+}
+
+impl Visualizer for Bars {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn draw(&mut self, frame: &VisualFrame) {
+        unsafe {
+            let cell_width = frame.width as f32 / frame.band_count as f32;
+            let width = cell_width * self.width;
+            if let Some((values, peaks)) = StereoPair(frame) {
+                DrawStereoBars(frame, values, peaks, cell_width, width);
+                return;
+            }
+            if !frame.bands_ready() {
+                return;
+            }
+
+            let height = frame.height as f32;
+            let smoother = &frame.smoothers[0];
+            let m = frame.band_count;
+            for (i, &amplitude) in smoother.values().iter().enumerate() {
+                if amplitude > 0.01 {
+                    let tip = BarColor(frame, i, m, amplitude, (self.color)(frame.theme));
+                    DrawCoolRectangle(
+                        (i as f32) * cell_width,
+                        height - height * amplitude,
+                        width,
+                        height * amplitude,
+                        tip,
+                        BarBaseColor(frame, tip),
+                    );
+                }
+            }
+
+            // Peak-hold markers, drawn even for bands that are currently silent
+            for (i, &peak) in smoother.peaks().iter().enumerate() {
+                if peak > 0.01 {
+                    DrawRectangle(
+                        ((i as f32) * cell_width) as i32,
+                        (height - height * peak) as i32 - 3,
+                        width.max(1.0) as i32,
+                        3,
+                        frame.theme.foreground,
+                    );
+                }
+            }
+        }
+    }
+}
+
+// WAVEFORM: the oscilloscope, drawn from raw samples rather than bands
+struct Waveform;
+
+impl Visualizer for Waveform {
+    fn name(&self) -> &str {
+        "waveform"
+    }
+
+    fn draw(&mut self, frame: &VisualFrame) {
+        DrawOscilloscope(frame);
+    }
+
+    fn wants_scope(&self) -> bool {
+        true
+    }
+}
+
+// STARBURST: a ray per band from the center of the screen. Stays on the downmix in the stereo
+// views.
+struct Starburst;
+
+impl Visualizer for Starburst {
+    fn name(&self) -> &str {
+        "starburst"
+    }
+
+    fn draw(&mut self, frame: &VisualFrame) {
+        unsafe {
+            if !frame.bands_ready() {
+                return;
+            }
+            let center = Vector2 {
+                x: (frame.width / 2) as f32,
+                y: (frame.height / 2) as f32,
+            };
+            let m = frame.band_count;
+            for (i, &amplitude) in frame.smoothers[0].values().iter().enumerate() {
+                if amplitude > 0.01 {
+                    // Angle for each ray wrt frequency range
+                    let angle = i as f32 * 360.0 / m as f32;
+                    let length = amplitude * ((frame.height / 2) as f32);
+                    let end = Vector2 {
+                        x: center.x + deg2rad(angle).cos() * length,
+                        y: center.y + deg2rad(angle).sin() * length,
+                    };
+                    let rayColor = BarColor(frame, i, m, amplitude, frame.theme.accent(i));
+                    DrawLineEx(center, end, 2.0, rayColor);
+                }
+            }
+        }
+    }
+}
+
+// RADIAL_BARS: bars around a ring, with peak-hold dots
+struct RadialBars;
+
+impl Visualizer for RadialBars {
+    fn name(&self) -> &str {
+        "radial_bars"
+    }
+
+    fn draw(&mut self, frame: &VisualFrame) {
+        unsafe {
+            let cell_width = frame.width as f32 / frame.band_count as f32;
+            if let Some((values, peaks)) = StereoPair(frame) {
+                DrawStereoRing(frame, values, peaks, cell_width);
+                return;
+            }
+            if !frame.bands_ready() {
+                return;
+            }
+
+            let center = Vector2 {
+                x: (frame.width / 2) as f32,
+                y: (frame.height / 2) as f32,
+            };
+            let innerRadius = (frame.height / 8) as f32;
+            let outerRadius = (frame.height / 4) as f32;
+            let amplitudeScale = (frame.height / 4) as f32;
+            let point = |angle: f32, radius: f32| Vector2 {
+                x: center.x + deg2rad(angle).cos() * radius,
+                y: center.y + deg2rad(angle).sin() * radius,
+            };
+
+            let smoother = &frame.smoothers[0];
+            let m = frame.band_count;
+            DrawCircle(
+                center.x as i32,
+                center.y as i32,
+                innerRadius,
+                frame.theme.foreground,
+            );
+            DrawCircleLines(
+                center.x as i32,
+                center.y as i32,
+                innerRadius,
+                frame.theme.foreground,
+            );
+            for (i, &amplitude) in smoother.values().iter().enumerate() {
+                if amplitude > 0.01 {
+                    let angle = i as f32 * 360.0 / m as f32;
+                    let barColor = BarColor(frame, i, m, amplitude, frame.theme.accent(i));
+                    DrawLineEx(
+                        point(angle, outerRadius),
+                        point(angle, outerRadius + amplitude * amplitudeScale),
+                        cell_width * 0.4,
+                        barColor,
+                    );
+                }
+            }
+            for (i, &peak) in smoother.peaks().iter().enumerate() {
+                if peak > 0.01 {
+                    let p = point(
+                        i as f32 * 360.0 / m as f32,
+                        outerRadius + peak * amplitudeScale,
+                    );
+                    DrawCircle(p.x as i32, p.y as i32, 2.0, frame.theme.foreground);
+                }
+            }
+        }
+    }
+}
+
+// The built-in modes in cycle order, the spectrogram starting with the configured colormap and
+// history
+pub fn builtin_visualizers(colormap: Colormap, history: f32) -> Vec<Box<dyn Visualizer>> {
+    vec![
+        Box::new(Bars {
+            name: "standard",
+            width: 0.4,
+            color: |theme| theme.red,
+        }),
+        Box::new(Bars {
+            name: "pixel",
+            width: 1.06,
+            color: |theme| theme.purple,
+        }),
+        Box::new(Waveform),
+        Box::new(Starburst),
+        Box::new(RadialBars),
+        Box::new(Spectrogram::new(colormap, history)),
+    ]
+}
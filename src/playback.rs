@@ -0,0 +1,1018 @@
+use crate::callback;
+use crate::loudness::{GainMode, Limiter, LoudnessTags, ScanCache};
+use crate::speed::{Speed, Wsola};
+use raylib::ffi::{
+    AttachAudioStreamProcessor, AudioStream, DetachAudioStreamProcessor, IsAudioStreamProcessed,
+    LoadAudioStream, PauseAudioStream, PlayAudioStream, ResumeAudioStream,
+    SetAudioStreamBufferSizeDefault, SetAudioStreamVolume, StopAudioStream, UnloadAudioStream,
+    UpdateAudioStream,
+};
+use rsmpeg::avcodec::{AVCodecContext, AVCodecParameters};
+use rsmpeg::avformat::AVFormatContextInput;
+use rsmpeg::avutil::{AVChannelLayout, AVRational};
+use rsmpeg::error::RsmpegError;
+use rsmpeg::ffi::{
+    av_get_sample_fmt_name, avcodec_flush_buffers, avformat_seek_file, AVMEDIA_TYPE_AUDIO,
+    AV_NOPTS_VALUE, AV_SAMPLE_FMT_FLT, AV_TIME_BASE,
+};
+use rsmpeg::swresample::SwrContext;
+use std::ffi::{CStr, CString};
+
+// Every track is decoded and resampled to this, so consecutive tracks can share one stream
+pub const ENGINE_SAMPLE_RATE: u32 = 48000;
+
+// Frames per raylib sub-buffer. The stream holds two, about 170 ms in all, refilled from the
+// render loop like raylib's own music streams.
+const BUFFER_FRAMES: usize = 4096;
+
+// Crossfade at an A-B loop's wrap, 10 ms. Too short to hear as a fade, long enough that the
+// jump back doesn't click.
+const LOOP_FADE: usize = 480;
+// Shorter loops than this, 100 ms, aren't set
+const MIN_LOOP: u64 = ENGINE_SAMPLE_RATE as u64 / 10;
+// Packets decoded ahead for a crossfade per update, a few buffers' worth with most codecs.
+// Builds up the fade's length of audio well before it is needed, without stalling a frame.
+const DECODE_AHEAD_PACKETS: usize = 8;
+
+// How a track hands over to the next one when it plays to the end
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Transition {
+    // Sample-exact, for live albums and continuous mixes
+    Gapless,
+    // Equal-power fade over this many seconds of the outgoing track's end
+    Crossfade(f32),
+}
+
+impl Transition {
+    pub fn name(self) -> String {
+        match self {
+            Transition::Gapless => "Gapless".to_string(),
+            Transition::Crossfade(seconds) => format!("Crossfade {:.1}s", seconds),
+        }
+    }
+}
+
+// What the file itself contains, before decoding to the engine's format
+#[derive(Clone, Debug, Default)]
+pub struct SourceFormat {
+    pub sample_rate: i32,
+    pub channels: i32,
+    pub sample_format: String,
+}
+
+// An open file decoding to interleaved stereo f32 at ENGINE_SAMPLE_RATE
+pub struct Decoder {
+    pub path: String,
+    pub source: SourceFormat,
+    // Seconds, 0 when the container doesn't say
+    pub length: f32,
+    pub tags: LoudnessTags,
+    // Linear factor every sample is scaled by
+    pub gain: f32,
+    format: AVFormatContextInput,
+    codec: AVCodecContext,
+    resampler: Option<SwrContext>,
+    stream_index: usize,
+    time_base: AVRational,
+    // Decoded samples not handed out yet
+    pending: Vec<f32>,
+    // Frame number of the next frame `read` hands out
+    position: u64,
+    // After a seek, decoded frames before this one are dropped to land exactly on the target
+    skip_to: Option<u64>,
+    end_of_file: bool,
+}
+
+impl Decoder {
+    pub fn open(path: &str) -> Result<Decoder, String> {
+        let c_path = CString::new(path).map_err(|e| e.to_string())?;
+        let format =
+            AVFormatContextInput::open(&c_path, None, &mut None).map_err(|e| e.to_string())?;
+        let (stream_index, decoder) = format
+            .find_best_stream(AVMEDIA_TYPE_AUDIO)
+            .map_err(|e| e.to_string())?
+            .ok_or("no audio stream")?;
+        let stream = &format.streams()[stream_index];
+        let codecpar = AVCodecParameters::clone(&stream.codecpar());
+        let time_base = stream.time_base;
+
+        let mut codec = AVCodecContext::new(&decoder);
+        codec.apply_codecpar(&codecpar).map_err(|e| e.to_string())?;
+        codec.set_pkt_timebase(time_base);
+        codec.open(None).map_err(|e| e.to_string())?;
+
+        let sample_format = unsafe {
+            let name = av_get_sample_fmt_name(codec.sample_fmt);
+            if name.is_null() {
+                "unknown".to_string()
+            } else {
+                CStr::from_ptr(name).to_string_lossy().into_owned()
+            }
+        };
+        // Fails here rather than once playing, when the stream can't become the engine's format.
+        // `drain` builds the resampler it uses from the first frame, which usually matches.
+        let stereo = AVChannelLayout::from_nb_channels(2);
+        SwrContext::new(
+            &stereo,
+            AV_SAMPLE_FMT_FLT,
+            ENGINE_SAMPLE_RATE as i32,
+            &codec.ch_layout,
+            codec.sample_fmt,
+            codec.sample_rate,
+        )
+        .and_then(|mut swr| swr.init())
+        .map_err(|e| format!("can't convert to {} Hz stereo: {}", ENGINE_SAMPLE_RATE, e))?;
+
+        let source = SourceFormat {
+            sample_rate: codec.sample_rate,
+            channels: codec.ch_layout.nb_channels,
+            sample_format,
+        };
+        let tags = LoudnessTags::read(&format);
+        let length = if format.duration > 0 {
+            format.duration as f32 / AV_TIME_BASE as f32
+        } else {
+            0.0
+        };
+
+        Ok(Decoder {
+            path: path.to_string(),
+            source,
+            length,
+            tags,
+            gain: 1.0,
+            format,
+            codec,
+            resampler: None,
+            stream_index,
+            time_base,
+            pending: Vec::new(),
+            position: 0,
+            skip_to: None,
+            end_of_file: false,
+        })
+    }
+
+    // Seconds into the track of the next frame `read` hands out
+    pub fn position(&self) -> f32 {
+        self.position as f32 / ENGINE_SAMPLE_RATE as f32
+    }
+
+    // Frames decoded ahead of the position
+    pub fn buffered(&self) -> usize {
+        self.pending.len() / 2
+    }
+
+    // Whether everything left of the track is buffered
+    pub fn at_end(&self) -> bool {
+        self.end_of_file
+    }
+
+    // Decodes until `frames` are buffered or the file ends
+    pub fn fill(&mut self, frames: usize) -> Result<(), String> {
+        self.fill_ahead(frames, usize::MAX)
+    }
+
+    // Like `fill`, but reads `packets` packets at most, so a long read-ahead can be spread
+    // over several calls
+    pub fn fill_ahead(&mut self, frames: usize, packets: usize) -> Result<(), String> {
+        let mut packets_read = 0;
+        while self.buffered() < frames && !self.end_of_file && packets_read < packets {
+            packets_read += 1;
+            match self.format.read_packet().map_err(|e| e.to_string())? {
+                Some(packet) if packet.stream_index as usize == self.stream_index => {
+                    self.codec
+                        .send_packet(Some(&packet))
+                        .map_err(|e| e.to_string())?;
+                }
+                Some(_) => continue,
+                None => {
+                    self.codec.send_packet(None).map_err(|e| e.to_string())?;
+                    self.end_of_file = true;
+                }
+            }
+            self.drain()?;
+        }
+        if self.end_of_file {
+            self.flush_resampler()?;
+        }
+        Ok(())
+    }
+
+    // Takes the last samples the resampler holds back, so a gapless join loses nothing
+    fn flush_resampler(&mut self) -> Result<(), String> {
+        let Some(swr) = self.resampler.as_mut() else {
+            return Ok(());
+        };
+        let capacity = swr.get_out_samples(0).max(0) as usize;
+        if capacity == 0 {
+            return Ok(());
+        }
+        let start = self.pending.len();
+        self.pending.resize(start + capacity * 2, 0.0);
+        let mut out = self.pending[start..].as_mut_ptr() as *mut u8;
+        let converted = unsafe { swr.convert(&mut out, capacity as i32, std::ptr::null(), 0) }
+            .map_err(|e| e.to_string())? as usize;
+        self.pending.truncate(start + converted * 2);
+        Ok(())
+    }
+
+    // Converts every frame the decoder has ready into `pending`
+    fn drain(&mut self) -> Result<(), String> {
+        loop {
+            let frame = match self.codec.receive_frame() {
+                Ok(frame) => frame,
+                Err(RsmpegError::DecoderDrainError) | Err(RsmpegError::DecoderFlushedError) => {
+                    return Ok(())
+                }
+                Err(e) => return Err(e.to_string()),
+            };
+            if self.resampler.is_none() {
+                let stereo = AVChannelLayout::from_nb_channels(2);
+                let mut swr = SwrContext::new(
+                    &stereo,
+                    AV_SAMPLE_FMT_FLT,
+                    ENGINE_SAMPLE_RATE as i32,
+                    &frame.ch_layout,
+                    frame.format,
+                    frame.sample_rate,
+                )
+                .map_err(|e| e.to_string())?;
+                swr.init().map_err(|e| e.to_string())?;
+                self.resampler = Some(swr);
+            }
+            let Some(swr) = self.resampler.as_mut() else {
+                continue;
+            };
+
+            let capacity = swr.get_out_samples(frame.nb_samples).max(0) as usize;
+            let start = self.pending.len();
+            self.pending.resize(start + capacity * 2, 0.0);
+            let mut out = self.pending[start..].as_mut_ptr() as *mut u8;
+            let converted = unsafe {
+                swr.convert(
+                    &mut out,
+                    capacity as i32,
+                    frame.extended_data as *const *const u8,
+                    frame.nb_samples,
+                )
+            }
+            .map_err(|e| e.to_string())? as usize;
+            self.pending.truncate(start + converted * 2);
+
+            // The first frame after a seek usually starts before the target
+            if let Some(target) = self.skip_to.take() {
+                if frame.pts != AV_NOPTS_VALUE {
+                    let seconds =
+                        frame.pts as f64 * self.time_base.num as f64 / self.time_base.den as f64;
+                    let first = (seconds * ENGINE_SAMPLE_RATE as f64).max(0.0) as u64;
+                    let skip = (target.saturating_sub(first) as usize).min(converted);
+                    self.pending.drain(start..start + skip * 2);
+                }
+            }
+        }
+    }
+
+    // Copies up to `out.len() / 2` frames into `out` and returns how many. Fewer only come
+    // back once the track has ended or can't be decoded any further.
+    pub fn read(&mut self, out: &mut [f32]) -> usize {
+        let frames = out.len() / 2;
+        if let Err(e) = self.fill(frames) {
+            println!("Error decoding {}: {}", self.path, e);
+            self.end_of_file = true;
+        }
+        let count = frames.min(self.buffered());
+        out[..count * 2].copy_from_slice(&self.pending[..count * 2]);
+        if self.gain != 1.0 {
+            for sample in out[..count * 2].iter_mut() {
+                *sample *= self.gain;
+            }
+        }
+        self.pending.drain(..count * 2);
+        self.position += count as u64;
+        count
+    }
+
+    // Frame number of the next frame `read` hands out
+    pub fn frame(&self) -> u64 {
+        self.position
+    }
+
+    pub fn seek(&mut self, seconds: f32) {
+        self.seek_frame((seconds.max(0.0) as f64 * ENGINE_SAMPLE_RATE as f64) as u64);
+    }
+
+    // Lands exactly on `frame`, for loops that have to join without a gap
+    pub fn seek_frame(&mut self, frame: u64) {
+        let target = (frame as f64 / ENGINE_SAMPLE_RATE as f64 * AV_TIME_BASE as f64) as i64;
+        let result = unsafe {
+            // Lands on the last seek point at or before the target, the rest is skipped while
+            // decoding
+            avformat_seek_file(self.format.as_mut_ptr(), -1, i64::MIN, target, target, 0)
+        };
+        if result < 0 {
+            println!("Could not seek in {}", self.path);
+            return;
+        }
+        unsafe { avcodec_flush_buffers(self.codec.as_mut_ptr()) };
+        // Samples still inside the resampler belong to the old position
+        self.resampler = None;
+        self.pending.clear();
+        self.end_of_file = false;
+        self.position = frame;
+        self.skip_to = Some(frame);
+    }
+}
+
+// Sent from `Player::update` when the track being played changes by itself
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PlayerEvent {
+    // Moved on to the preloaded track, its start is playing now
+    Advanced,
+    // The track ended with nothing preloaded after it, or couldn't be played at all
+    Ended,
+}
+
+// Plays tracks through a single raylib stream. The next track is opened ahead of time and
+// mixed in without stopping the stream, so transitions are gapless or crossfaded and the
+// stream processor keeps seeing audio across them.
+pub struct Player {
+    stream: AudioStream,
+    pub transition: Transition,
+    current: Option<Decoder>,
+    // Preloaded to follow `current`
+    next: Option<Decoder>,
+    // The path `next` was last asked to be, so a broken file isn't reopened every frame
+    next_path: Option<String>,
+    // The track fading out under `current`, frames of the fade done and the fade's length
+    fading: Option<(Decoder, usize, usize)>,
+    playing: bool,
+    // Raised while the stream was refilled outside of `update`, reported by the next one
+    pending_event: Option<PlayerEvent>,
+    gain_mode: GainMode,
+    // Added to every ReplayGain gain, in dB
+    preamp: f32,
+    // Gains measured by --scan for files without tags
+    scanned: ScanCache,
+    // Catches the peaks a positive gain pushes over full scale
+    limiter: Limiter,
+    speed: Speed,
+    // Tempo changes, then pitch and varispeed by playing the result at another rate. Both
+    // are skipped while the speed is normal.
+    stretch: Wsola,
+    varispeed: Option<SwrContext>,
+    // Audio out of both, waiting for the stream
+    stretched: Vec<f32>,
+    // A-B loop points in frames of the current track, it loops once both are set
+    loop_start: Option<u64>,
+    loop_end: Option<u64>,
+    // What played on past the loop's end, faded out under its start, and the frames of that
+    // fade done
+    loop_tail: Vec<f32>,
+    loop_fade: Option<usize>,
+    // A second decoder of the current track, kept waiting at the loop's start with some audio
+    // decoded, so the wrap swaps decoders instead of seeking
+    loop_spare: Option<Decoder>,
+    buffer: Vec<f32>,
+    scratch: Vec<f32>,
+}
+
+impl Player {
+    // Must be called after InitAudioDevice
+    pub fn new(transition: Transition, gain_mode: GainMode, preamp: f32) -> Player {
+        unsafe {
+            SetAudioStreamBufferSizeDefault(BUFFER_FRAMES as i32);
+            let stream = LoadAudioStream(ENGINE_SAMPLE_RATE, 32, 2);
+            // The one stream lives as long as the player, so the processor is attached once
+            AttachAudioStreamProcessor(stream, Some(callback));
+            PlayAudioStream(stream);
+            Player {
+                stream,
+                transition,
+                current: None,
+                next: None,
+                next_path: None,
+                fading: None,
+                playing: false,
+                pending_event: None,
+                gain_mode,
+                preamp,
+                scanned: ScanCache::load(),
+                limiter: Limiter::new(ENGINE_SAMPLE_RATE),
+                speed: Speed::default(),
+                stretch: Wsola::new(),
+                varispeed: None,
+                stretched: Vec::new(),
+                loop_start: None,
+                loop_end: None,
+                loop_tail: vec![0.0; LOOP_FADE * 2],
+                loop_fade: None,
+                loop_spare: None,
+                buffer: vec![0.0; BUFFER_FRAMES * 2],
+                scratch: vec![0.0; BUFFER_FRAMES * 2],
+            }
+        }
+    }
+
+    // Switches to `path` right away. On failure nothing is left playing and the next update
+    // reports Ended, so auto-advance can move past the track.
+    pub fn play(&mut self, path: &str) -> Result<(), String> {
+        self.fading = None;
+        self.current = None;
+        self.clear_stretch();
+        self.clear_loop();
+        self.resume();
+        let decoder = if self.next_path.as_deref() == Some(path) && self.next.is_some() {
+            self.next_path = None;
+            self.next.take()
+        } else {
+            None
+        };
+        let mut decoder = match decoder {
+            Some(decoder) => decoder,
+            None => Decoder::open(path)?,
+        };
+        self.apply_gain(&mut decoder);
+        self.current = Some(decoder);
+        Ok(())
+    }
+
+    // Opens the track that follows the current one, or forgets it with None. Cheap to call
+    // every frame, the file is only opened when the path changes.
+    pub fn preload(&mut self, path: Option<&str>) {
+        if self.next_path.as_deref() == path {
+            return;
+        }
+        self.next_path = path.map(String::from);
+        self.next = path.and_then(|path| match Decoder::open(path) {
+            Ok(mut decoder) => {
+                self.apply_gain(&mut decoder);
+                Some(decoder)
+            }
+            Err(e) => {
+                println!("Cannot preload {}: {}", path, e);
+                None
+            }
+        });
+    }
+
+    // Refills the stream's processed buffers, call once per frame
+    pub fn update(&mut self) -> Option<PlayerEvent> {
+        if let Some(event) = self.pending_event.take() {
+            return Some(event);
+        }
+        if !self.playing {
+            return None;
+        }
+        if self.current.is_none() && self.stretch_is_idle() {
+            self.playing = false;
+            return Some(PlayerEvent::Ended);
+        }
+        self.decode_ahead();
+        self.prepare_loop_spare();
+        self.refill()
+    }
+
+    // Hands the stream every sub-buffer it has played out
+    fn refill(&mut self) -> Option<PlayerEvent> {
+        let mut event = None;
+        unsafe {
+            while IsAudioStreamProcessed(self.stream) {
+                let filled = self.render(&mut event);
+                self.buffer[filled * 2..].fill(0.0);
+                // Without normalization the audio goes out untouched
+                if self.gain_mode != GainMode::Off {
+                    self.limiter.process(&mut self.buffer[..filled * 2]);
+                }
+                UpdateAudioStream(
+                    self.stream,
+                    self.buffer.as_ptr() as *const std::ffi::c_void,
+                    BUFFER_FRAMES as i32,
+                );
+                // Over once the last track is gone and the time stretch has played it out
+                if self.current.is_none() && self.stretch_is_idle() {
+                    self.playing = false;
+                    event = Some(PlayerEvent::Ended);
+                    break;
+                }
+                if filled == 0 {
+                    break;
+                }
+            }
+        }
+        event
+    }
+
+    // Buffers a crossfade's length of the current track bit by bit, so `mix` knows when the
+    // rest of it fits into the fade without decoding seconds of audio at once
+    fn decode_ahead(&mut self) {
+        let Transition::Crossfade(seconds) = self.transition else {
+            return;
+        };
+        if self.fading.is_some() || self.next.is_none() || self.loop_region().is_some() {
+            return;
+        }
+        if let Some(current) = self.current.as_mut() {
+            let fade = (seconds * ENGINE_SAMPLE_RATE as f32) as usize;
+            if let Err(e) = current.fill_ahead(fade + 1, DECODE_AHEAD_PACKETS) {
+                println!("Error decoding {}: {}", current.path, e);
+            }
+        }
+    }
+
+    // Sends the loop's spare decoder back to the loop's start after a wrap or a new A, and
+    // decodes a buffer's worth there bit by bit
+    fn prepare_loop_spare(&mut self) {
+        let Some((start, _)) = self.loop_region() else {
+            return;
+        };
+        let Some(spare) = self.loop_spare.as_mut() else {
+            return;
+        };
+        if spare.frame() != start {
+            spare.seek_frame(start);
+            if spare.frame() != start {
+                // Wraps fall back to seeking the current decoder
+                self.loop_spare = None;
+                return;
+            }
+        }
+        if let Err(e) = spare.fill_ahead(BUFFER_FRAMES, DECODE_AHEAD_PACKETS) {
+            println!("Error decoding {}: {}", spare.path, e);
+            self.loop_spare = None;
+        }
+    }
+
+    // Opens the loop's spare decoder once a loop is set
+    fn open_loop_spare(&mut self) {
+        if self.loop_region().is_none() || self.loop_spare.is_some() {
+            return;
+        }
+        let Some(path) = self.current.as_ref().map(|current| current.path.clone()) else {
+            return;
+        };
+        match Decoder::open(&path) {
+            Ok(mut spare) => {
+                self.apply_gain(&mut spare);
+                self.loop_spare = Some(spare);
+            }
+            Err(e) => println!("Cannot open {} a second time for the loop: {}", path, e),
+        }
+    }
+
+    // Fills `buffer` from the mix, through the time stretch and varispeed unless the speed is
+    // normal. Like `mix`, less than a full buffer only comes back when nothing is left.
+    fn render(&mut self, event: &mut Option<PlayerEvent>) -> usize {
+        if self.speed.is_normal() {
+            return self.mix(event);
+        }
+        while self.stretched.len() < BUFFER_FRAMES * 2 {
+            if self.current.is_some() {
+                let filled = self.mix(event);
+                self.stretch.push(&self.buffer[..filled * 2]);
+            }
+            if self.current.is_none() {
+                self.stretch.finish();
+            }
+            let count = self.stretch.pull(&mut self.scratch);
+            if count > 0 {
+                self.resample(count);
+            } else if self.current.is_none() {
+                self.flush_varispeed();
+                break;
+            }
+        }
+        let count = (self.stretched.len() / 2).min(BUFFER_FRAMES);
+        self.buffer[..count * 2].copy_from_slice(&self.stretched[..count * 2]);
+        self.stretched.drain(..count * 2);
+        count
+    }
+
+    // Plays the first `frames` of `scratch` at the speed's resampling rate into `stretched`.
+    // Reading the audio as if it was recorded at a higher rate speeds it up and raises the
+    // pitch as much.
+    fn resample(&mut self, frames: usize) {
+        let factor = self.speed.resample();
+        if factor == 1.0 {
+            self.stretched
+                .extend_from_slice(&self.scratch[..frames * 2]);
+            return;
+        }
+        if self.varispeed.is_none() {
+            let stereo = AVChannelLayout::from_nb_channels(2);
+            let rate = (ENGINE_SAMPLE_RATE as f32 * factor).round() as i32;
+            let swr = SwrContext::new(
+                &stereo,
+                AV_SAMPLE_FMT_FLT,
+                ENGINE_SAMPLE_RATE as i32,
+                &stereo,
+                AV_SAMPLE_FMT_FLT,
+                rate,
+            )
+            .and_then(|mut swr| swr.init().map(|_| swr));
+            match swr {
+                Ok(swr) => self.varispeed = Some(swr),
+                Err(e) => {
+                    println!("Cannot change the playback speed: {}", e);
+                    self.stretched
+                        .extend_from_slice(&self.scratch[..frames * 2]);
+                    return;
+                }
+            }
+        }
+        let Some(swr) = self.varispeed.as_mut() else {
+            return;
+        };
+        let capacity = swr.get_out_samples(frames as i32).max(0) as usize;
+        let start = self.stretched.len();
+        self.stretched.resize(start + capacity * 2, 0.0);
+        let mut out = self.stretched[start..].as_mut_ptr() as *mut u8;
+        let input = [self.scratch.as_ptr() as *const u8];
+        let converted =
+            unsafe { swr.convert(&mut out, capacity as i32, input.as_ptr(), frames as i32) };
+        match converted {
+            Ok(converted) => self.stretched.truncate(start + converted as usize * 2),
+            Err(e) => {
+                println!("Error changing the playback speed: {}", e);
+                self.stretched.truncate(start);
+            }
+        }
+    }
+
+    // Takes the last samples the varispeed resampler holds back, then drops it
+    fn flush_varispeed(&mut self) {
+        let Some(mut swr) = self.varispeed.take() else {
+            return;
+        };
+        let capacity = swr.get_out_samples(0).max(0) as usize;
+        if capacity == 0 {
+            return;
+        }
+        let start = self.stretched.len();
+        self.stretched.resize(start + capacity * 2, 0.0);
+        let mut out = self.stretched[start..].as_mut_ptr() as *mut u8;
+        let converted =
+            unsafe { swr.convert(&mut out, capacity as i32, std::ptr::null(), 0) }.unwrap_or(0);
+        self.stretched
+            .truncate(start + converted.max(0) as usize * 2);
+    }
+
+    fn clear_stretch(&mut self) {
+        self.stretch.clear();
+        self.varispeed = None;
+        self.stretched.clear();
+    }
+
+    fn stretch_is_idle(&self) -> bool {
+        self.stretch.is_idle() && self.stretched.is_empty()
+    }
+
+    fn loop_region(&self) -> Option<(u64, u64)> {
+        Some((self.loop_start?, self.loop_end?))
+    }
+
+    // Sets A. A B that would come before it is dropped.
+    pub fn set_loop_start(&mut self, seconds: f32) {
+        let frame = (seconds.max(0.0) as f64 * ENGINE_SAMPLE_RATE as f64) as u64;
+        self.loop_start = Some(frame);
+        if self.loop_end.is_some_and(|end| end < frame + MIN_LOOP) {
+            self.loop_end = None;
+        }
+        self.open_loop_spare();
+    }
+
+    // Sets B, which starts the loop. Without an A it loops from the start of the track, and
+    // set before A the two swap.
+    pub fn set_loop_end(&mut self, seconds: f32) {
+        let frame = (seconds.max(0.0) as f64 * ENGINE_SAMPLE_RATE as f64) as u64;
+        let start = self.loop_start.unwrap_or(0);
+        let (start, end) = (start.min(frame), start.max(frame));
+        if end < start + MIN_LOOP {
+            return;
+        }
+        self.loop_start = Some(start);
+        self.loop_end = Some(end);
+        self.open_loop_spare();
+    }
+
+    pub fn clear_loop(&mut self) {
+        self.loop_start = None;
+        self.loop_end = None;
+        self.loop_fade = None;
+        self.loop_spare = None;
+    }
+
+    // A and B in seconds
+    pub fn loop_points(&self) -> (Option<f32>, Option<f32>) {
+        let seconds = |frame: u64| frame as f32 / ENGINE_SAMPLE_RATE as f32;
+        (self.loop_start.map(seconds), self.loop_end.map(seconds))
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        if speed.resample() != self.speed.resample() {
+            // A new rate needs a new resampler, what the old one holds still plays
+            self.flush_varispeed();
+        }
+        self.speed = speed;
+        self.stretch.set_speed(speed.stretch());
+        if speed.is_normal() {
+            // The little audio still inside the stretch is skipped rather than played
+            // at the old speed
+            self.clear_stretch();
+        }
+    }
+
+    // Fills `buffer` from the current track, the fading one and the next. Returns the frames
+    // written, less than a full buffer only when nothing is left to play.
+    fn mix(&mut self, event: &mut Option<PlayerEvent>) -> usize {
+        let mut filled = 0;
+        while filled < BUFFER_FRAMES {
+            let region = self.loop_region();
+            let Some(current) = self.current.as_mut() else {
+                break;
+            };
+
+            // The fade starts once the rest of the track fits into it, so both ends line up.
+            // `decode_ahead` has buffered the rest by then. A loop keeps the track from ending
+            // at all.
+            if let (Transition::Crossfade(seconds), None, true) = (
+                self.transition,
+                &self.fading,
+                self.next.is_some() && region.is_none(),
+            ) {
+                let fade = (seconds * ENGINE_SAMPLE_RATE as f32) as usize;
+                if current.at_end() && current.buffered() <= fade && current.buffered() > 0 {
+                    let length = current.buffered();
+                    let outgoing = self.current.take();
+                    self.current = self.next.take();
+                    self.next_path = None;
+                    self.fading = outgoing.map(|outgoing| (outgoing, 0, length));
+                    *event = Some(PlayerEvent::Advanced);
+                    continue;
+                }
+            }
+
+            let want = BUFFER_FRAMES - filled;
+            let out = &mut self.buffer[filled * 2..];
+            let ended = match &mut self.fading {
+                Some((outgoing, done, length)) => {
+                    let span = want.min(*length - *done);
+                    let read = current.read(&mut out[..span * 2]);
+                    out[read * 2..span * 2].fill(0.0);
+                    let tail = &mut self.scratch[..span * 2];
+                    let tail_read = outgoing.read(tail);
+                    tail[tail_read * 2..].fill(0.0);
+                    for i in 0..span {
+                        let t = (*done + i) as f32 / *length as f32;
+                        let (fade_in, fade_out) = (t * std::f32::consts::FRAC_PI_2).sin_cos();
+                        for c in 0..2 {
+                            out[i * 2 + c] = out[i * 2 + c] * fade_in + tail[i * 2 + c] * fade_out;
+                        }
+                    }
+                    *done += span;
+                    if *done >= *length {
+                        self.fading = None;
+                    }
+                    filled += span;
+                    read < span
+                }
+                None => {
+                    // Up to the loop's end at most, so the wrap lands on the exact frame
+                    let span = match region {
+                        Some((_, end)) => want.min(end.saturating_sub(current.frame()) as usize),
+                        None => want,
+                    };
+                    let read = current.read(&mut out[..span * 2]);
+                    if let Some(done) = self.loop_fade.as_mut() {
+                        let count = read.min(LOOP_FADE - *done);
+                        for i in 0..count {
+                            let t = (*done + i) as f32 / LOOP_FADE as f32;
+                            let (fade_in, fade_out) = (t * std::f32::consts::FRAC_PI_2).sin_cos();
+                            for c in 0..2 {
+                                out[i * 2 + c] = out[i * 2 + c] * fade_in
+                                    + self.loop_tail[(*done + i) * 2 + c] * fade_out;
+                            }
+                        }
+                        *done += count;
+                        if *done >= LOOP_FADE {
+                            self.loop_fade = None;
+                        }
+                    }
+                    filled += read;
+
+                    // Back to the loop's start at its end, or at the track's end for a loop
+                    // that reaches past it. The stream carries on without a break, so the
+                    // analysis sees one continuous signal.
+                    if let Some((start, end)) = region {
+                        if current.frame() >= end || (read < span && current.frame() > start) {
+                            let tail_read = current.read(&mut self.loop_tail);
+                            self.loop_tail[tail_read * 2..].fill(0.0);
+                            // The spare already has the start decoded. The outgoing decoder
+                            // becomes the spare, sent back to the start by the next update.
+                            let spare = self.loop_spare.take_if(|spare| spare.frame() == start);
+                            if let Some(spare) = spare {
+                                self.loop_spare = Some(std::mem::replace(current, spare));
+                                self.loop_fade = Some(0);
+                                continue;
+                            }
+                            current.seek_frame(start);
+                            if current.frame() == start {
+                                self.loop_fade = Some(0);
+                                continue;
+                            }
+                            // A file that can't seek plays on without the loop
+                            self.loop_start = None;
+                            self.loop_end = None;
+                        }
+                    }
+                    read < span
+                }
+            };
+            if !ended {
+                continue;
+            }
+
+            // The current track is over, carry straight on with the next one
+            self.clear_loop();
+            self.current = self.next.take();
+            self.next_path = None;
+            if self.current.is_some() {
+                *event = Some(PlayerEvent::Advanced);
+            }
+        }
+        if self.current.is_none() {
+            // A fade into a track shorter than the fade is cut off with it
+            self.fading = None;
+        }
+        filled
+    }
+
+    pub fn seek(&mut self, seconds: f32) {
+        // Jumping away ends any fade in progress
+        self.fading = None;
+        self.loop_fade = None;
+        self.clear_stretch();
+        let Some(current) = self.current.as_mut() else {
+            return;
+        };
+        current.seek(seconds);
+        // Stopping drops what the stream still had queued from before the seek, so neither the
+        // speakers nor the analysis get it. Refilled at once, a paused stream included, so it
+        // starts from the new position however playback continues.
+        let playing = self.playing;
+        unsafe {
+            // raylib ignores a stop while paused
+            ResumeAudioStream(self.stream);
+            StopAudioStream(self.stream);
+        }
+        self.pending_event = self.refill();
+        unsafe {
+            PlayAudioStream(self.stream);
+            if !playing {
+                PauseAudioStream(self.stream);
+            }
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing && self.current.is_some()
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+        unsafe { PauseAudioStream(self.stream) };
+    }
+
+    pub fn resume(&mut self) {
+        self.playing = true;
+        unsafe { ResumeAudioStream(self.stream) };
+    }
+
+    // The tags, or failing that the scanned gains, turned into the decoder's gain
+    fn apply_gain(&self, decoder: &mut Decoder) {
+        let tags = if decoder.tags.is_empty() {
+            self.scanned.get(&decoder.path).unwrap_or_default()
+        } else {
+            decoder.tags
+        };
+        decoder.gain = match tags.gain(self.gain_mode) {
+            Some(gain) => gain.linear(self.preamp),
+            // Untagged tracks play as they are
+            None => 1.0,
+        };
+    }
+
+    pub fn gain_mode(&self) -> GainMode {
+        self.gain_mode
+    }
+
+    // Takes effect at once, also on the preloaded and fading tracks
+    pub fn set_gain_mode(&mut self, mode: GainMode) {
+        self.gain_mode = mode;
+        // Taken out while their gains are worked out, apply_gain borrows the player
+        let mut current = self.current.take();
+        let mut next = self.next.take();
+        let mut fading = self.fading.take();
+        let mut spare = self.loop_spare.take();
+        let outgoing = fading.as_mut().map(|(outgoing, _, _)| outgoing);
+        for decoder in current
+            .iter_mut()
+            .chain(next.iter_mut())
+            .chain(outgoing)
+            .chain(spare.iter_mut())
+        {
+            self.apply_gain(decoder);
+        }
+        self.current = current;
+        self.next = next;
+        self.fading = fading;
+        self.loop_spare = spare;
+    }
+
+    // What the current track's gain comes to, in dB. None when nothing is applied.
+    pub fn gain_db(&self) -> Option<f32> {
+        let current = self.current.as_ref()?;
+        (current.gain != 1.0).then(|| 20.0 * current.gain.log10())
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        unsafe { SetAudioStreamVolume(self.stream, volume) };
+    }
+
+    // Seconds into the current track. What was decoded minus about the sub-buffer still
+    // playing, raylib doesn't tell how far into it the device is, and minus what waits in the
+    // time stretch.
+    pub fn position(&self) -> f32 {
+        self.current.as_ref().map_or(0.0, |current| {
+            // Every frame that goes out covers `rate` frames of the track
+            let queued = (BUFFER_FRAMES + self.stretched.len() / 2) as f32 * self.speed.rate()
+                + self.stretch.latency();
+            playing_position(
+                current.position(),
+                queued / ENGINE_SAMPLE_RATE as f32,
+                self.loop_points(),
+            )
+        })
+    }
+
+    pub fn length(&self) -> f32 {
+        self.current.as_ref().map_or(0.0, |current| current.length)
+    }
+
+    pub fn source(&self) -> SourceFormat {
+        self.current
+            .as_ref()
+            .map(|current| current.source.clone())
+            .unwrap_or_default()
+    }
+}
+
+// Seconds into the track of what is heard, `queued` seconds behind the decoder at `decoded`.
+// Just after a loop wrapped, that is still from before its end.
+fn playing_position(decoded: f32, queued: f32, loop_points: (Option<f32>, Option<f32>)) -> f32 {
+    let mut position = decoded - queued;
+    if let (Some(start), Some(end)) = loop_points {
+        if position < start && decoded >= start {
+            position += end - start;
+        }
+    }
+    position.max(0.0)
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        unsafe {
+            DetachAudioStreamProcessor(self.stream, Some(callback));
+            UnloadAudioStream(self.stream);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn playing_position_lags_the_decoder() {
+        assert_eq!(playing_position(10.0, 0.5, (None, None)), 9.5);
+        // Nothing before the start of the track
+        assert_eq!(playing_position(0.25, 0.5, (None, None)), 0.0);
+        // A without B isn't a loop yet
+        assert_eq!(playing_position(5.25, 0.5, (Some(5.0), None)), 4.75);
+    }
+
+    #[test]
+    fn playing_position_is_before_the_loop_end_just_after_a_wrap() {
+        let region = (Some(5.0), Some(8.0));
+        // Decoding has wrapped back to A, what plays is still the end of the loop
+        assert_eq!(playing_position(5.25, 0.5, region), 7.75);
+        // Caught up with the wrap
+        assert_eq!(playing_position(6.0, 0.5, region), 5.5);
+        // Not in the loop yet, approaching A from before it
+        assert_eq!(playing_position(4.75, 0.5, region), 4.25);
+    }
+}
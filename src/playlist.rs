@@ -0,0 +1,447 @@
+use crate::playlist_file::{is_playlist_file, load_playlist};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn is_audio_file(filename: &str) -> bool {
+    let extensions = [".mp3", ".wav", ".ogg", ".flac", ".aac"];
+    let lower = filename.to_lowercase();
+    extensions.iter().any(|ext| lower.ends_with(ext))
+}
+
+// Accepts playlists too, so they can be passed on the command line or dropped like songs
+pub fn is_song_file(filename: &str) -> bool {
+    is_audio_file(filename) || is_playlist_file(filename)
+}
+
+// One queue entry. Title and duration are only known when a playlist file provided them.
+#[derive(Clone, Debug, Default)]
+pub struct Track {
+    pub path: String,
+    pub title: Option<String>,
+    pub duration: Option<i32>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RepeatMode {
+    Off,
+    One,
+    All,
+}
+
+impl RepeatMode {
+    pub fn next(self) -> RepeatMode {
+        match self {
+            RepeatMode::Off => RepeatMode::All,
+            RepeatMode::All => RepeatMode::One,
+            RepeatMode::One => RepeatMode::Off,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RepeatMode::Off => "Off",
+            RepeatMode::One => "One",
+            RepeatMode::All => "All",
+        }
+    }
+}
+
+// The play queue. `tracks` keeps the order files were added in, `order` is the order they are
+// played in (identical unless shuffle is on) and `position` indexes into `order`.
+pub struct Playlist {
+    tracks: Vec<Track>,
+    order: Vec<usize>,
+    position: usize,
+    pub shuffle: bool,
+    pub repeat: RepeatMode,
+    rng: u64,
+}
+
+impl Playlist {
+    pub fn new() -> Playlist {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0x2545_f491_4f6c_dd1d);
+        Playlist {
+            tracks: Vec::new(),
+            order: Vec::new(),
+            position: 0,
+            shuffle: false,
+            repeat: RepeatMode::Off,
+            // xorshift must not start from zero
+            rng: seed | 1,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tracks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tracks.is_empty()
+    }
+
+    // 1-based position of the current track in play order, for display
+    pub fn position(&self) -> usize {
+        self.position + 1
+    }
+
+    pub fn current(&self) -> Option<&str> {
+        self.order
+            .get(self.position)
+            .map(|&index| self.tracks[index].path.as_str())
+    }
+
+    // Tracks in play order, e.g. for saving the queue
+    pub fn queue(&self) -> Vec<Track> {
+        self.order.iter().map(|&i| self.tracks[i].clone()).collect()
+    }
+
+    // Adds a song file, every entry of a playlist file, or every song file below a directory
+    // in name order. Returns how many tracks were added.
+    pub fn add_path(&mut self, path: &str) -> usize {
+        let p = Path::new(path);
+        if is_playlist_file(path) {
+            if !p.is_file() {
+                println!("Playlist {} not found", path);
+                return 0;
+            }
+            match load_playlist(path) {
+                Ok(tracks) => {
+                    let before = self.tracks.len();
+                    // Only songs that are actually there, playlists don't nest
+                    for track in tracks {
+                        if !is_audio_file(&track.path) {
+                            println!("Skipping playlist entry {}: not a song file", track.path);
+                        } else if !Path::new(&track.path).is_file() {
+                            println!("Skipping playlist entry {}: file not found", track.path);
+                        } else {
+                            self.push(track);
+                        }
+                    }
+                    self.tracks.len() - before
+                }
+                Err(e) => {
+                    println!("Could not read playlist {}: {}", path, e);
+                    0
+                }
+            }
+        } else if p.is_dir() {
+            let mut entries: Vec<_> = match std::fs::read_dir(p) {
+                Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+                Err(e) => {
+                    println!("Could not read directory {}: {}", path, e);
+                    return 0;
+                }
+            };
+            entries.sort();
+            // Playlists lying around in a music folder would only add its songs a second time
+            entries
+                .iter()
+                .map(|entry| entry.to_string_lossy().into_owned())
+                .filter(|entry| !is_playlist_file(entry))
+                .map(|entry| self.add_path(&entry))
+                .sum()
+        } else if is_song_file(path) {
+            self.push(Track {
+                path: path.to_string(),
+                ..Track::default()
+            });
+            1
+        } else {
+            0
+        }
+    }
+
+    fn push(&mut self, track: Track) {
+        self.order.push(self.tracks.len());
+        self.tracks.push(track);
+    }
+
+    // Makes the first track added by the last `count` additions current, e.g. after a drop
+    pub fn jump_to_added(&mut self, count: usize) -> Option<&str> {
+        if count == 0 {
+            return None;
+        }
+        let first_added = self.tracks.len() - count;
+        self.position = self.order.iter().position(|&i| i == first_added)?;
+        self.current()
+    }
+
+    // Manual skip forward. Wraps around at the end unless repeat is off.
+    pub fn next_track(&mut self) -> Option<&str> {
+        if self.position + 1 < self.order.len() {
+            self.position += 1;
+        } else if self.repeat != RepeatMode::Off && !self.order.is_empty() {
+            self.start_over();
+        } else {
+            return None;
+        }
+        self.current()
+    }
+
+    // Manual skip back. Wraps around at the start unless repeat is off.
+    pub fn previous_track(&mut self) -> Option<&str> {
+        if self.position > 0 {
+            self.position -= 1;
+        } else if self.repeat != RepeatMode::Off && !self.order.is_empty() {
+            self.position = self.order.len() - 1;
+        } else {
+            return None;
+        }
+        self.current()
+    }
+
+    // Called when the current track has played to the end
+    pub fn advance(&mut self) -> Option<&str> {
+        if self.repeat == RepeatMode::One {
+            return self.current();
+        }
+        self.next_track()
+    }
+
+    // The track advance() will move to, so it can be opened ahead of time. At the end of a
+    // shuffled pass that is the first track of the order the next pass will be shuffled into.
+    pub fn peek_next(&self) -> Option<&str> {
+        if self.repeat == RepeatMode::One {
+            return self.current();
+        }
+        let index = if self.position + 1 < self.order.len() {
+            self.order[self.position + 1]
+        } else if self.repeat != RepeatMode::Off && !self.order.is_empty() {
+            if self.shuffle {
+                let mut order = self.order.clone();
+                let mut rng = self.rng;
+                shuffle(&mut order, &mut rng);
+                order[0]
+            } else {
+                self.order[0]
+            }
+        } else {
+            return None;
+        };
+        Some(self.tracks[index].path.as_str())
+    }
+
+    pub fn toggle_shuffle(&mut self) {
+        self.shuffle = !self.shuffle;
+        let current = self.order.get(self.position).copied();
+        self.order = (0..self.tracks.len()).collect();
+        if self.shuffle {
+            self.shuffle_order();
+        }
+        // Keep playing the same track from its place in the new order
+        self.position = current
+            .and_then(|c| self.order.iter().position(|&i| i == c))
+            .unwrap_or(0);
+        if self.shuffle {
+            self.order.swap(0, self.position);
+            self.position = 0;
+        }
+    }
+
+    fn start_over(&mut self) {
+        // Each pass through a shuffled playlist gets a fresh order
+        if self.shuffle {
+            self.shuffle_order();
+        }
+        self.position = 0;
+    }
+
+    fn shuffle_order(&mut self) {
+        shuffle(&mut self.order, &mut self.rng);
+    }
+}
+
+impl Default for Playlist {
+    fn default() -> Playlist {
+        Playlist::new()
+    }
+}
+
+// Fisher-Yates with a xorshift generator, good enough for picking songs. The same order and rng
+// state always give the same result, which is what lets peek_next() look into the next pass.
+fn shuffle(order: &mut [usize], rng: &mut u64) {
+    for i in (1..order.len()).rev() {
+        *rng ^= *rng << 13;
+        *rng ^= *rng >> 7;
+        *rng ^= *rng << 17;
+        let j = (*rng % (i as u64 + 1)) as usize;
+        order.swap(i, j);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playlist(count: usize) -> Playlist {
+        let mut playlist = Playlist::new();
+        for i in 0..count {
+            playlist.push(Track {
+                path: i.to_string(),
+                ..Track::default()
+            });
+        }
+        playlist
+    }
+
+    #[test]
+    fn next_and_previous_step_through_the_queue() {
+        let mut playlist = playlist(3);
+        assert_eq!(playlist.current(), Some("0"));
+        assert_eq!(playlist.next_track(), Some("1"));
+        assert_eq!(playlist.next_track(), Some("2"));
+        assert_eq!(playlist.position(), 3);
+        assert_eq!(playlist.previous_track(), Some("1"));
+        assert_eq!(playlist.previous_track(), Some("0"));
+    }
+
+    #[test]
+    fn empty_playlist_has_nothing_to_play() {
+        for repeat in [RepeatMode::Off, RepeatMode::All, RepeatMode::One] {
+            let mut playlist = playlist(0);
+            playlist.repeat = repeat;
+            assert_eq!(playlist.current(), None);
+            assert_eq!(playlist.next_track(), None);
+            assert_eq!(playlist.previous_track(), None);
+            assert_eq!(playlist.advance(), None);
+        }
+    }
+
+    #[test]
+    fn repeat_off_stops_at_both_ends() {
+        let mut playlist = playlist(2);
+        assert_eq!(playlist.previous_track(), None);
+        assert_eq!(playlist.current(), Some("0"));
+        assert_eq!(playlist.advance(), Some("1"));
+        assert_eq!(playlist.advance(), None);
+        assert_eq!(playlist.next_track(), None);
+        assert_eq!(playlist.current(), Some("1"));
+    }
+
+    #[test]
+    fn repeat_all_wraps_at_both_ends() {
+        let mut playlist = playlist(3);
+        playlist.repeat = RepeatMode::All;
+        assert_eq!(playlist.previous_track(), Some("2"));
+        assert_eq!(playlist.next_track(), Some("0"));
+        playlist.next_track();
+        playlist.next_track();
+        assert_eq!(playlist.advance(), Some("0"));
+    }
+
+    #[test]
+    fn repeat_one_replays_on_advance_but_skips_manually() {
+        let mut playlist = playlist(2);
+        playlist.repeat = RepeatMode::One;
+        assert_eq!(playlist.advance(), Some("0"));
+        assert_eq!(playlist.advance(), Some("0"));
+        assert_eq!(playlist.next_track(), Some("1"));
+        assert_eq!(playlist.advance(), Some("1"));
+        // Manual skips wrap like repeat all
+        assert_eq!(playlist.next_track(), Some("0"));
+        assert_eq!(playlist.previous_track(), Some("1"));
+    }
+
+    #[test]
+    fn shuffle_keeps_current_track_first() {
+        for start in 0..5 {
+            let mut playlist = playlist(5);
+            for _ in 0..start {
+                playlist.next_track();
+            }
+            playlist.toggle_shuffle();
+            assert_eq!(playlist.position(), 1);
+            assert_eq!(playlist.current(), Some(start.to_string().as_str()));
+        }
+    }
+
+    #[test]
+    fn shuffle_visits_every_track_once_per_pass() {
+        let mut playlist = playlist(20);
+        playlist.repeat = RepeatMode::All;
+        playlist.toggle_shuffle();
+        for _ in 0..3 {
+            let mut seen = [false; 20];
+            for _ in 0..20 {
+                let index: usize = playlist.current().unwrap().parse().unwrap();
+                assert!(!seen[index], "track {} played twice", index);
+                seen[index] = true;
+                playlist.advance();
+            }
+            assert!(seen.iter().all(|&s| s));
+        }
+    }
+
+    #[test]
+    fn peek_next_agrees_with_advance() {
+        for shuffle in [false, true] {
+            for repeat in [RepeatMode::Off, RepeatMode::All, RepeatMode::One] {
+                for count in [1, 2, 7] {
+                    let mut playlist = playlist(count);
+                    playlist.repeat = repeat;
+                    if shuffle {
+                        playlist.toggle_shuffle();
+                    }
+                    // Several passes, so shuffled playlists get reshuffled in between
+                    for _ in 0..count * 3 {
+                        let peeked = playlist.peek_next().map(str::to_string);
+                        let advanced = playlist.advance().map(str::to_string);
+                        assert_eq!(
+                            peeked,
+                            advanced,
+                            "shuffle {}, repeat {}, {} tracks",
+                            shuffle,
+                            repeat.name(),
+                            count
+                        );
+                        if advanced.is_none() {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unshuffle_restores_added_order_at_current_track() {
+        let mut playlist = playlist(6);
+        playlist.toggle_shuffle();
+        playlist.next_track();
+        playlist.next_track();
+        let current = playlist.current().unwrap().to_string();
+        playlist.toggle_shuffle();
+        assert_eq!(playlist.current(), Some(current.as_str()));
+        let order: Vec<String> = playlist.queue().into_iter().map(|t| t.path).collect();
+        assert_eq!(order, ["0", "1", "2", "3", "4", "5"]);
+    }
+
+    #[test]
+    fn playlist_import_skips_missing_nested_and_non_audio_entries() {
+        let dir = std::env::temp_dir().join(format!("playlist-import-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("song.mp3"), b"").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"").unwrap();
+        std::fs::write(
+            dir.join("list.m3u"),
+            "song.mp3\nmissing.mp3\nnotes.txt\nother.m3u\n",
+        )
+        .unwrap();
+
+        let mut playlist = playlist(0);
+        let missing = dir.join("missing.m3u");
+        assert_eq!(playlist.add_path(&missing.to_string_lossy()), 0);
+        assert_eq!(
+            playlist.add_path(&dir.join("list.m3u").to_string_lossy()),
+            1
+        );
+        assert_eq!(
+            playlist.current(),
+            Some(dir.join("song.mp3").to_string_lossy().as_ref())
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,409 @@
+use crate::playlist::Track;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub fn is_playlist_file(filename: &str) -> bool {
+    let lower = filename.to_lowercase();
+    [".m3u", ".m3u8", ".pls", ".xspf"]
+        .iter()
+        .any(|ext| lower.ends_with(ext))
+}
+
+// Reads an M3U/M3U8, PLS or XSPF playlist. Entries are resolved against the playlist's own
+// directory; remote URLs are skipped since raylib can only stream local files.
+pub fn load_playlist(path: &str) -> std::io::Result<Vec<Track>> {
+    // Some M3U files are Latin-1 rather than UTF-8, lossy decoding keeps the ASCII paths usable
+    let bytes = std::fs::read(path)?;
+    let contents = String::from_utf8_lossy(&bytes);
+    let contents = contents.trim_start_matches('\u{feff}');
+    let base = Path::new(path).parent().unwrap_or(Path::new("."));
+
+    let lower = path.to_lowercase();
+    let tracks = if lower.ends_with(".pls") {
+        parse_pls(contents, base)
+    } else if lower.ends_with(".xspf") {
+        parse_xspf(contents, base)
+    } else {
+        parse_m3u(contents, base)
+    };
+    Ok(tracks)
+}
+
+// Writes the queue as extended M3U in UTF-8, with absolute paths so the file can be moved
+pub fn save_m3u8(path: &str, tracks: &[Track]) -> std::io::Result<()> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(file, "#EXTM3U")?;
+    for track in tracks {
+        let title = track.title.clone().unwrap_or_else(|| {
+            Path::new(&track.path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        });
+        writeln!(file, "#EXTINF:{},{}", track.duration.unwrap_or(-1), title)?;
+        let absolute =
+            std::fs::canonicalize(&track.path).unwrap_or_else(|_| PathBuf::from(&track.path));
+        writeln!(file, "{}", absolute.to_string_lossy())?;
+    }
+    file.flush()
+}
+
+fn parse_m3u(contents: &str, base: &Path) -> Vec<Track> {
+    let mut tracks = Vec::new();
+    // #EXTINF applies to the next path line
+    let mut pending: (Option<i32>, Option<String>) = (None, None);
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            // #EXTINF:<seconds>[ attributes],<display title>
+            let (duration, title) = info.split_once(',').unwrap_or((info, ""));
+            let duration = duration
+                .split_whitespace()
+                .next()
+                .and_then(|d| d.parse::<f32>().ok())
+                .map(|d| d as i32)
+                .filter(|&d| d >= 0);
+            let title = Some(title.trim().to_string()).filter(|t| !t.is_empty());
+            pending = (duration, title);
+        } else if !line.is_empty() && !line.starts_with('#') {
+            if let Some(path) = resolve_entry(line, base) {
+                tracks.push(Track {
+                    path,
+                    duration: pending.0,
+                    title: pending.1.take(),
+                });
+            }
+            pending = (None, None);
+        }
+    }
+    tracks
+}
+
+fn parse_pls(contents: &str, base: &Path) -> Vec<Track> {
+    // FileN, TitleN and LengthN may come in any order, each key yields a partial track
+    let mut parts: Vec<(usize, Track)> = Vec::new();
+    for line in contents.lines() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_lowercase();
+        let value = value.trim();
+        let index = |prefix: &str| key.strip_prefix(prefix).and_then(|i| i.parse().ok());
+
+        if let Some(index) = index("file") {
+            if let Some(path) = resolve_entry(value, base) {
+                parts.push((
+                    index,
+                    Track {
+                        path,
+                        ..Track::default()
+                    },
+                ));
+            }
+        } else if let Some(index) = index("title") {
+            parts.push((
+                index,
+                Track {
+                    title: Some(value.to_string()).filter(|t| !t.is_empty()),
+                    ..Track::default()
+                },
+            ));
+        } else if let Some(index) = index("length") {
+            parts.push((
+                index,
+                Track {
+                    duration: value.parse().ok().filter(|&d: &i32| d >= 0),
+                    ..Track::default()
+                },
+            ));
+        }
+    }
+    merge_numbered(parts)
+}
+
+// Folds the per-key partial tracks of a PLS file into one track per entry number
+fn merge_numbered(mut parts: Vec<(usize, Track)>) -> Vec<Track> {
+    parts.sort_by_key(|(index, _)| *index);
+    let mut tracks: Vec<(usize, Track)> = Vec::new();
+    for (index, part) in parts {
+        if tracks.last().map(|(i, _)| *i) != Some(index) {
+            tracks.push((index, Track::default()));
+        }
+        let track = &mut tracks.last_mut().unwrap().1;
+        if !part.path.is_empty() {
+            track.path = part.path;
+        }
+        track.title = part.title.or(track.title.take());
+        track.duration = part.duration.or(track.duration);
+    }
+    tracks
+        .into_iter()
+        .map(|(_, track)| track)
+        .filter(|track| !track.path.is_empty())
+        .collect()
+}
+
+fn parse_xspf(contents: &str, base: &Path) -> Vec<Track> {
+    let mut tracks = Vec::new();
+    let mut rest = contents;
+    while let Some(start) = rest.find("<track>") {
+        let after = &rest[start..];
+        let end = after.find("</track>").unwrap_or(after.len());
+        let element = &after[..end];
+        rest = &after[end..];
+        if rest.starts_with("</track>") {
+            rest = &rest["</track>".len()..];
+        }
+
+        let Some(location) = xml_text(element, "location") else {
+            continue;
+        };
+        // Locations are URIs, so relative ones are percent-encoded as well
+        let location = if location.contains("://") {
+            location
+        } else {
+            percent_decode(&location)
+        };
+        if let Some(path) = resolve_entry(&location, base) {
+            tracks.push(Track {
+                path,
+                title: xml_text(element, "title"),
+                // XSPF durations are in milliseconds
+                duration: xml_text(element, "duration")
+                    .and_then(|d| d.parse::<i64>().ok())
+                    .map(|ms| (ms / 1000) as i32),
+            });
+        }
+    }
+    tracks
+}
+
+// Text content of the first <tag>...</tag> in `element`, entity-decoded
+fn xml_text(element: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = element.find(&open)? + open.len();
+    let end = start + element[start..].find(&close)?;
+    let text = element[start..end].trim();
+    let text = text
+        .strip_prefix("<![CDATA[")
+        .and_then(|t| t.strip_suffix("]]>"))
+        .map(String::from)
+        .unwrap_or_else(|| decode_entities(text));
+    Some(text).filter(|t| !t.is_empty())
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+// Turns a playlist entry (relative path, absolute path or file:// URI) into a local path
+fn resolve_entry(entry: &str, base: &Path) -> Option<String> {
+    let entry = entry.trim();
+    let path = if let Some(uri) = entry.strip_prefix("file://") {
+        // file:///home/... or file://localhost/home/...
+        let uri = uri.strip_prefix("localhost").unwrap_or(uri);
+        PathBuf::from(percent_decode(uri))
+    } else if entry.contains("://") {
+        println!("Skipping remote playlist entry {}", entry);
+        return None;
+    } else {
+        // Playlists written on Windows use backslashes
+        let entry = if cfg!(windows) {
+            entry.to_string()
+        } else {
+            entry.replace('\\', "/")
+        };
+        let path = PathBuf::from(entry);
+        if path.is_absolute() {
+            path
+        } else {
+            base.join(path)
+        }
+    };
+    Some(path.to_string_lossy().into_owned())
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(path: &str, title: Option<&str>, duration: Option<i32>) -> Track {
+        Track {
+            path: path.to_string(),
+            title: title.map(String::from),
+            duration,
+        }
+    }
+
+    fn assert_tracks(actual: Vec<Track>, expected: &[Track]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for (a, e) in actual.iter().zip(expected) {
+            assert_eq!(
+                (&a.path, &a.title, a.duration),
+                (&e.path, &e.title, e.duration)
+            );
+        }
+    }
+
+    #[test]
+    fn m3u_entries() {
+        let cases = [
+            ("a.mp3\n", vec![track("/music/a.mp3", None, None)]),
+            (
+                "#EXTM3U\n#EXTINF:123,Artist - Title\na.mp3\n",
+                vec![track("/music/a.mp3", Some("Artist - Title"), Some(123))],
+            ),
+            // Attributes before the comma, commas in the title, fractional and unknown lengths
+            (
+                "#EXTINF:-1 tvg-id=\"x\",A, B\na.mp3\n#EXTINF:4.5,\nb.mp3\n",
+                vec![
+                    track("/music/a.mp3", Some("A, B"), None),
+                    track("/music/b.mp3", None, Some(4)),
+                ],
+            ),
+            // EXTINF only applies to the next entry
+            (
+                "#EXTINF:10,First\na.mp3\nb.mp3\n",
+                vec![
+                    track("/music/a.mp3", Some("First"), Some(10)),
+                    track("/music/b.mp3", None, None),
+                ],
+            ),
+            (
+                "sub\\dir\\a.mp3\r\n/abs/b.mp3\r\n\r\n# comment\r\n",
+                vec![
+                    track("/music/sub/dir/a.mp3", None, None),
+                    track("/abs/b.mp3", None, None),
+                ],
+            ),
+            ("http://radio.example/stream\n", vec![]),
+        ];
+        for (contents, expected) in cases {
+            assert_tracks(parse_m3u(contents, Path::new("/music")), &expected);
+        }
+    }
+
+    #[test]
+    fn pls_entries() {
+        let cases = [
+            (
+                "[playlist]\nFile1=a.mp3\nTitle1=A\nLength1=60\nNumberOfEntries=1\n",
+                vec![track("/music/a.mp3", Some("A"), Some(60))],
+            ),
+            // Keys out of order, numbering gaps and lowercase keys
+            (
+                "Title7=B\nfile7=b.mp3\nFile2=a.mp3\nLength7=-1\n",
+                vec![
+                    track("/music/a.mp3", None, None),
+                    track("/music/b.mp3", Some("B"), None),
+                ],
+            ),
+            // Titles without a file are dropped
+            (
+                "Title1=Orphan\nFile2=a.mp3\n",
+                vec![track("/music/a.mp3", None, None)],
+            ),
+        ];
+        for (contents, expected) in cases {
+            assert_tracks(parse_pls(contents, Path::new("/music")), &expected);
+        }
+    }
+
+    #[test]
+    fn merge_numbered_folds_parts_by_index() {
+        let parts = vec![
+            (3, track("", Some("C"), None)),
+            (1, track("/a.mp3", None, None)),
+            (3, track("/c.mp3", None, Some(5))),
+            (1, track("", None, Some(7))),
+            (2, track("", Some("No file"), None)),
+        ];
+        assert_tracks(
+            merge_numbered(parts),
+            &[
+                track("/a.mp3", None, Some(7)),
+                track("/c.mp3", Some("C"), Some(5)),
+            ],
+        );
+    }
+
+    #[test]
+    fn xspf_entries() {
+        let cases = [
+            (
+                "<trackList><track><location>file:///music/a%20b.mp3</location>\
+                 <title>Rock &amp; Roll</title><duration>61500</duration></track></trackList>",
+                vec![track("/music/a b.mp3", Some("Rock & Roll"), Some(61))],
+            ),
+            (
+                "<track><location>file://localhost/music/a.mp3</location></track>\
+                 <track><title>No location</title></track>\
+                 <track><location>sub/%C3%A9t%C3%A9.flac</location>\
+                 <title><![CDATA[a &amp; b]]></title></track>",
+                vec![
+                    track("/music/a.mp3", None, None),
+                    track("/base/sub/été.flac", Some("a &amp; b"), None),
+                ],
+            ),
+            (
+                "<track><location>https://example.com/a.mp3</location></track>",
+                vec![],
+            ),
+        ];
+        for (contents, expected) in cases {
+            assert_tracks(parse_xspf(contents, Path::new("/base")), &expected);
+        }
+    }
+
+    #[test]
+    fn entities_decode_once() {
+        let cases = [
+            ("a &lt;b&gt; c", "a <b> c"),
+            ("&quot;x&quot; &apos;y&apos;", "\"x\" 'y'"),
+            ("&amp;lt;", "&lt;"),
+            ("plain", "plain"),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(decode_entities(text), expected);
+        }
+    }
+
+    #[test]
+    fn percent_decoding() {
+        let cases = [
+            ("a%20b", "a b"),
+            ("%C3%A9", "é"),
+            ("100%", "100%"),
+            ("%zz%4", "%zz%4"),
+            ("%2F%2f", "//"),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(percent_decode(text), expected);
+        }
+    }
+}
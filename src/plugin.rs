@@ -0,0 +1,256 @@
+use crate::visualizer::{VisualFrame, Visualizer};
+use libloading::Library;
+use raylib::ffi::{Color, DrawCircleV, DrawLineEx, DrawRectangleV, DrawText, Vector2};
+use std::ffi::{c_char, c_void, CStr};
+use std::path::Path;
+
+// Visualizer plugins are shared objects exporting `raven_plugin`, which returns a static
+// `RavenPlugin` describing them. The structs below are the C ABI they are built against, see
+// plugins/raven_plugin.h. Anything that changes their layout must bump the version.
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+const ENTRY_SYMBOL: &[u8] = b"raven_plugin\0";
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct RavenColor {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl From<Color> for RavenColor {
+    fn from(c: Color) -> RavenColor {
+        RavenColor {
+            r: c.r,
+            g: c.g,
+            b: c.b,
+            a: c.a,
+        }
+    }
+}
+
+impl From<RavenColor> for Color {
+    fn from(c: RavenColor) -> Color {
+        Color {
+            r: c.r,
+            g: c.g,
+            b: c.b,
+            a: c.a,
+        }
+    }
+}
+
+// One frame of analysis. The pointers are only valid for the duration of the draw call.
+#[repr(C)]
+pub struct RavenFrame {
+    // Smoothed bands, raw bands and peak-hold values, 0..=1, lowest frequency first
+    pub bands: *const f32,
+    pub raw_bands: *const f32,
+    pub peaks: *const f32,
+    pub band_count: usize,
+    // Newest raw samples of each channel in -1..1, triggered like the oscilloscope
+    pub left: *const f32,
+    pub right: *const f32,
+    pub sample_count: usize,
+    // -1 (out of phase) to 1 (mono)
+    pub correlation: f32,
+    // Seconds since the previous frame
+    pub delta: f32,
+}
+
+// Screen size, the current theme and the drawing functions a plugin draws with. Going through
+// these instead of linking raylib keeps plugins independent of the raylib version.
+#[repr(C)]
+pub struct RavenDrawContext {
+    pub width: i32,
+    pub height: i32,
+    pub background: RavenColor,
+    pub foreground: RavenColor,
+    // The sequence the built-in ray and radial modes cycle through
+    pub accents: [RavenColor; 6],
+    pub gradient: [RavenColor; 3],
+    pub rect: unsafe extern "C" fn(f32, f32, f32, f32, RavenColor),
+    pub line: unsafe extern "C" fn(f32, f32, f32, f32, f32, RavenColor),
+    pub circle: unsafe extern "C" fn(f32, f32, f32, RavenColor),
+    pub text: unsafe extern "C" fn(*const c_char, f32, f32, f32, RavenColor),
+}
+
+#[repr(C)]
+pub struct RavenPlugin {
+    pub abi_version: u32,
+    // Shown as the mode's name
+    pub name: *const c_char,
+    // Returns the state handed to `draw` and `destroy`, may be left out by stateless plugins
+    pub create: Option<unsafe extern "C" fn() -> *mut c_void>,
+    pub draw: Option<unsafe extern "C" fn(*mut c_void, *const RavenFrame, *const RavenDrawContext)>,
+    pub destroy: Option<unsafe extern "C" fn(*mut c_void)>,
+}
+
+unsafe extern "C" fn draw_rect(x: f32, y: f32, width: f32, height: f32, color: RavenColor) {
+    DrawRectangleV(
+        Vector2 { x, y },
+        Vector2 {
+            x: width,
+            y: height,
+        },
+        color.into(),
+    );
+}
+
+unsafe extern "C" fn draw_line(x1: f32, y1: f32, x2: f32, y2: f32, thick: f32, color: RavenColor) {
+    DrawLineEx(
+        Vector2 { x: x1, y: y1 },
+        Vector2 { x: x2, y: y2 },
+        thick,
+        color.into(),
+    );
+}
+
+unsafe extern "C" fn draw_circle(x: f32, y: f32, radius: f32, color: RavenColor) {
+    DrawCircleV(Vector2 { x, y }, radius, color.into());
+}
+
+unsafe extern "C" fn draw_text(text: *const c_char, x: f32, y: f32, size: f32, color: RavenColor) {
+    if !text.is_null() {
+        DrawText(text, x as i32, y as i32, size as i32, color.into());
+    }
+}
+
+// A loaded plugin and the state it created
+pub struct Plugin {
+    pub name: String,
+    descriptor: *const RavenPlugin,
+    state: *mut c_void,
+    // Dropped last, the descriptor and state point into it
+    _library: Library,
+}
+
+impl Plugin {
+    pub fn load(path: &Path) -> Result<Plugin, String> {
+        unsafe {
+            let library = Library::new(path).map_err(|e| e.to_string())?;
+            let entry = library
+                .get::<unsafe extern "C" fn() -> *const RavenPlugin>(ENTRY_SYMBOL)
+                .map_err(|e| e.to_string())?;
+            let descriptor = entry();
+            if descriptor.is_null() {
+                return Err("raven_plugin returned null".to_string());
+            }
+            let plugin = &*descriptor;
+            if plugin.abi_version != PLUGIN_ABI_VERSION {
+                return Err(format!(
+                    "built for plugin ABI {}, this player uses {}",
+                    plugin.abi_version, PLUGIN_ABI_VERSION
+                ));
+            }
+            if plugin.draw.is_none() {
+                return Err("no draw function".to_string());
+            }
+            let name = if plugin.name.is_null() {
+                path.file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default()
+            } else {
+                CStr::from_ptr(plugin.name).to_string_lossy().into_owned()
+            };
+            let state = match plugin.create {
+                Some(create) => create(),
+                None => std::ptr::null_mut(),
+            };
+            Ok(Plugin {
+                name,
+                descriptor,
+                state,
+                _library: library,
+            })
+        }
+    }
+}
+
+impl Visualizer for Plugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn draw(&mut self, frame: &VisualFrame) {
+        if !frame.bands_ready() {
+            return;
+        }
+        let smoother = &frame.smoothers[0];
+        let (left, right) = (frame.scope.left(), frame.scope.right());
+        let input = RavenFrame {
+            bands: smoother.values().as_ptr(),
+            raw_bands: frame.spectrum.bands.as_ptr(),
+            peaks: smoother.peaks().as_ptr(),
+            band_count: frame.band_count,
+            left: left.as_ptr(),
+            right: right.as_ptr(),
+            sample_count: left.len().min(right.len()),
+            correlation: frame.spectrum.correlation,
+            delta: frame.delta,
+        };
+        let theme = frame.theme;
+        let context = RavenDrawContext {
+            width: frame.width,
+            height: frame.height,
+            background: theme.background.into(),
+            foreground: theme.foreground.into(),
+            accents: std::array::from_fn(|i| theme.accent(i).into()),
+            gradient: theme.gradient.map(RavenColor::from),
+            rect: draw_rect,
+            line: draw_line,
+            circle: draw_circle,
+            text: draw_text,
+        };
+        unsafe {
+            if let Some(draw) = (*self.descriptor).draw {
+                draw(self.state, &input, &context);
+            }
+        }
+    }
+
+    // Plugins are handed the scope's samples too
+    fn wants_scope(&self) -> bool {
+        true
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(destroy) = (*self.descriptor).destroy {
+                destroy(self.state);
+            }
+        }
+    }
+}
+
+// Every shared object in `dir`, in name order. A plugin that fails to load is reported and
+// left out. Loading runs the plugin's code, so only trusted files belong in the directory.
+pub fn load_plugin_dir(dir: &Path) -> Vec<Plugin> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            p.extension()
+                .is_some_and(|ext| ext == std::env::consts::DLL_EXTENSION)
+        })
+        .collect();
+    paths.sort();
+
+    let mut plugins = Vec::new();
+    for path in paths {
+        match Plugin::load(&path) {
+            Ok(plugin) => {
+                println!("Loaded visualizer plugin {}", plugin.name);
+                plugins.push(plugin);
+            }
+            Err(e) => println!("Skipping plugin {}: {}", path.display(), e),
+        }
+    }
+    plugins
+}
@@ -0,0 +1,527 @@
+use crate::analysis::{Analyzer, SpectrumFrame};
+use crate::config::Config;
+use crate::equalizer::Equalizer;
+use crate::headless::HeadlessContext;
+use crate::scope::Oscilloscope;
+use crate::smoothing::{BandSmoother, SmoothingSettings};
+use crate::spectrum::{band_count, SpectrumSettings, BAND_LOW_HZ, BAND_STEP};
+use crate::visualizer::VisualFrame;
+use crate::{
+    callback, currentTheme, extract_metadata, gradientMode, LoadVisualizers, ProgressBarRect,
+    LEFT_RING, RIGHT_RING, SAMPLE_RING,
+};
+use raylib::ffi::{
+    BeginTextureMode, ClearBackground, ColorAlpha, DrawRectangleRec, DrawTextEx, EndTextureMode,
+    Font, ImageFlipVertical, LoadFontEx, LoadImageFromTexture, LoadRenderTexture, MeasureTextEx,
+    Rectangle, RenderTexture2D, UnloadFont, UnloadImage, UnloadRenderTexture, Vector2,
+};
+use rsmpeg::avcodec::{AVCodec, AVCodecContext, AVCodecParameters, AVPacket};
+use rsmpeg::avformat::{AVFormatContextInput, AVFormatContextOutput};
+use rsmpeg::avutil::{AVChannelLayout, AVFrame, AVRational};
+use rsmpeg::error::RsmpegError;
+use rsmpeg::ffi::{
+    avformat_query_codec, AVFMT_GLOBALHEADER, AVMEDIA_TYPE_AUDIO, AV_CODEC_FLAG_GLOBAL_HEADER,
+    AV_CODEC_ID_MPEG4, AV_PIX_FMT_RGBA, AV_PIX_FMT_YUV420P, AV_SAMPLE_FMT_FLT,
+    FF_COMPLIANCE_NORMAL, SWS_BILINEAR,
+};
+use rsmpeg::swresample::SwrContext;
+use rsmpeg::swscale::SwsContext;
+use std::ffi::CString;
+
+// Used when FFmpeg was built without libx264
+const FALLBACK_BIT_RATE: i64 = 8_000_000;
+
+// Decoded audio waiting to be fed to the analysis, interleaved stereo f32 in the layout the
+// stream processor callback expects
+struct PendingAudio {
+    samples: Vec<f32>,
+    // Frames decoded so far, including the ones already fed
+    decoded: usize,
+    fed: usize,
+    // The configured EQ, which the callback only runs during playback
+    equalizer: Equalizer,
+}
+
+impl PendingAudio {
+    // Feeds the frames up to `end` through the EQ and the same callback playback uses
+    fn feed_until(&mut self, end: usize) {
+        let count = end.min(self.decoded).saturating_sub(self.fed);
+        if count == 0 {
+            return;
+        }
+        self.equalizer.process(&mut self.samples[..count * 2]);
+        unsafe {
+            callback(
+                self.samples.as_mut_ptr() as *mut std::ffi::c_void,
+                count as u32,
+            )
+        };
+        self.samples.drain(..count * 2);
+        self.fed += count;
+    }
+}
+
+// Everything the video side of the render needs between frames
+struct VideoEncoder {
+    encoder: AVCodecContext,
+    sws: SwsContext,
+    rgba: AVFrame,
+    yuv: AVFrame,
+    stream_index: i32,
+    frames: i64,
+}
+
+impl VideoEncoder {
+    fn new(
+        output: &mut AVFormatContextOutput,
+        width: i32,
+        height: i32,
+        fps: i32,
+    ) -> Result<VideoEncoder, String> {
+        let x264 = CString::new("libx264").expect("CString new failed");
+        let codec = AVCodec::find_encoder_by_name(&x264)
+            .or_else(|| AVCodec::find_encoder(AV_CODEC_ID_MPEG4))
+            .ok_or("no H.264 or MPEG-4 encoder available")?;
+        // 4:2:0 needs even dimensions, the last row or column is dropped otherwise
+        let (videoWidth, videoHeight) = (width & !1, height & !1);
+
+        let mut encoder = AVCodecContext::new(&codec);
+        encoder.set_width(videoWidth);
+        encoder.set_height(videoHeight);
+        encoder.set_pix_fmt(AV_PIX_FMT_YUV420P);
+        encoder.set_time_base(AVRational { num: 1, den: fps });
+        encoder.set_framerate(AVRational { num: fps, den: 1 });
+        if codec.id == AV_CODEC_ID_MPEG4 {
+            encoder.set_bit_rate(FALLBACK_BIT_RATE);
+        }
+        if output.oformat().flags & AVFMT_GLOBALHEADER as i32 != 0 {
+            encoder.set_flags(encoder.flags | AV_CODEC_FLAG_GLOBAL_HEADER as i32);
+        }
+        encoder.open(None).map_err(|e| e.to_string())?;
+
+        let mut stream = output.new_stream();
+        stream.set_codecpar(encoder.extract_codecpar());
+        stream.set_time_base(encoder.time_base);
+        let stream_index = stream.index;
+
+        let mut rgba = AVFrame::new();
+        rgba.set_width(width);
+        rgba.set_height(height);
+        rgba.set_format(AV_PIX_FMT_RGBA);
+        rgba.alloc_buffer().map_err(|e| e.to_string())?;
+        let mut yuv = AVFrame::new();
+        yuv.set_width(videoWidth);
+        yuv.set_height(videoHeight);
+        yuv.set_format(AV_PIX_FMT_YUV420P);
+        yuv.alloc_buffer().map_err(|e| e.to_string())?;
+
+        let sws = SwsContext::get_context(
+            width,
+            height,
+            AV_PIX_FMT_RGBA,
+            videoWidth,
+            videoHeight,
+            AV_PIX_FMT_YUV420P,
+            SWS_BILINEAR,
+            None,
+            None,
+            None,
+        )
+        .ok_or("could not convert RGBA to YUV")?;
+
+        Ok(VideoEncoder {
+            encoder,
+            sws,
+            rgba,
+            yuv,
+            stream_index,
+            frames: 0,
+        })
+    }
+
+    // Reads back `target` and encodes it as the next frame
+    fn encode_target(
+        &mut self,
+        output: &mut AVFormatContextOutput,
+        target: RenderTexture2D,
+    ) -> Result<(), String> {
+        unsafe {
+            let mut image = LoadImageFromTexture(target.texture);
+            // OpenGL's origin is the bottom left, so render textures read back upside down
+            ImageFlipVertical(&mut image);
+            let row_bytes = image.width as usize * 4;
+            for y in 0..image.height as usize {
+                std::ptr::copy_nonoverlapping(
+                    (image.data as *const u8).add(y * row_bytes),
+                    self.rgba.data[0].add(y * self.rgba.linesize[0] as usize),
+                    row_bytes,
+                );
+            }
+            UnloadImage(image);
+        }
+
+        self.yuv.make_writable().map_err(|e| e.to_string())?;
+        self.sws
+            .scale_frame(&self.rgba, 0, self.rgba.height, &mut self.yuv)
+            .map_err(|e| e.to_string())?;
+        self.yuv.set_pts(self.frames);
+        self.frames += 1;
+        self.encoder
+            .send_frame(Some(&self.yuv))
+            .map_err(|e| e.to_string())?;
+        self.write_packets(output)
+    }
+
+    // Flushes the frames the encoder still holds
+    fn finish(&mut self, output: &mut AVFormatContextOutput) -> Result<(), String> {
+        self.encoder.send_frame(None).map_err(|e| e.to_string())?;
+        self.write_packets(output)
+    }
+
+    fn write_packets(&mut self, output: &mut AVFormatContextOutput) -> Result<(), String> {
+        let streamTimeBase = output.streams()[self.stream_index as usize].time_base;
+        loop {
+            match self.encoder.receive_packet() {
+                Ok(mut packet) => {
+                    packet.set_stream_index(self.stream_index);
+                    packet.rescale_ts(self.encoder.time_base, streamTimeBase);
+                    output
+                        .interleaved_write_frame(&mut packet)
+                        .map_err(|e| e.to_string())?;
+                }
+                Err(RsmpegError::EncoderDrainError) | Err(RsmpegError::EncoderFlushedError) => {
+                    return Ok(())
+                }
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+}
+
+// Where the original audio stream's packets are copied to
+struct AudioCopy {
+    input_index: usize,
+    output_index: i32,
+    input_time_base: AVRational,
+    output_time_base: AVRational,
+}
+
+impl AudioCopy {
+    fn write(
+        &self,
+        output: &mut AVFormatContextOutput,
+        packet: &mut AVPacket,
+    ) -> Result<(), String> {
+        packet.set_stream_index(self.output_index);
+        packet.rescale_ts(self.input_time_base, self.output_time_base);
+        packet.set_pos(-1);
+        output
+            .interleaved_write_frame(packet)
+            .map_err(|e| e.to_string())
+    }
+}
+
+// Title, track and progress, the parts of the player's overlay that make sense in a video
+fn DrawRenderOverlay(font: Font, width: i32, height: i32, trackName: &str, progress: f32) {
+    unsafe {
+        let title = CString::new("Rusty rAVen").expect("CString new failed");
+        let titleSize = MeasureTextEx(font, title.as_ptr(), 40.0, 2.0);
+        DrawTextEx(
+            font,
+            title.as_ptr(),
+            Vector2 {
+                x: (width / 2) as f32 - titleSize.x / 2.0,
+                y: 20.0,
+            },
+            40.0,
+            2.0,
+            currentTheme.blue,
+        );
+
+        let track = CString::new(trackName).unwrap_or_default();
+        DrawTextEx(
+            font,
+            track.as_ptr(),
+            Vector2 {
+                x: 10.0,
+                y: height as f32 - 30.0,
+            },
+            20.0,
+            1.0,
+            currentTheme.foreground,
+        );
+
+        let progressBar = ProgressBarRect(width, height);
+        DrawRectangleRec(progressBar, ColorAlpha(currentTheme.foreground, 0.2));
+        DrawRectangleRec(
+            Rectangle {
+                width: progressBar.width * progress.clamp(0.0, 1.0),
+                ..progressBar
+            },
+            currentTheme.blue,
+        );
+    }
+}
+
+// Decodes `input` as fast as possible, draws the current visual mode for every video frame at
+// the config's fps and writes the frames to `path` with the original audio stream copied in.
+// Draws through a headless EGL context, so it needs no window or display server.
+pub fn render_to_file(input: &str, path: &str, config: &Config) -> Result<(), String> {
+    let c_input = CString::new(input).map_err(|e| e.to_string())?;
+    let mut inputCtx =
+        AVFormatContextInput::open(&c_input, None, &mut None).map_err(|e| e.to_string())?;
+    let (audioIndex, decoderCodec) = inputCtx
+        .find_best_stream(AVMEDIA_TYPE_AUDIO)
+        .map_err(|e| e.to_string())?
+        .ok_or("no audio stream")?;
+    let audioCodecpar = AVCodecParameters::clone(&inputCtx.streams()[audioIndex].codecpar());
+    let audioTimeBase = inputCtx.streams()[audioIndex].time_base;
+    let mut decoder = AVCodecContext::new(&decoderCodec);
+    decoder
+        .apply_codecpar(&audioCodecpar)
+        .map_err(|e| e.to_string())?;
+    decoder.set_pkt_timebase(audioTimeBase);
+    decoder.open(None).map_err(|e| e.to_string())?;
+
+    let c_path = CString::new(path).map_err(|e| e.to_string())?;
+    let mut output = AVFormatContextOutput::create(&c_path, None).map_err(|e| e.to_string())?;
+    // The audio is copied as it is, so the container has to be able to hold its codec
+    if unsafe {
+        avformat_query_codec(
+            output.oformat().as_ptr(),
+            audioCodecpar.codec_id,
+            FF_COMPLIANCE_NORMAL as i32,
+        )
+    } == 0
+    {
+        return Err(format!(
+            "{} can't hold this file's audio codec, try a .mkv output",
+            path
+        ));
+    }
+
+    let context = HeadlessContext::new(config.width, config.height)?;
+
+    let result = (|| {
+        let mut video = VideoEncoder::new(&mut output, config.width, config.height, config.fps)?;
+
+        let mut audioStream = output.new_stream();
+        let mut copiedCodecpar = audioCodecpar.clone();
+        // The input container's tag may mean something else in the output one
+        unsafe { (*copiedCodecpar.as_mut_ptr()).codec_tag = 0 };
+        audioStream.set_codecpar(copiedCodecpar);
+        audioStream.set_time_base(audioTimeBase);
+        let audioOutIndex = audioStream.index;
+
+        output.write_header(&mut None).map_err(|e| e.to_string())?;
+        let audio = AudioCopy {
+            input_index: audioIndex,
+            output_index: audioOutIndex,
+            input_time_base: audioTimeBase,
+            output_time_base: output.streams()[audioOutIndex as usize].time_base,
+        };
+
+        // Whatever was rendered before an error is still finished into a playable file
+        let rendered = RenderFrames(
+            input,
+            &mut inputCtx,
+            &mut decoder,
+            &mut output,
+            &mut video,
+            &audio,
+            config,
+        );
+        let finished = video
+            .finish(&mut output)
+            .and_then(|_| output.write_trailer().map_err(|e| e.to_string()));
+        rendered.and(finished)
+    })();
+
+    drop(context);
+    result
+}
+
+// The decode, draw and encode loop of `render_to_file`
+fn RenderFrames(
+    input: &str,
+    inputCtx: &mut AVFormatContextInput,
+    decoder: &mut AVCodecContext,
+    output: &mut AVFormatContextOutput,
+    video: &mut VideoEncoder,
+    audio: &AudioCopy,
+    config: &Config,
+) -> Result<(), String> {
+    let metadata = extract_metadata(input).unwrap_or_default();
+    let trackName = match (metadata.artist.is_empty(), metadata.title.is_empty()) {
+        (false, false) => format!("{} - {}", metadata.artist, metadata.title),
+        (true, false) => metadata.title.clone(),
+        _ => std::path::Path::new(input)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    };
+    let sampleRate = decoder.sample_rate;
+    let fps = config.fps;
+    let (width, height) = (config.width, config.height);
+    let m = band_count(BAND_LOW_HZ, BAND_STEP, config.fft_size);
+    let settings = SpectrumSettings {
+        sample_rate: sampleRate as u32,
+        bands: m,
+        ..SpectrumSettings::default()
+    };
+    let mut analyzer = Analyzer::new(config.fft_size);
+    let mut spectrum = SpectrumFrame::new(config.fft_size);
+    let mut smoothers = [
+        BandSmoother::new(SmoothingSettings::default()),
+        BandSmoother::new(SmoothingSettings::default()),
+        BandSmoother::new(SmoothingSettings::default()),
+    ];
+    let mut scope = Oscilloscope::new();
+    let mut visualizers = LoadVisualizers(config);
+    let delta = 1.0 / fps as f32;
+
+    let stereo = AVChannelLayout::from_nb_channels(2);
+    let mut resampler: Option<SwrContext> = None;
+    // Designed for the file's own rate, which the analysis runs at here
+    let (equalizer, mut eqControl) = Equalizer::new();
+    eqControl.set(config.equalizer.design(sampleRate as u32));
+    let mut pending = PendingAudio {
+        samples: Vec::new(),
+        decoded: 0,
+        fed: 0,
+        equalizer,
+    };
+
+    unsafe {
+        let font_path = CString::new(config.font_path.clone()).expect("CString failed");
+        let font = LoadFontEx(font_path.as_ptr(), 24, std::ptr::null_mut(), 0);
+        let target = LoadRenderTexture(width, height);
+
+        let mut flushed = false;
+        let result = (|| -> Result<(), String> {
+            loop {
+                // Decode until the next video frame's audio is complete, or the file ends
+                let frameEnd =
+                    ((video.frames + 1) as f64 * sampleRate as f64 / fps as f64).round() as usize;
+                while pending.decoded < frameEnd && !flushed {
+                    match inputCtx.read_packet().map_err(|e| e.to_string())? {
+                        Some(mut packet) => {
+                            if packet.stream_index as usize != audio.input_index {
+                                continue;
+                            }
+                            decoder
+                                .send_packet(Some(&packet))
+                                .map_err(|e| e.to_string())?;
+                            audio.write(output, &mut packet)?;
+                        }
+                        None => {
+                            decoder.send_packet(None).map_err(|e| e.to_string())?;
+                            flushed = true;
+                        }
+                    }
+                    DrainDecoder(decoder, &stereo, &mut resampler, &mut pending)?;
+                }
+                if pending.fed >= pending.decoded {
+                    return Ok(());
+                }
+
+                pending.feed_until(frameEnd);
+                analyzer.analyse(
+                    &SAMPLE_RING,
+                    &LEFT_RING,
+                    &RIGHT_RING,
+                    &settings,
+                    &mut spectrum,
+                );
+                smoothers[0].update(&spectrum.bands, delta);
+                smoothers[1].update(&spectrum.channel_bands[0], delta);
+                smoothers[2].update(&spectrum.channel_bands[1], delta);
+                if visualizers.wants_scope() {
+                    scope.capture(&LEFT_RING, &RIGHT_RING);
+                }
+
+                BeginTextureMode(target);
+                ClearBackground(currentTheme.background);
+                visualizers.draw(&VisualFrame {
+                    spectrum: &spectrum,
+                    smoothers: &smoothers,
+                    scope: &scope,
+                    band_count: m,
+                    sample_rate: sampleRate as u32,
+                    width,
+                    height,
+                    delta,
+                    theme: currentTheme,
+                    gradient: gradientMode,
+                });
+                let elapsed = pending.fed as f32 / sampleRate as f32;
+                let progress = if metadata.duration > 0.0 {
+                    elapsed / metadata.duration
+                } else {
+                    0.0
+                };
+                DrawRenderOverlay(font, width, height, &trackName, progress);
+                EndTextureMode();
+
+                video.encode_target(output, target)?;
+                if video.frames % (fps as i64 * 10) == 0 {
+                    println!("Rendered {:.0} s", elapsed);
+                }
+            }
+        })();
+
+        UnloadFont(font);
+        UnloadRenderTexture(target);
+        result
+    }
+}
+
+// Converts every frame the decoder has ready to interleaved stereo f32 at the file's rate
+fn DrainDecoder(
+    decoder: &mut AVCodecContext,
+    stereo: &AVChannelLayout,
+    resampler: &mut Option<SwrContext>,
+    pending: &mut PendingAudio,
+) -> Result<(), String> {
+    loop {
+        let frame = match decoder.receive_frame() {
+            Ok(frame) => frame,
+            Err(RsmpegError::DecoderDrainError) | Err(RsmpegError::DecoderFlushedError) => {
+                return Ok(())
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+        if resampler.is_none() {
+            let mut swr = SwrContext::new(
+                stereo,
+                AV_SAMPLE_FMT_FLT,
+                frame.sample_rate,
+                &frame.ch_layout,
+                frame.format,
+                frame.sample_rate,
+            )
+            .map_err(|e| e.to_string())?;
+            swr.init().map_err(|e| e.to_string())?;
+            *resampler = Some(swr);
+        }
+        let Some(swr) = resampler.as_mut() else {
+            continue;
+        };
+
+        let capacity = swr.get_out_samples(frame.nb_samples).max(0) as usize;
+        let start = pending.samples.len();
+        pending.samples.resize(start + capacity * 2, 0.0);
+        let mut out = pending.samples[start..].as_mut_ptr() as *mut u8;
+        let converted = unsafe {
+            swr.convert(
+                &mut out,
+                capacity as i32,
+                frame.extended_data as *const *const u8,
+                frame.nb_samples,
+            )
+        }
+        .map_err(|e| e.to_string())? as usize;
+        pending.samples.truncate(start + converted * 2);
+        pending.decoded += converted;
+    }
+}
@@ -0,0 +1,102 @@
+use crate::analysis::SampleRing;
+
+// Number of samples drawn across the screen, about 46 ms at 44.1 kHz
+pub const SCOPE_WINDOW: usize = 2048;
+
+// How far into the captured history a trigger point is searched for. The capture is this much
+// longer than the window so that any trigger found still leaves a full window to draw.
+const TRIGGER_SEARCH: usize = SCOPE_WINDOW;
+
+// The signal must dip this far below the trigger level before a crossing counts, so noise
+// riding on the level doesn't retrigger
+const TRIGGER_HYSTERESIS: f32 = 0.02;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ScopeView {
+    // Single trace of (L + R) / 2
+    Mono,
+    // Left channel on the upper half, right channel on the lower half
+    Split,
+    // Vectorscope: L against R, rotated 45 degrees so mono content is a vertical line
+    XY,
+}
+
+impl ScopeView {
+    pub fn next(self) -> ScopeView {
+        match self {
+            ScopeView::Mono => ScopeView::Split,
+            ScopeView::Split => ScopeView::XY,
+            ScopeView::XY => ScopeView::Mono,
+        }
+    }
+}
+
+// Render-side state of the WAVEFORM mode. Captures the newest raw samples from the scope rings
+// every frame and finds a stable point to start drawing from.
+pub struct Oscilloscope {
+    pub view: ScopeView,
+    pub trigger_level: f32,
+    left: Vec<f32>,
+    right: Vec<f32>,
+    mid: Vec<f32>,
+    trigger: usize,
+}
+
+impl Oscilloscope {
+    pub fn new() -> Oscilloscope {
+        let len = SCOPE_WINDOW + TRIGGER_SEARCH;
+        Oscilloscope {
+            view: ScopeView::Mono,
+            trigger_level: 0.0,
+            left: vec![0.0; len],
+            right: vec![0.0; len],
+            mid: vec![0.0; len],
+            trigger: 0,
+        }
+    }
+
+    pub fn capture(&mut self, left: &SampleRing, right: &SampleRing) {
+        SampleRing::read_stereo_aligned(left, right, &mut self.left, &mut self.right);
+        for ((mid, l), r) in self.mid.iter_mut().zip(&self.left).zip(&self.right) {
+            *mid = (l + r) / 2.0;
+        }
+
+        self.trigger = find_trigger(&self.mid, self.trigger_level, TRIGGER_SEARCH)
+            // Free-run on the newest samples when nothing crosses the level (silence, DC)
+            .unwrap_or(TRIGGER_SEARCH);
+    }
+
+    pub fn left(&self) -> &[f32] {
+        &self.left[self.trigger..self.trigger + SCOPE_WINDOW]
+    }
+
+    pub fn right(&self) -> &[f32] {
+        &self.right[self.trigger..self.trigger + SCOPE_WINDOW]
+    }
+
+    pub fn mid(&self) -> &[f32] {
+        &self.mid[self.trigger..self.trigger + SCOPE_WINDOW]
+    }
+}
+
+impl Default for Oscilloscope {
+    fn default() -> Oscilloscope {
+        Oscilloscope::new()
+    }
+}
+
+// Index of the last rising crossing of `level` within the first `search` samples. Using the
+// last one keeps the drawn window as close as possible to the newest audio.
+fn find_trigger(samples: &[f32], level: f32, search: usize) -> Option<usize> {
+    let mut armed = false;
+    let mut found = None;
+    for i in 1..search.min(samples.len()) {
+        if samples[i] < level - TRIGGER_HYSTERESIS {
+            armed = true;
+        } else if armed && samples[i - 1] < level && samples[i] >= level {
+            found = Some(i);
+            armed = false;
+        }
+    }
+    found
+}
@@ -0,0 +1,96 @@
+// Time constants and peak behaviour of BandSmoother, all in seconds so they hold at any FPS
+#[derive(Copy, Clone, Debug)]
+pub struct SmoothingSettings {
+    // Time constant used while a band is rising
+    pub attack: f32,
+    // Time constant used while a band is falling
+    pub release: f32,
+    // How long a peak marker stays put before it starts to fall
+    pub peak_hold: f32,
+    // Acceleration of a falling peak marker, in full-scale units per second squared
+    pub gravity: f32,
+}
+
+impl Default for SmoothingSettings {
+    fn default() -> SmoothingSettings {
+        SmoothingSettings {
+            attack: 0.015,
+            release: 0.12,
+            peak_hold: 0.5,
+            gravity: 2.5,
+        }
+    }
+}
+
+// Per-band attack/release smoothing with peak-hold markers.
+//
+// Owned by the render loop and fed the latest bands once per frame. Visualizations that opt in
+// read `values` instead of the raw bands and can draw `peaks` on top.
+pub struct BandSmoother {
+    pub settings: SmoothingSettings,
+    values: Vec<f32>,
+    peaks: Vec<f32>,
+    peak_velocity: Vec<f32>,
+    // Seconds left before each peak starts falling
+    peak_timer: Vec<f32>,
+}
+
+impl BandSmoother {
+    pub fn new(settings: SmoothingSettings) -> BandSmoother {
+        BandSmoother {
+            settings,
+            values: Vec::new(),
+            peaks: Vec::new(),
+            peak_velocity: Vec::new(),
+            peak_timer: Vec::new(),
+        }
+    }
+
+    pub fn values(&self) -> &[f32] {
+        &self.values
+    }
+
+    pub fn peaks(&self) -> &[f32] {
+        &self.peaks
+    }
+
+    // Drops all history, e.g. when the band count changes or playback jumps
+    pub fn reset(&mut self) {
+        self.values.clear();
+        self.peaks.clear();
+        self.peak_velocity.clear();
+        self.peak_timer.clear();
+    }
+
+    // Advances the state by `dt` seconds towards `bands`
+    pub fn update(&mut self, bands: &[f32], dt: f32) {
+        if self.values.len() != bands.len() {
+            self.values = bands.to_vec();
+            self.peaks = bands.to_vec();
+            self.peak_velocity = vec![0.0; bands.len()];
+            self.peak_timer = vec![self.settings.peak_hold; bands.len()];
+            return;
+        }
+
+        // One-pole filter coefficient for this frame's duration: 1 - e^(-dt / tau)
+        let attack = 1.0 - (-dt / self.settings.attack.max(1e-4)).exp();
+        let release = 1.0 - (-dt / self.settings.release.max(1e-4)).exp();
+
+        for (i, &target) in bands.iter().enumerate() {
+            let value = &mut self.values[i];
+            let k = if target > *value { attack } else { release };
+            *value += (target - *value) * k;
+
+            if *value >= self.peaks[i] {
+                self.peaks[i] = *value;
+                self.peak_velocity[i] = 0.0;
+                self.peak_timer[i] = self.settings.peak_hold;
+            } else if self.peak_timer[i] > 0.0 {
+                self.peak_timer[i] -= dt;
+            } else {
+                self.peak_velocity[i] += self.settings.gravity * dt;
+                self.peaks[i] = (self.peaks[i] - self.peak_velocity[i] * dt).max(*value);
+            }
+        }
+    }
+}
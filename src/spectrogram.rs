@@ -0,0 +1,304 @@
+use crate::spectrum::{BandAggregation, BandLayout};
+use crate::theme::{rgb, sample_stops, Theme};
+use crate::visualizer::{VisualFrame, Visualizer};
+use raylib::ffi::{
+    Color, DrawTextureRec, GenImageColor, LoadTextureFromImage, Rectangle, Texture2D, UnloadImage,
+    UnloadTexture, UpdateTexture, UpdateTextureRec, Vector2,
+};
+
+// Matplotlib's maps sampled at nine evenly spaced points
+const VIRIDIS: [Color; 9] = [
+    rgb(0x440154),
+    rgb(0x472d7b),
+    rgb(0x3b528b),
+    rgb(0x2c728e),
+    rgb(0x21918c),
+    rgb(0x28ae80),
+    rgb(0x5ec962),
+    rgb(0xaddc30),
+    rgb(0xfde725),
+];
+
+const MAGMA: [Color; 9] = [
+    rgb(0x000004),
+    rgb(0x1c1044),
+    rgb(0x4f127b),
+    rgb(0x812581),
+    rgb(0xb5367a),
+    rgb(0xe55064),
+    rgb(0xfb8761),
+    rgb(0xfec287),
+    rgb(0xfcfdbf),
+];
+
+const WHITE: Color = rgb(0xffffff);
+
+// How the spectrogram turns a display value into a color
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Colormap {
+    #[default]
+    Viridis,
+    Magma,
+    // From the theme's background through its gradient
+    Theme,
+}
+
+impl Colormap {
+    // Names used in the config file, in `next` order
+    pub const NAMES: [&'static str; 3] = ["viridis", "magma", "theme"];
+
+    pub fn from_name(name: &str) -> Option<Colormap> {
+        match name.to_ascii_lowercase().as_str() {
+            "viridis" => Some(Colormap::Viridis),
+            "magma" => Some(Colormap::Magma),
+            "theme" => Some(Colormap::Theme),
+            _ => None,
+        }
+    }
+
+    pub fn next(self) -> Colormap {
+        match self {
+            Colormap::Viridis => Colormap::Magma,
+            Colormap::Magma => Colormap::Theme,
+            Colormap::Theme => Colormap::Viridis,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Colormap::Viridis => "Viridis",
+            Colormap::Magma => "Magma",
+            Colormap::Theme => "Theme gradient",
+        }
+    }
+
+    // Color of `value` (0..=1)
+    pub fn color(self, value: f32, theme: Theme) -> Color {
+        match self {
+            Colormap::Viridis => sample_stops(&VIRIDIS, value),
+            Colormap::Magma => sample_stops(&MAGMA, value),
+            Colormap::Theme => sample_stops(
+                &[
+                    theme.background,
+                    theme.gradient[0],
+                    theme.gradient[1],
+                    theme.gradient[2],
+                ],
+                value,
+            ),
+        }
+    }
+}
+
+// Identifies the colors the texture was last painted with. raylib's Color can't be compared.
+fn palette_key(colormap: Colormap, theme: Theme) -> (Colormap, [u32; 4]) {
+    let pack = |c: Color| u32::from_be_bytes([c.r, c.g, c.b, c.a]);
+    (
+        colormap,
+        [
+            pack(theme.background),
+            pack(theme.gradient[0]),
+            pack(theme.gradient[1]),
+            pack(theme.gradient[2]),
+        ],
+    )
+}
+
+// SPECTROGRAM: a waterfall scrolling right to left, one texture column per time step with the
+// lowest frequency at the bottom on a log scale. The texture has one texel per screen pixel and
+// is used as a ring of columns, so scrolling only uploads the new ones.
+#[derive(Default)]
+pub struct Spectrogram {
+    texture: Option<Texture2D>,
+    width: usize,
+    height: usize,
+    // Display value of every texel, row by row like the texture, so the history can be
+    // repainted when the colormap or theme changes
+    values: Vec<f32>,
+    // Upload scratch, a full texture's worth
+    pixels: Vec<Color>,
+    // Loudest value per row since the last column was written, lowest frequency first
+    column: Vec<f32>,
+    rows: Vec<f32>,
+    // Next column to write, which is also the oldest one on screen
+    write: usize,
+    // Fraction of a column owed from the time that has passed
+    pending: f32,
+    layout: Option<BandLayout>,
+    last_sequence: u64,
+    painted: Option<(Colormap, [u32; 4])>,
+    colormap: Colormap,
+    // Seconds of history on screen
+    history: f32,
+}
+
+impl Spectrogram {
+    pub fn new(colormap: Colormap, history: f32) -> Spectrogram {
+        let mut spectrogram = Spectrogram::default();
+        spectrogram.colormap = colormap;
+        spectrogram.history = history;
+        spectrogram
+    }
+
+    fn unload(&mut self) {
+        if let Some(texture) = self.texture.take() {
+            unsafe { UnloadTexture(texture) };
+        }
+    }
+
+    // Stores the accumulated column at the write position and uploads it
+    fn write_column(&mut self, colormap: Colormap, theme: Theme) {
+        let (x, height) = (self.write, self.height);
+        for (row, &value) in self.column.iter().enumerate() {
+            let y = height - 1 - row;
+            self.values[y * self.width + x] = value;
+            self.pixels[y] = colormap.color(value, theme);
+        }
+        if let Some(texture) = self.texture {
+            unsafe {
+                UpdateTextureRec(
+                    texture,
+                    Rectangle {
+                        x: x as f32,
+                        y: 0.0,
+                        width: 1.0,
+                        height: height as f32,
+                    },
+                    self.pixels.as_ptr() as *const std::ffi::c_void,
+                );
+            }
+        }
+        self.write = (self.write + 1) % self.width;
+    }
+
+    // Recolors the whole history
+    fn repaint(&mut self, colormap: Colormap, theme: Theme) {
+        for (pixel, &value) in self.pixels.iter_mut().zip(&self.values) {
+            *pixel = colormap.color(value, theme);
+        }
+        if let Some(texture) = self.texture {
+            unsafe { UpdateTexture(texture, self.pixels.as_ptr() as *const std::ffi::c_void) };
+        }
+        self.painted = Some(palette_key(colormap, theme));
+    }
+}
+
+impl Visualizer for Spectrogram {
+    fn name(&self) -> &str {
+        "spectrogram"
+    }
+
+    fn init(&mut self, width: i32, height: i32) {
+        self.unload();
+        self.width = width.max(1) as usize;
+        self.height = height.max(1) as usize;
+        self.values = vec![0.0; self.width * self.height];
+        self.pixels = vec![WHITE; self.width * self.height];
+        self.column = vec![0.0; self.height];
+        self.write = 0;
+        self.pending = 0.0;
+        self.painted = None;
+        unsafe {
+            let image = GenImageColor(width, height, WHITE);
+            self.texture = Some(LoadTextureFromImage(image));
+            UnloadImage(image);
+        }
+    }
+
+    fn resize(&mut self, width: i32, height: i32) {
+        self.init(width, height);
+    }
+
+    fn update(&mut self, frame: &VisualFrame) {
+        let (colormap, theme, history) = (self.colormap, frame.theme, self.history);
+        if self.painted != Some(palette_key(colormap, theme)) {
+            self.repaint(colormap, theme);
+        }
+
+        // Stands still while no new audio is analysed, e.g. when paused
+        let magnitudes = &frame.spectrum.magnitudes;
+        if frame.spectrum.sequence == self.last_sequence || magnitudes.is_empty() {
+            return;
+        }
+        self.last_sequence = frame.spectrum.sequence;
+
+        let half = magnitudes.len();
+        if !self
+            .layout
+            .as_ref()
+            .is_some_and(|l| l.matches(self.height, half, frame.sample_rate))
+        {
+            self.layout = Some(BandLayout::new(self.height, half, frame.sample_rate));
+        }
+        if let Some(layout) = &self.layout {
+            layout.aggregate(magnitudes, BandAggregation::Peak, &mut self.rows);
+        }
+        for (held, &value) in self.column.iter_mut().zip(&self.rows) {
+            *held = held.max(value);
+        }
+
+        // The screen holds `history` seconds whatever the frame rate
+        self.pending =
+            (self.pending + frame.delta * self.width as f32 / history).min(self.width as f32);
+        let columns = (self.pending as usize).min(self.width);
+        self.pending -= columns as f32;
+        for _ in 0..columns {
+            self.write_column(colormap, theme);
+        }
+        if columns > 0 {
+            self.column.fill(0.0);
+        }
+    }
+
+    fn colormap(&self) -> Option<Colormap> {
+        Some(self.colormap)
+    }
+
+    fn next_colormap(&mut self) {
+        self.colormap = self.colormap.next();
+    }
+
+    fn draw(&mut self, _frame: &VisualFrame) {
+        let Some(texture) = self.texture else {
+            return;
+        };
+        // Oldest columns from the write position on go on the left, the newest on the right
+        let (width, height) = (self.width as f32, self.height as f32);
+        let split = self.write as f32;
+        unsafe {
+            DrawTextureRec(
+                texture,
+                Rectangle {
+                    x: split,
+                    y: 0.0,
+                    width: width - split,
+                    height,
+                },
+                Vector2 { x: 0.0, y: 0.0 },
+                WHITE,
+            );
+            if split > 0.0 {
+                DrawTextureRec(
+                    texture,
+                    Rectangle {
+                        x: 0.0,
+                        y: 0.0,
+                        width: split,
+                        height,
+                    },
+                    Vector2 {
+                        x: width - split,
+                        y: 0.0,
+                    },
+                    WHITE,
+                );
+            }
+        }
+    }
+}
+
+impl Drop for Spectrogram {
+    fn drop(&mut self) {
+        self.unload();
+    }
+}
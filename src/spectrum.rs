@@ -0,0 +1,313 @@
+use num_complex::Complex32;
+use std::f32::consts::PI;
+
+// Window applied to the samples before the FFT, trading main lobe width for leakage
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WindowFunction {
+    Hann,
+    Hamming,
+    BlackmanHarris,
+    FlatTop,
+}
+
+impl WindowFunction {
+    pub fn next(self) -> WindowFunction {
+        match self {
+            WindowFunction::Hann => WindowFunction::Hamming,
+            WindowFunction::Hamming => WindowFunction::BlackmanHarris,
+            WindowFunction::BlackmanHarris => WindowFunction::FlatTop,
+            WindowFunction::FlatTop => WindowFunction::Hann,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WindowFunction::Hann => "Hann",
+            WindowFunction::Hamming => "Hamming",
+            WindowFunction::BlackmanHarris => "Blackman-Harris",
+            WindowFunction::FlatTop => "Flat-top",
+        }
+    }
+
+    // Cosine-sum coefficients a0, a1, ... of w(i) = a0 - a1 cos(x) + a2 cos(2x) - ...
+    fn cosine_terms(self) -> &'static [f32] {
+        match self {
+            WindowFunction::Hann => &[0.5, 0.5],
+            WindowFunction::Hamming => &[0.54, 0.46],
+            WindowFunction::BlackmanHarris => &[0.35875, 0.48829, 0.14128, 0.01168],
+            WindowFunction::FlatTop => &[
+                0.215_578_95,
+                0.416_631_58,
+                0.277_263_16,
+                0.083_578_95,
+                0.006_947_37,
+            ],
+        }
+    }
+
+    // Fills `dest` with the periodic form of the window, which is what spectral analysis wants
+    pub fn fill(self, dest: &mut [f32]) {
+        let n = dest.len() as f32;
+        let terms = self.cosine_terms();
+        for (i, w) in dest.iter_mut().enumerate() {
+            let x = 2.0 * PI * i as f32 / n;
+            *w = terms
+                .iter()
+                .enumerate()
+                .map(|(k, a)| {
+                    let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+                    sign * a * (k as f32 * x).cos()
+                })
+                .sum();
+        }
+    }
+}
+
+// How the FFT bins falling into one band are combined
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BandAggregation {
+    Peak,
+    Average,
+}
+
+impl BandAggregation {
+    pub fn name(self) -> &'static str {
+        match self {
+            BandAggregation::Peak => "Peak",
+            BandAggregation::Average => "Average",
+        }
+    }
+}
+
+// Which pair of signals gets a spectrum of its own next to the downmix
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StereoView {
+    // Downmix only
+    Mono,
+    LeftRight,
+    // (L + R) / 2 and (L - R) / 2, the side spectrum shows what only lives in the stereo image
+    MidSide,
+}
+
+impl StereoView {
+    pub fn next(self) -> StereoView {
+        match self {
+            StereoView::Mono => StereoView::LeftRight,
+            StereoView::LeftRight => StereoView::MidSide,
+            StereoView::MidSide => StereoView::Mono,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StereoView::Mono => "Mono",
+            StereoView::LeftRight => "L/R",
+            StereoView::MidSide => "M/S",
+        }
+    }
+}
+
+// Knobs of the magnitude computation, read by the analysis thread every frame
+#[derive(Copy, Clone, Debug)]
+pub struct SpectrumSettings {
+    pub window: WindowFunction,
+    // Map magnitudes to decibels between `db_floor` and 0 dBFS instead of linear amplitude
+    pub db_scale: bool,
+    pub db_floor: f32,
+    // dB per octave added relative to 1 kHz, compensates the 1/f roll-off of music
+    pub tilt: f32,
+    pub sample_rate: u32,
+    // Number of log-spaced bands handed to the visualizations
+    pub bands: usize,
+    pub aggregation: BandAggregation,
+    pub stereo: StereoView,
+}
+
+impl Default for SpectrumSettings {
+    fn default() -> SpectrumSettings {
+        SpectrumSettings {
+            window: WindowFunction::Hann,
+            db_scale: true,
+            db_floor: -80.0,
+            tilt: 3.0,
+            sample_rate: 44100,
+            // Same count the default 8192-point FFT gives in the render loop
+            bands: band_count(BAND_LOW_HZ, BAND_STEP, 1 << 13),
+            aggregation: BandAggregation::Peak,
+            stereo: StereoView::Mono,
+        }
+    }
+}
+
+// Tilt is pivoted around this frequency, so it leaves the mids where they are
+const TILT_REFERENCE_HZ: f32 = 1000.0;
+
+// Converts the positive-frequency half of an FFT (`bins`, n/2 long) to display values in 0..=1.
+//
+// `window_sum` is the sum of the window coefficients: dividing by it (times two for the
+// discarded negative frequencies) makes a full-scale sine read 1.0, i.e. 0 dBFS, whatever the
+// window and FFT size.
+pub fn compute_magnitudes(
+    bins: &[Complex32],
+    window_sum: f32,
+    settings: &SpectrumSettings,
+    magnitudes: &mut [f32],
+) {
+    let scale = 2.0 / window_sum;
+    let bin_hz = settings.sample_rate as f32 / (2 * bins.len()) as f32;
+
+    for (i, (bin, magnitude)) in bins.iter().zip(magnitudes.iter_mut()).enumerate() {
+        let linear = bin.norm() * scale;
+        let tilt_db = if settings.tilt != 0.0 && i > 0 {
+            settings.tilt * (i as f32 * bin_hz / TILT_REFERENCE_HZ).log2()
+        } else {
+            0.0
+        };
+
+        *magnitude = if settings.db_scale {
+            let db = 20.0 * linear.max(1e-12).log10() + tilt_db;
+            ((db - settings.db_floor) / -settings.db_floor).clamp(0.0, 1.0)
+        } else {
+            (linear * 10.0_f32.powf(tilt_db / 20.0)).clamp(0.0, 1.0)
+        };
+    }
+}
+
+// Lowest band edge and the ratio between consecutive band edges
+pub const BAND_LOW_HZ: f32 = 20.0;
+pub const BAND_STEP: f32 = 1.06;
+
+// Number of bands obtained by stepping from `low` by `step` until `high` is reached
+pub fn band_count(low: f32, step: f32, high: usize) -> usize {
+    let mut m = 0;
+    let mut f = low;
+    while f < high as f32 {
+        m += 1;
+        f *= step;
+    }
+    m
+}
+
+// Maps the n/2 positive-frequency bins onto `count` log-spaced bands running from BAND_LOW_HZ
+// up to Nyquist. Rebuilt only when the band count, FFT size or sample rate change.
+pub struct BandLayout {
+    // Half-open bin range covered by each band, never empty
+    ranges: Vec<(usize, usize)>,
+    count: usize,
+    half: usize,
+    sample_rate: u32,
+}
+
+impl BandLayout {
+    pub fn new(count: usize, half: usize, sample_rate: u32) -> BandLayout {
+        let nyquist = sample_rate as f32 / 2.0;
+        let bin_hz = nyquist / half as f32;
+        let ratio = (nyquist / BAND_LOW_HZ).max(1.0);
+
+        let ranges = (0..count)
+            .map(|band| {
+                let low_hz = BAND_LOW_HZ * ratio.powf(band as f32 / count as f32);
+                let high_hz = BAND_LOW_HZ * ratio.powf((band + 1) as f32 / count as f32);
+                let low = ((low_hz / bin_hz).round() as usize).min(half - 1);
+                let high = ((high_hz / bin_hz).round() as usize).clamp(low + 1, half);
+                (low, high)
+            })
+            .collect();
+
+        BandLayout {
+            ranges,
+            count,
+            half,
+            sample_rate,
+        }
+    }
+
+    pub fn matches(&self, count: usize, half: usize, sample_rate: u32) -> bool {
+        self.count == count && self.half == half && self.sample_rate == sample_rate
+    }
+
+    pub fn aggregate(
+        &self,
+        magnitudes: &[f32],
+        aggregation: BandAggregation,
+        bands: &mut Vec<f32>,
+    ) {
+        bands.clear();
+        bands.extend(self.ranges.iter().map(|&(low, high)| {
+            let bins = &magnitudes[low..high];
+            match aggregation {
+                BandAggregation::Peak => bins.iter().cloned().fold(0.0, f32::max),
+                BandAggregation::Average => bins.iter().sum::<f32>() / bins.len() as f32,
+            }
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Band counts, bin counts and rates the settings allow, including more bands than bins
+    const LAYOUTS: [(usize, usize, u32); 6] = [
+        (1, 512, 48000),
+        (64, 512, 44100),
+        (128, 4096, 48000),
+        (200, 64, 48000),
+        (32, 16384, 96000),
+        (10, 8, 8000),
+    ];
+
+    #[test]
+    fn bands_are_monotonic_and_never_empty() {
+        for (count, half, rate) in LAYOUTS {
+            let layout = BandLayout::new(count, half, rate);
+            assert_eq!(layout.ranges.len(), count);
+            for &(low, high) in &layout.ranges {
+                assert!(low < high && high <= half, "{:?}", (count, half, rate));
+            }
+            for pair in layout.ranges.windows(2) {
+                assert!(pair[0].0 <= pair[1].0 && pair[0].1 <= pair[1].1);
+            }
+        }
+    }
+
+    #[test]
+    fn bands_cover_every_bin_from_low_edge_to_nyquist() {
+        for (count, half, rate) in LAYOUTS {
+            let layout = BandLayout::new(count, half, rate);
+            let bin_hz = rate as f32 / 2.0 / half as f32;
+            let first = ((BAND_LOW_HZ / bin_hz).round() as usize).min(half - 1);
+            assert_eq!(layout.ranges[0].0, first);
+            assert_eq!(layout.ranges[count - 1].1, half);
+            // No gaps: each band starts at or before the end of the previous one
+            for pair in layout.ranges.windows(2) {
+                assert!(pair[1].0 <= pair[0].1, "{:?}", (count, half, rate));
+            }
+        }
+    }
+
+    #[test]
+    fn matches_only_the_same_parameters() {
+        let layout = BandLayout::new(64, 2048, 48000);
+        assert!(layout.matches(64, 2048, 48000));
+        assert!(!layout.matches(65, 2048, 48000));
+        assert!(!layout.matches(64, 1024, 48000));
+        assert!(!layout.matches(64, 2048, 44100));
+    }
+
+    #[test]
+    fn aggregate_takes_peak_or_average_of_each_band() {
+        let layout = BandLayout {
+            ranges: vec![(0, 2), (1, 4)],
+            count: 2,
+            half: 4,
+            sample_rate: 48000,
+        };
+        let magnitudes = [0.2, 0.4, 0.6, 0.2];
+        let mut bands = vec![1.0; 5];
+        layout.aggregate(&magnitudes, BandAggregation::Peak, &mut bands);
+        assert_eq!(bands, [0.4, 0.6]);
+        layout.aggregate(&magnitudes, BandAggregation::Average, &mut bands);
+        assert!((bands[0] - 0.3).abs() < 1e-6 && (bands[1] - 0.4).abs() < 1e-6);
+    }
+}
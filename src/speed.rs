@@ -0,0 +1,374 @@
+// Limits and steps for the keys changing the speed
+pub const MIN_TEMPO: f32 = 0.25;
+pub const MAX_TEMPO: f32 = 2.0;
+pub const MAX_PITCH: i32 = 12;
+pub const MIN_VARISPEED: f32 = 0.5;
+pub const MAX_VARISPEED: f32 = 2.0;
+// Tempo and varispeed move in 5% steps, twenty of them to 100%. Pitch moves in semitones.
+const STEPS_PER_UNIT: f32 = 20.0;
+
+// Pieces the time stretch cuts the input into, about 43 ms at 48 kHz. Long enough to hold a
+// few periods of a bass note, short enough that transients don't smear into echoes.
+const WINDOW: usize = 2048;
+// The output moves on by half a piece, so every frame is made of two overlapping pieces
+const HOP: usize = WINDOW / 2;
+// How far a piece may move from where it nominally starts to line up with the previous one
+const TOLERANCE: usize = 256;
+// Only every few frames are compared while lining up, plenty to find the best match
+const SEARCH_STEP: usize = 4;
+
+// How fast and how high tracks play. Tempo keeps the pitch, pitch keeps the tempo and
+// varispeed changes both, like a tape running faster.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Speed {
+    pub tempo: f32,
+    // Semitones up or down
+    pub pitch: i32,
+    pub varispeed: f32,
+}
+
+impl Default for Speed {
+    fn default() -> Speed {
+        Speed {
+            tempo: 1.0,
+            pitch: 0,
+            varispeed: 1.0,
+        }
+    }
+}
+
+impl Speed {
+    // Playing as recorded, the audio then skips the time stretch altogether
+    pub fn is_normal(self) -> bool {
+        self == Speed::default()
+    }
+
+    pub fn step_tempo(self, steps: i32) -> Speed {
+        Speed {
+            tempo: step_percent(self.tempo, steps, MIN_TEMPO, MAX_TEMPO),
+            ..self
+        }
+    }
+
+    pub fn step_pitch(self, steps: i32) -> Speed {
+        Speed {
+            pitch: (self.pitch + steps).clamp(-MAX_PITCH, MAX_PITCH),
+            ..self
+        }
+    }
+
+    pub fn step_varispeed(self, steps: i32) -> Speed {
+        Speed {
+            varispeed: step_percent(self.varispeed, steps, MIN_VARISPEED, MAX_VARISPEED),
+            ..self
+        }
+    }
+
+    fn pitch_ratio(self) -> f32 {
+        2f32.powf(self.pitch as f32 / 12.0)
+    }
+
+    // What the time stretch speeds the audio up by. Raising the pitch by resampling also
+    // shortens it, the stretch makes up for that.
+    pub fn stretch(self) -> f32 {
+        self.tempo / self.pitch_ratio()
+    }
+
+    // What the resampler speeds the audio up by, which raises the pitch as much
+    pub fn resample(self) -> f32 {
+        self.pitch_ratio() * self.varispeed
+    }
+
+    // Seconds of the track that play in one second
+    pub fn rate(self) -> f32 {
+        self.tempo * self.varispeed
+    }
+
+    // The settings that differ from normal, for the HUD
+    pub fn describe(self) -> String {
+        let mut parts = Vec::new();
+        if self.tempo != 1.0 {
+            parts.push(format!("Tempo {:.0}%", self.tempo * 100.0));
+        }
+        if self.pitch != 0 {
+            parts.push(format!("Pitch {:+} st", self.pitch));
+        }
+        if self.varispeed != 1.0 {
+            parts.push(format!("Varispeed {:.0}%", self.varispeed * 100.0));
+        }
+        parts.join(" | ")
+    }
+}
+
+// Rounded to whole steps, so going up and back down lands exactly on 100% again
+fn step_percent(value: f32, steps: i32, min: f32, max: f32) -> f32 {
+    (((value * STEPS_PER_UNIT).round() + steps as f32) / STEPS_PER_UNIT).clamp(min, max)
+}
+
+// Changes the tempo of interleaved stereo without changing its pitch, by WSOLA (waveform
+// similarity overlap-add). Pieces of the input are taken a little closer together or further
+// apart than they are put down in the output, each moved within TOLERANCE to where it lines
+// up best with what followed the previous piece, so the waveforms join without a phase jump.
+pub struct Wsola {
+    speed: f32,
+    window: Vec<f32>,
+    // Input not cut up yet, along with what the next search still looks at
+    input: Vec<f32>,
+    // Where the next piece nominally starts in `input`, in frames
+    position: f64,
+    // Where the audio following the previous piece starts in `input`, what the next piece
+    // should continue like
+    continuation: Option<usize>,
+    // The windowed second half of the previous piece, the next piece's first half adds to it
+    overlap: Vec<f32>,
+    // Finished audio waiting to be pulled
+    output: Vec<f32>,
+    // Set once no more input comes, to the frames of output the rest of the input makes
+    remaining: Option<usize>,
+}
+
+impl Wsola {
+    pub fn new() -> Wsola {
+        // A periodic Hann window, two of them half a window apart add up to exactly 1
+        let window = (0..WINDOW)
+            .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / WINDOW as f32).cos())
+            .collect();
+        Wsola {
+            speed: 1.0,
+            window,
+            input: Vec::new(),
+            position: 0.0,
+            continuation: None,
+            overlap: vec![0.0; HOP * 2],
+            output: Vec::new(),
+            remaining: None,
+        }
+    }
+
+    // Above 1 plays faster, below 1 slower. Takes effect from the next piece on.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    pub fn push(&mut self, samples: &[f32]) {
+        self.input.extend_from_slice(samples);
+    }
+
+    // No more input is coming, the rest of it is played out
+    pub fn finish(&mut self) {
+        if self.remaining.is_some() {
+            return;
+        }
+        // The output so far covers the input up to the next piece
+        let left = (self.input.len() / 2) as f64 - self.position;
+        self.remaining = Some((left.max(0.0) / self.speed as f64).round() as usize);
+    }
+
+    // Forgets all audio, for a seek or a new track
+    pub fn clear(&mut self) {
+        self.input.clear();
+        self.position = 0.0;
+        self.continuation = None;
+        self.overlap.fill(0.0);
+        self.output.clear();
+        self.remaining = None;
+    }
+
+    // Whether nothing is left to pull, also after `finish` until the rest is out
+    pub fn is_idle(&self) -> bool {
+        self.input.is_empty() && self.output.is_empty()
+    }
+
+    // Frames of input between the next frame `pull` hands out and the newest input
+    pub fn latency(&self) -> f32 {
+        (self.input.len() / 2) as f32 - self.position as f32
+            + (self.output.len() / 2) as f32 * self.speed
+    }
+
+    // Copies up to `out.len() / 2` frames into `out` and returns how many. Fewer come back
+    // when more input is needed, or once everything is out after `finish`.
+    pub fn pull(&mut self, out: &mut [f32]) -> usize {
+        while self.output.len() < out.len() && self.step() {}
+        if self.remaining == Some(0) {
+            self.input.clear();
+        }
+        let count = (out.len() / 2).min(self.output.len() / 2);
+        out[..count * 2].copy_from_slice(&self.output[..count * 2]);
+        self.output.drain(..count * 2);
+        count
+    }
+
+    // Cuts the next piece and adds up to HOP frames to the output. False when more input is
+    // needed or everything is out.
+    fn step(&mut self) -> bool {
+        let nominal = self.position.round() as usize;
+        let needed = (nominal + TOLERANCE + WINDOW) * 2;
+        match self.remaining {
+            Some(0) => return false,
+            // Silence after the end lets the last pieces be cut like any other
+            Some(_) if needed > self.input.len() => self.input.resize(needed, 0.0),
+            None if needed > self.input.len() => return false,
+            _ => {}
+        }
+        let start = match self.continuation {
+            None => nominal,
+            Some(target) => self.best_match(target, nominal),
+        };
+
+        let emitted = self.remaining.map_or(HOP, |remaining| remaining.min(HOP));
+        for i in 0..WINDOW {
+            let weight = self.window[i];
+            for c in 0..2 {
+                let sample = self.input[(start + i) * 2 + c] * weight;
+                if i < HOP {
+                    if i < emitted {
+                        self.output.push(self.overlap[i * 2 + c] + sample);
+                    }
+                } else {
+                    self.overlap[(i - HOP) * 2 + c] = sample;
+                }
+            }
+        }
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= emitted;
+        }
+        self.position += HOP as f64 * self.speed as f64;
+
+        // Drop the input nothing looks at again: the next search starts TOLERANCE before the
+        // next piece and compares against what follows this one
+        let next = self.position.round() as usize;
+        let unused = next.saturating_sub(TOLERANCE).min(start + HOP);
+        self.input.drain(..unused * 2);
+        self.position -= unused as f64;
+        self.continuation = Some(start + HOP - unused);
+        true
+    }
+
+    // The start within TOLERANCE of `nominal` whose first half is most like the audio at
+    // `target`, by normalized cross-correlation of the mono mix
+    fn best_match(&self, target: usize, nominal: usize) -> usize {
+        let mono = |frame: usize| self.input[frame * 2] + self.input[frame * 2 + 1];
+        let score = |candidate: usize| {
+            let mut correlation = 0.0;
+            let mut energy = 0.0;
+            for i in (0..HOP).step_by(SEARCH_STEP) {
+                let sample = mono(candidate + i);
+                correlation += mono(target + i) * sample;
+                energy += sample * sample;
+            }
+            if energy > 0.0 {
+                correlation / energy.sqrt()
+            } else {
+                0.0
+            }
+        };
+        // Only a strictly better match moves the piece, so silence stays where it is
+        let mut best = (nominal, score(nominal));
+        for candidate in nominal.saturating_sub(TOLERANCE)..=nominal + TOLERANCE {
+            let candidate_score = score(candidate);
+            if candidate_score > best.1 {
+                best = (candidate, candidate_score);
+            }
+        }
+        best.0
+    }
+}
+
+impl Default for Wsola {
+    fn default() -> Wsola {
+        Wsola::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f32, frames: usize) -> Vec<f32> {
+        let mut samples = Vec::with_capacity(frames * 2);
+        for i in 0..frames {
+            let sample = 0.5 * (2.0 * std::f32::consts::PI * frequency * i as f32 / 48000.0).sin();
+            samples.push(sample);
+            samples.push(sample);
+        }
+        samples
+    }
+
+    // Everything `wsola` makes of `input`, fed in buffer-sized chunks like the player does
+    fn stretch(speed: f32, input: &[f32]) -> Vec<f32> {
+        let mut wsola = Wsola::new();
+        wsola.set_speed(speed);
+        let mut output = Vec::new();
+        let mut out = vec![0.0; 4096 * 2];
+        for chunk in input.chunks(4096 * 2) {
+            wsola.push(chunk);
+            let count = wsola.pull(&mut out);
+            output.extend_from_slice(&out[..count * 2]);
+        }
+        wsola.finish();
+        loop {
+            let count = wsola.pull(&mut out);
+            if count == 0 {
+                break;
+            }
+            output.extend_from_slice(&out[..count * 2]);
+        }
+        assert!(wsola.is_idle());
+        output
+    }
+
+    fn frequency(samples: &[f32]) -> f32 {
+        let left: Vec<f32> = samples.iter().step_by(2).copied().collect();
+        let crossings = left
+            .windows(2)
+            .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+            .count();
+        crossings as f32 * 48000.0 / left.len() as f32
+    }
+
+    #[test]
+    fn normal_speed_passes_audio_through() {
+        let input = sine(440.0, 48000);
+        let output = stretch(1.0, &input);
+        assert_eq!(output.len(), input.len());
+        // Only the first half piece fades in
+        for (a, b) in output[HOP * 2..].iter().zip(&input[HOP * 2..]) {
+            assert!((a - b).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn tempo_changes_length_not_pitch() {
+        let input = sine(440.0, 48000 * 2);
+        for speed in [0.5, 0.8, 1.5, 2.0] {
+            let output = stretch(speed, &input);
+            let expected = input.len() as f32 / speed;
+            assert!(
+                (output.len() as f32 - expected).abs() < (WINDOW * 2) as f32,
+                "{}: {} frames",
+                speed,
+                output.len() / 2
+            );
+            let middle = &output[output.len() / 4..output.len() * 3 / 4];
+            let measured = frequency(middle);
+            assert!((measured - 440.0).abs() < 5.0, "{}: {} Hz", speed, measured);
+        }
+    }
+
+    #[test]
+    fn steps_return_to_normal() {
+        let speed = Speed::default()
+            .step_tempo(-3)
+            .step_varispeed(2)
+            .step_pitch(5);
+        assert_eq!(speed.tempo, 0.85);
+        assert_eq!(speed.describe(), "Tempo 85% | Pitch +5 st | Varispeed 110%");
+        assert!(speed
+            .step_tempo(3)
+            .step_varispeed(-2)
+            .step_pitch(-5)
+            .is_normal());
+        assert_eq!(Speed::default().step_pitch(-20).pitch, -MAX_PITCH);
+        assert_eq!(Speed::default().step_tempo(-100).tempo, MIN_TEMPO);
+    }
+}
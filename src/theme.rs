@@ -0,0 +1,321 @@
+use crate::config::location;
+use raylib::ffi::Color;
+use serde::Deserialize;
+use std::path::Path;
+use toml::Spanned;
+
+// Color roles every visualization draws with. The accents are named by hue so presets from
+// other color schemes map onto them, the gradient runs from quiet/low to loud/high.
+#[derive(Copy, Clone, Debug)]
+pub struct Theme {
+    pub background: Color,
+    pub foreground: Color,
+    pub red: Color,
+    pub orange: Color,
+    pub yellow: Color,
+    pub green: Color,
+    pub aqua: Color,
+    pub blue: Color,
+    pub purple: Color,
+    pub gradient: [Color; 3],
+}
+
+pub const fn rgb(hex: u32) -> Color {
+    Color {
+        r: (hex >> 16) as u8,
+        g: (hex >> 8) as u8,
+        b: hex as u8,
+        a: 255,
+    }
+}
+
+pub const GRUVBOX: Theme = Theme {
+    background: rgb(0x282828),
+    foreground: rgb(0xebdbb2),
+    red: rgb(0xfb4934),
+    orange: rgb(0xfe8019),
+    yellow: rgb(0xfabd2f),
+    green: rgb(0xb8bb26),
+    aqua: rgb(0x8ec07c),
+    blue: rgb(0x83a598),
+    purple: rgb(0xd3869b),
+    gradient: [rgb(0xb8bb26), rgb(0xfabd2f), rgb(0xfb4934)],
+};
+
+pub const NORD: Theme = Theme {
+    background: rgb(0x2e3440),
+    foreground: rgb(0xeceff4),
+    red: rgb(0xbf616a),
+    orange: rgb(0xd08770),
+    yellow: rgb(0xebcb8b),
+    green: rgb(0xa3be8c),
+    aqua: rgb(0x8fbcbb),
+    blue: rgb(0x81a1c1),
+    purple: rgb(0xb48ead),
+    gradient: [rgb(0x5e81ac), rgb(0x88c0d0), rgb(0xeceff4)],
+};
+
+pub const DRACULA: Theme = Theme {
+    background: rgb(0x282a36),
+    foreground: rgb(0xf8f8f2),
+    red: rgb(0xff5555),
+    orange: rgb(0xffb86c),
+    yellow: rgb(0xf1fa8c),
+    green: rgb(0x50fa7b),
+    aqua: rgb(0x8be9fd),
+    blue: rgb(0x6272a4),
+    purple: rgb(0xbd93f9),
+    gradient: [rgb(0xbd93f9), rgb(0xff79c6), rgb(0xf1fa8c)],
+};
+
+// Mocha, the dark flavour
+pub const CATPPUCCIN: Theme = Theme {
+    background: rgb(0x1e1e2e),
+    foreground: rgb(0xcdd6f4),
+    red: rgb(0xf38ba8),
+    orange: rgb(0xfab387),
+    yellow: rgb(0xf9e2af),
+    green: rgb(0xa6e3a1),
+    aqua: rgb(0x94e2d5),
+    blue: rgb(0x89b4fa),
+    purple: rgb(0xcba6f7),
+    gradient: [rgb(0x89b4fa), rgb(0xcba6f7), rgb(0xf38ba8)],
+};
+
+// Solarized dark
+pub const SOLARIZED: Theme = Theme {
+    background: rgb(0x002b36),
+    foreground: rgb(0x93a1a1),
+    red: rgb(0xdc322f),
+    orange: rgb(0xcb4b16),
+    yellow: rgb(0xb58900),
+    green: rgb(0x859900),
+    aqua: rgb(0x2aa198),
+    blue: rgb(0x268bd2),
+    purple: rgb(0x6c71c4),
+    gradient: [rgb(0x268bd2), rgb(0x2aa198), rgb(0x859900)],
+};
+
+pub const PRESETS: [(&str, Theme); 5] = [
+    ("Gruvbox", GRUVBOX),
+    ("Nord", NORD),
+    ("Dracula", DRACULA),
+    ("Catppuccin", CATPPUCCIN),
+    ("Solarized", SOLARIZED),
+];
+
+impl Theme {
+    // Accent `i` of the repeating sequence the ray and radial modes cycle through
+    pub fn accent(self, i: usize) -> Color {
+        [
+            self.yellow,
+            self.blue,
+            self.green,
+            self.red,
+            self.orange,
+            self.purple,
+        ][i % 6]
+    }
+
+    // Color at `t` (0..=1) along the gradient stops
+    pub fn gradient_at(self, t: f32) -> Color {
+        sample_stops(&self.gradient, t)
+    }
+}
+
+// Color at `t` (0..=1) along evenly spaced `stops`, at least two of them
+pub fn sample_stops(stops: &[Color], t: f32) -> Color {
+    let position = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+    let index = (position as usize).min(stops.len() - 2);
+    lerp_color(stops[index], stops[index + 1], position - index as f32)
+}
+
+fn lerp_color(from: Color, to: Color, t: f32) -> Color {
+    let channel = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+    Color {
+        r: channel(from.r, to.r),
+        g: channel(from.g, to.g),
+        b: channel(from.b, to.b),
+        a: channel(from.a, to.a),
+    }
+}
+
+// How bar colors are picked
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GradientMode {
+    // Each mode's own accent colors
+    Off,
+    // Along the gradient from the lowest to the highest band
+    Frequency,
+    // Along the gradient from silence to full scale, within each bar
+    Amplitude,
+}
+
+impl GradientMode {
+    pub fn next(self) -> GradientMode {
+        match self {
+            GradientMode::Off => GradientMode::Frequency,
+            GradientMode::Frequency => GradientMode::Amplitude,
+            GradientMode::Amplitude => GradientMode::Off,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GradientMode::Off => "Accents",
+            GradientMode::Frequency => "Gradient by frequency",
+            GradientMode::Amplitude => "Gradient by amplitude",
+        }
+    }
+}
+
+// "#rrggbb" or "#rrggbbaa"
+pub fn parse_color(text: &str) -> Option<Color> {
+    let hex = text.trim().strip_prefix('#')?;
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some(Color {
+        r: channel(0)?,
+        g: channel(2)?,
+        b: channel(4)?,
+        a: if hex.len() == 8 { channel(6)? } else { 255 },
+    })
+}
+
+// Color roles as written in a theme file or the config's [colors] table. Roles left out keep
+// the value of the theme they are applied to.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeOverrides {
+    pub name: Option<String>,
+    #[serde(alias = "bg")]
+    background: Option<Spanned<String>>,
+    #[serde(alias = "fg")]
+    foreground: Option<Spanned<String>>,
+    red: Option<Spanned<String>>,
+    orange: Option<Spanned<String>>,
+    yellow: Option<Spanned<String>>,
+    green: Option<Spanned<String>>,
+    aqua: Option<Spanned<String>>,
+    blue: Option<Spanned<String>>,
+    purple: Option<Spanned<String>>,
+    gradient: Option<Spanned<Vec<String>>>,
+}
+
+impl ThemeOverrides {
+    pub fn is_empty(&self) -> bool {
+        let colors = [
+            &self.background,
+            &self.foreground,
+            &self.red,
+            &self.orange,
+            &self.yellow,
+            &self.green,
+            &self.aqua,
+            &self.blue,
+            &self.purple,
+        ];
+        colors.iter().all(|c| c.is_none()) && self.gradient.is_none()
+    }
+
+    // `source` is the TOML text the overrides were read from, for error positions
+    pub fn apply(&self, theme: &mut Theme, source: &str) -> Result<(), String> {
+        let color = |value: &Option<Spanned<String>>, role: &str, target: &mut Color| {
+            if let Some(v) = value {
+                *target = parse_color(v.get_ref()).ok_or_else(|| {
+                    format!(
+                        "{}: {} must be \"#rrggbb\" or \"#rrggbbaa\", got \"{}\"",
+                        location(source, v.span().start),
+                        role,
+                        v.get_ref()
+                    )
+                })?;
+            }
+            Ok::<(), String>(())
+        };
+        color(&self.background, "background", &mut theme.background)?;
+        color(&self.foreground, "foreground", &mut theme.foreground)?;
+        color(&self.red, "red", &mut theme.red)?;
+        color(&self.orange, "orange", &mut theme.orange)?;
+        color(&self.yellow, "yellow", &mut theme.yellow)?;
+        color(&self.green, "green", &mut theme.green)?;
+        color(&self.aqua, "aqua", &mut theme.aqua)?;
+        color(&self.blue, "blue", &mut theme.blue)?;
+        color(&self.purple, "purple", &mut theme.purple)?;
+
+        if let Some(stops) = &self.gradient {
+            let at = location(source, stops.span().start);
+            let parsed: Option<Vec<Color>> =
+                stops.get_ref().iter().map(|s| parse_color(s)).collect();
+            let parsed = parsed.ok_or_else(|| {
+                format!(
+                    "{}: gradient stops must be \"#rrggbb\" or \"#rrggbbaa\"",
+                    at
+                )
+            })?;
+            theme.gradient = match parsed[..] {
+                // Two stops get their midpoint in the middle
+                [low, high] => [low, lerp_color(low, high, 0.5), high],
+                [low, mid, high] => [low, mid, high],
+                _ => {
+                    return Err(format!(
+                        "{}: gradient needs 2 or 3 stops, got {}",
+                        at,
+                        parsed.len()
+                    ))
+                }
+            };
+        }
+        Ok(())
+    }
+}
+
+// Reads a theme file: an optional `name` and the roles to change from Gruvbox. The theme is
+// named after the file unless it sets `name`.
+pub fn load_theme_file(path: &Path) -> Result<(String, Theme), String> {
+    let source = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let file: ThemeOverrides = toml::from_str(&source).map_err(|e| match e.span() {
+        Some(span) => format!(
+            "{}:{}: {}",
+            path.display(),
+            location(&source, span.start),
+            e.message()
+        ),
+        None => format!("{}: {}", path.display(), e.message()),
+    })?;
+
+    let mut theme = GRUVBOX;
+    file.apply(&mut theme, &source)
+        .map_err(|e| format!("{}:{}", path.display(), e))?;
+    let name = file.name.unwrap_or_else(|| {
+        path.file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    });
+    Ok((name, theme))
+}
+
+// Every *.toml in `dir`, in name order. A broken file is reported and left out rather than
+// keeping the player from starting.
+pub fn load_theme_dir(dir: &Path) -> Vec<(String, Theme)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+
+    let mut themes = Vec::new();
+    for path in paths {
+        match load_theme_file(&path) {
+            Ok(theme) => themes.push(theme),
+            Err(e) => println!("Skipping theme {}", e),
+        }
+    }
+    themes
+}
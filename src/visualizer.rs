@@ -0,0 +1,149 @@
+use crate::analysis::SpectrumFrame;
+use crate::scope::Oscilloscope;
+use crate::smoothing::BandSmoother;
+use crate::spectrogram::Colormap;
+use crate::theme::{GradientMode, Theme};
+
+// Everything a visualization mode draws from in one frame
+pub struct VisualFrame<'a> {
+    // Raw bands, the stereo view and correlation as the analysis thread published them
+    pub spectrum: &'a SpectrumFrame,
+    // Attack/release smoothed bands of the downmix and of the stereo pair
+    pub smoothers: &'a [BandSmoother; 3],
+    // Only captured for modes whose `wants_scope` is true
+    pub scope: &'a Oscilloscope,
+    // Configured number of bands. Right after a settings change the bands may lag one frame.
+    pub band_count: usize,
+    // Of the playing track, to put frequencies to the FFT bins
+    pub sample_rate: u32,
+    pub width: i32,
+    pub height: i32,
+    // Seconds since the previous frame
+    pub delta: f32,
+    pub theme: Theme,
+    // How the bar modes color their bars
+    pub gradient: GradientMode,
+}
+
+impl VisualFrame<'_> {
+    // Whether the downmix bands have the configured count yet
+    pub fn bands_ready(&self) -> bool {
+        self.spectrum.bands.len() == self.band_count
+            && self.smoothers[0].values().len() == self.band_count
+    }
+}
+
+// A visualization mode. Its instance lives in the registry for the whole run, so state kept in
+// it survives switching to another mode and back.
+pub trait Visualizer {
+    // Shown to the user and matched against the config's window.mode, case-insensitively
+    fn name(&self) -> &str;
+
+    // Called with the screen size the first time the mode is shown
+    fn init(&mut self, _width: i32, _height: i32) {}
+
+    // Called before drawing when the screen size differs from the last init or resize
+    fn resize(&mut self, _width: i32, _height: i32) {}
+
+    // Called every frame the mode is shown, before `draw`
+    fn update(&mut self, _frame: &VisualFrame) {}
+
+    // Called between BeginDrawing and EndDrawing
+    fn draw(&mut self, frame: &VisualFrame);
+
+    // Whether the render loop has to capture the oscilloscope samples for this mode
+    fn wants_scope(&self) -> bool {
+        false
+    }
+
+    // The colormap of modes that have one, shown in the status line
+    fn colormap(&self) -> Option<Colormap> {
+        None
+    }
+
+    // Called when the user cycles colormaps, for modes that have one
+    fn next_colormap(&mut self) {}
+}
+
+// Owns every mode in cycle order and tracks which one is shown
+#[derive(Default)]
+pub struct Registry {
+    visualizers: Vec<Box<dyn Visualizer>>,
+    // Size each mode was last initialised or resized for, None until it is first shown
+    sizes: Vec<Option<(i32, i32)>>,
+    current: usize,
+}
+
+impl Registry {
+    pub fn register(&mut self, visualizer: Box<dyn Visualizer>) {
+        self.visualizers.push(visualizer);
+        self.sizes.push(None);
+    }
+
+    pub fn len(&self) -> usize {
+        self.visualizers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.visualizers.is_empty()
+    }
+
+    pub fn current(&self) -> Option<&dyn Visualizer> {
+        self.visualizers.get(self.current).map(|v| v.as_ref())
+    }
+
+    // Shows the mode called `name`. Returns false, keeping the current one, if there is none.
+    pub fn select(&mut self, name: &str) -> bool {
+        match self
+            .visualizers
+            .iter()
+            .position(|v| v.name().eq_ignore_ascii_case(name))
+        {
+            Some(index) => {
+                self.current = index;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn select_next(&mut self) {
+        if !self.is_empty() {
+            self.current = (self.current + 1) % self.len();
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        if !self.is_empty() {
+            self.current = (self.current + self.len() - 1) % self.len();
+        }
+    }
+
+    // Cycles the colormap of every mode that has one, shown or not
+    pub fn next_colormap(&mut self) {
+        for visualizer in self.visualizers.iter_mut() {
+            visualizer.next_colormap();
+        }
+    }
+
+    pub fn wants_scope(&self) -> bool {
+        self.current().is_some_and(|v| v.wants_scope())
+    }
+
+    // Runs the current mode's hooks for this frame
+    pub fn draw(&mut self, frame: &VisualFrame) {
+        let Some(visualizer) = self.visualizers.get_mut(self.current) else {
+            return;
+        };
+        let size = (frame.width, frame.height);
+        match self.sizes[self.current] {
+            None => visualizer.init(frame.width, frame.height),
+            Some(last) if last != size => visualizer.resize(frame.width, frame.height),
+            Some(_) => {}
+        }
+        self.sizes[self.current] = Some(size);
+
+        visualizer.update(frame);
+        visualizer.draw(frame);
+    }
+}